@@ -0,0 +1,35 @@
+extern crate cbindgen;
+
+use std::env;
+use std::path::Path;
+
+/// Generates the header of the C API in `src/capi.rs` into `OUT_DIR`, and
+/// enables the `jit` cfg where the feature is supported. The `c_api` test
+/// checks the header matches the checked-in `include/mal.h`, which is
+/// rewritten only when `MAL_UPDATE_HEADER` is set.
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(jit)");
+    let supported = env::var("CARGO_CFG_TARGET_ARCH").map(|a| a == "x86_64") == Ok(true)
+        && env::var("CARGO_CFG_TARGET_OS").map(|os| os == "linux") == Ok(true);
+    if env::var_os("CARGO_FEATURE_JIT").is_some() && supported {
+        println!("cargo:rustc-cfg=jit");
+    }
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    // cbindgen exports the `pub` items of every module, not only capi.rs
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=MAL_UPDATE_HEADER");
+    let dir = Path::new(&crate_dir);
+    let config = cbindgen::Config::from_file(dir.join("cbindgen.toml")).expect("cbindgen.toml");
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("generate mal.h");
+    let header = Path::new(&env::var("OUT_DIR").unwrap()).join("mal.h");
+    bindings.write_to_file(&header);
+    println!("cargo:rustc-env=MAL_GENERATED_HEADER={}", header.display());
+    if env::var_os("MAL_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(dir.join("include/mal.h"));
+    }
+}
@@ -0,0 +1,237 @@
+//! `#[derive(IntoMal, FromMal)]` for the conversion traits of
+//! `rs::convert`, which describes how the types are represented in mal.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as Tokens;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, GenericParam, Ident};
+
+#[proc_macro_derive(IntoMal)]
+pub fn derive_into_mal(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let body = match input.data {
+        Data::Struct(ref data) => into_fields(&data.fields, quote!(self.), true),
+        Data::Enum(ref data) => {
+            let name = &input.ident;
+            let arms = data.variants.iter().map(|variant| {
+                let ident = &variant.ident;
+                let tag = variant_tag(ident);
+                let bindings = bindings(&variant.fields);
+                let pattern = match variant.fields {
+                    Fields::Named(_) => quote!(#name::#ident { #(#bindings),* }),
+                    Fields::Unnamed(_) => quote!(#name::#ident(#(#bindings),*)),
+                    Fields::Unit => quote!(#name::#ident),
+                };
+                let values = match variant.fields {
+                    Fields::Named(_) => {
+                        let map = into_fields(&variant.fields, quote!(), false);
+                        quote!(vec![#map])
+                    }
+                    _ => quote!(vec![#(::rs::convert::IntoMal::into_mal(#bindings)),*]),
+                };
+                quote!(#pattern => ::rs::convert::tagged(#tag, #values))
+            });
+            quote!(match self { #(#arms,)* })
+        }
+        Data::Union(_) => return unsupported(&input),
+    };
+    expand(&input, quote!(IntoMal), |name, generics| {
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+        quote! {
+            impl #impl_generics ::rs::convert::IntoMal for #name #ty_generics #where_clause {
+                fn into_mal(self) -> ::rs::types::MalType {
+                    #body
+                }
+            }
+        }
+    })
+}
+
+#[proc_macro_derive(FromMal)]
+pub fn derive_from_mal(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let expected = name.to_string();
+    let body = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(_) => {
+                let fields = from_named(&data.fields, None);
+                quote! {
+                    let map = ::rs::convert::entries(mal, #expected)?;
+                    Ok(#name { #fields })
+                }
+            }
+            Fields::Unnamed(ref fields) if fields.unnamed.len() == 1 => {
+                quote!(Ok(#name(::rs::convert::FromMal::from_mal(mal)?)))
+            }
+            Fields::Unnamed(ref fields) => {
+                let len = fields.unnamed.len();
+                let elements = (0..len).map(|i| quote!(::rs::convert::element(&items, #i)?));
+                quote! {
+                    let items = ::rs::convert::tuple(mal, #len)?;
+                    Ok(#name(#(#elements),*))
+                }
+            }
+            Fields::Unit => quote! {
+                <() as ::rs::convert::FromMal>::from_mal(mal)?;
+                Ok(#name)
+            },
+        },
+        Data::Enum(ref data) => {
+            let tags: Vec<String> = data.variants.iter().map(|v| variant_tag(&v.ident)).collect();
+            let arms = data.variants.iter().zip(&tags).map(|(variant, tag)| {
+                let ident = &variant.ident;
+                let (len, value) = match variant.fields {
+                    Fields::Named(_) => {
+                        let fields = from_named(&variant.fields, Some("[1]"));
+                        (
+                            1usize,
+                            quote! {{
+                                let map = ::rs::convert::at("[1]", ::rs::convert::entries(&values[0], "Map"))?;
+                                #name::#ident { #fields }
+                            }},
+                        )
+                    }
+                    Fields::Unnamed(ref fields) => {
+                        let elements = (0..fields.unnamed.len()).map(|i| {
+                            let segment = format!("[{}]", i + 1);
+                            quote!(::rs::convert::at(#segment, ::rs::convert::FromMal::from_mal(&values[#i]))?)
+                        });
+                        (fields.unnamed.len(), quote!(#name::#ident(#(#elements),*)))
+                    }
+                    Fields::Unit => (0, quote!(#name::#ident)),
+                };
+                quote! {
+                    #tag => {
+                        ::rs::convert::check_arity(tag, &values, #len, mal)?;
+                        Ok(#value)
+                    }
+                }
+            });
+            quote! {
+                let (tag, values) = ::rs::convert::untag(mal, #expected)?;
+                match tag.as_str() {
+                    #(#arms)*
+                    _ => ::rs::convert::unknown_variant(&[#(#tags),*], mal),
+                }
+            }
+        }
+        Data::Union(_) => return unsupported(&input),
+    };
+    expand(&input, quote!(FromMal), |name, generics| {
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+        quote! {
+            impl #impl_generics ::rs::convert::FromMal for #name #ty_generics #where_clause {
+                fn from_mal(mal: &::rs::types::MalType) -> ::rs::convert::Fallible<Self> {
+                    #body
+                }
+            }
+        }
+    })
+}
+
+/// The impl made by `f` for the type of `input`, with every type parameter
+/// bound by `bound`.
+fn expand<F>(input: &DeriveInput, bound: Tokens, f: F) -> TokenStream
+where
+    F: FnOnce(&Ident, &syn::Generics) -> Tokens,
+{
+    let mut generics = input.generics.clone();
+    for param in &mut generics.params {
+        if let GenericParam::Type(ref mut param) = *param {
+            param.bounds.push(syn::parse_quote!(::rs::convert::#bound));
+        }
+    }
+    f(&input.ident, &generics).into()
+}
+
+fn unsupported(input: &DeriveInput) -> TokenStream {
+    Error::new_spanned(&input.ident, "unions can not be converted to mal")
+        .to_compile_error()
+        .into()
+}
+
+/// The converted `fields`, read from `prefix` followed by the field: a
+/// keyword map for named fields, else a vector of them, or the field itself
+/// for a newtype when `newtype` is set.
+fn into_fields(fields: &Fields, prefix: Tokens, newtype: bool) -> Tokens {
+    match *fields {
+        Fields::Named(ref named) => {
+            let entries = named.named.iter().map(|field| {
+                let ident = field.ident.as_ref().unwrap();
+                let key = field_key(ident);
+                quote!((#key, ::rs::convert::IntoMal::into_mal(#prefix #ident)))
+            });
+            quote!(::rs::convert::keyword_map(vec![#(#entries),*]))
+        }
+        Fields::Unnamed(ref unnamed) if newtype && unnamed.unnamed.len() == 1 => {
+            quote!(::rs::convert::IntoMal::into_mal(#prefix 0))
+        }
+        Fields::Unnamed(ref unnamed) => {
+            let values = (0..unnamed.unnamed.len()).map(|i| {
+                let index = syn::Index::from(i);
+                quote!(::rs::convert::IntoMal::into_mal(#prefix #index))
+            });
+            quote!(::rs::convert::vector(vec![#(#values),*]))
+        }
+        Fields::Unit => quote!(::rs::convert::IntoMal::into_mal(())),
+    }
+}
+
+/// The initializers of named `fields` from the entries of `map`, the errors
+/// located under `segment` when given.
+fn from_named(fields: &Fields, segment: Option<&str>) -> Tokens {
+    let inits = fields.iter().map(|field| {
+        let ident = field.ident.as_ref().unwrap();
+        let key = field_key(ident);
+        let value = quote!(::rs::convert::field(map, #key));
+        match segment {
+            Some(segment) => quote!(#ident: ::rs::convert::at(#segment, #value)?),
+            None => quote!(#ident: #value?),
+        }
+    });
+    quote!(#(#inits),*)
+}
+
+/// Names to bind the fields of a variant to in a pattern.
+fn bindings(fields: &Fields) -> Vec<Ident> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match field.ident {
+            Some(ref ident) => ident.clone(),
+            None => Ident::new(&format!("field{}", i), proc_macro2::Span::call_site()),
+        })
+        .collect()
+}
+
+fn unraw(ident: &Ident) -> String {
+    let name = ident.to_string();
+    name.trim_start_matches("r#").to_string()
+}
+
+/// `first_name` is keyed by `:first-name`.
+fn field_key(ident: &Ident) -> String {
+    format!(":{}", unraw(ident).replace('_', "-"))
+}
+
+/// `BigCircle` is tagged `:big-circle`.
+fn variant_tag(ident: &Ident) -> String {
+    let mut tag = ":".to_string();
+    for (i, c) in unraw(ident).chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                tag.push('-');
+            }
+            tag.extend(c.to_lowercase());
+        } else {
+            tag.push(c);
+        }
+    }
+    tag
+}
@@ -0,0 +1,95 @@
+#ifndef MAL_H
+#define MAL_H
+
+/* Generated from src/capi.rs by build.rs. Do not edit. */
+
+#include <stddef.h>
+
+/*
+ The call succeeded.
+ */
+#define MAL_OK 0
+
+/*
+ Mal raised an error, or the arguments were invalid.
+ */
+#define MAL_ERROR 1
+
+/*
+ The interpreter panicked. It should not be used anymore.
+ */
+#define MAL_PANIC 2
+
+/*
+ A mal interpreter, made by `mal_new` and freed by `mal_free`.
+ */
+typedef struct MalInterpreter MalInterpreter;
+
+/*
+ A mal function implemented in C. It gets the `user_data` it was
+ registered with and its args printed readably, and returns the result as
+ mal source, which is copied before the next call. On failure it returns
+ NULL and points `error` at a message, copied as well.
+ */
+typedef const char *(*MalFn)(void *user_data,
+                             size_t argc,
+                             const char *const *argv,
+                             const char **error);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Makes a new interpreter, or returns NULL if that panicked.
+ */
+MalInterpreter *mal_new(void);
+
+/*
+ Frees an interpreter made by `mal_new`, with its namespaces and what
+ was defined in them. NULL is ignored.
+
+ # Safety
+
+ `mal` is not used afterwards.
+ */
+void mal_free(MalInterpreter *mal);
+
+/*
+ Evaluates the forms of `source`. Returns `MAL_OK` and points `result` at
+ the printed value of the last form, or another status and points it at
+ the error message.
+
+ # Safety
+
+ `mal` comes from `mal_new`, `source` is NUL-terminated, and `result` is
+ NULL or writable.
+ */
+int mal_eval_string(MalInterpreter *mal, const char *source, char **result);
+
+/*
+ Binds `name` in the current namespace to a mal function calling `f`
+ with `user_data`. On failure, points `error` at the message unless it is
+ NULL.
+
+ # Safety
+
+ `mal` comes from `mal_new`, `name` is NUL-terminated, `user_data` stays
+ valid while the function can be called, and `error` is NULL or writable.
+ */
+int mal_register_fn(MalInterpreter *mal, const char *name, MalFn f, void *user_data, char **error);
+
+/*
+ Frees a string returned by the API. NULL is ignored.
+
+ # Safety
+
+ `s` comes from the API and is not used afterwards.
+ */
+void mal_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MAL_H */
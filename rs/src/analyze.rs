@@ -0,0 +1,680 @@
+//! The pass run on each form before `eval`, which resolves the names bound
+//! by `fn*`, `let*` and `catch*` to slots of their frames and caches the
+//! bindings of globals. Against looking every name up by walking the envs,
+//! it made a recursive `fib` about twice as fast and `tests/perf3.mal`,
+//! which mostly calls globals, some thirty times.
+
+use arity::{parse_fn, Clause, FnForm};
+use destructure::{self, Bindings, Temps};
+use env::{env_frames, env_get, Env};
+use exception::parse_try;
+use failure::{Error, Fallible};
+use lazy::seq_to_list;
+use namespace::{qualified_binding, split_qualified};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::mem;
+use std::rc::Rc;
+use symbol::Sym;
+use trace;
+use types::{InnerMalType, MalType};
+
+pub fn is_special_form(name: &str) -> bool {
+    matches!(
+        name,
+        "def!"
+            | "let*"
+            | "loop*"
+            | "recur"
+            | "do"
+            | "if"
+            | "fn*"
+            | "eval"
+            | "quote"
+            | "quasiquote"
+            | "defmacro!"
+            | "var"
+            | "doc"
+            | "source"
+            | "apropos"
+            | "find-doc"
+            | "defprotocol"
+            | "extend-type"
+            | "extend-protocol"
+            | "lazy-seq"
+            | "macroexpand"
+            | "try*"
+            | "binding"
+            | "current-env"
+    )
+}
+
+pub fn quasiquote(ast: MalType) -> MalType {
+    if !is_pair(&ast) {
+        return new_mal!(List(
+            linked_list![new_mal!(Symbol(Sym::new("quote"))), ast],
+            new_mal!(Nil)
+        ));
+    }
+
+    let mut list = ast.to_items();
+    let first = list.pop_front().unwrap();
+    if first.is_symbol() && first.to_symbol() == "unquote" {
+        return list.pop_front().unwrap();
+    }
+
+    if is_pair(&first) {
+        let mut list_of_first = first.clone().to_items();
+        let first_of_first = list_of_first.pop_front().unwrap();
+        if first_of_first.is_symbol() && first_of_first.to_symbol() == "splice-unquote" {
+            let ret = linked_list![
+                new_mal!(Symbol(Sym::new("concat"))),
+                list_of_first.pop_front().unwrap(),
+                quasiquote(new_mal!(Vec(list, new_mal!(Nil)))),
+            ];
+            return new_mal!(List(ret, new_mal!(Nil)));
+        }
+    }
+
+    let l = linked_list![
+        new_mal!(Symbol(Sym::new("cons"))),
+        quasiquote(first),
+        quasiquote(new_mal!(Vec(list, new_mal!(Nil)))),
+    ];
+
+    new_mal!(List(l, new_mal!(Nil)))
+}
+
+fn is_pair(param: &MalType) -> bool {
+    param.is_collection() && !param.to_items_ref().is_empty()
+}
+
+fn macro_named(name: Sym, env: &Env) -> Option<MalType> {
+    if is_special_form(&name) {
+        return None;
+    }
+    env_get(env.clone(), name).filter(|f| f.is_closure() && f.is_macro_closure())
+}
+
+fn is_macro_call(ast: &MalType, env: &Env) -> bool {
+    ast.did_collection_have_leading_symbol()
+        && macro_named(ast.to_items_ref().front().unwrap().to_symbol(), env).is_some()
+}
+
+pub fn macroexpand(mut ast: MalType, env: &Env) -> Fallible<MalType> {
+    while is_macro_call(&ast, env) {
+        let mut items = ast.to_items();
+        let first_el = items.pop_front().unwrap();
+        let func = env_get(env.clone(), first_el.to_symbol()).expect("get macro func");
+        ast = func.to_closure().call(items)?;
+    }
+    Ok(ast)
+}
+
+/// Prepares `form` to be evaluated in `env`: expands macros, and resolves
+/// each symbol bound by an enclosing `fn*`, `let*` or `catch*` to the slot
+/// it will have at run time. Other symbols become globals, with qualified
+/// ones resolved to their namespace's binding. Forms whose syntax is wrong
+/// are left alone for `eval` to report, except a `recur` which is not in
+/// tail position of its `loop*` or `fn*`.
+///
+/// A name a `let*` binds again gets a slot of its own, so a `fn*` made
+/// before keeps seeing the earlier value, as in Clojure:
+/// `(let* [a 1 b (fn* [] a) a 2] (b))` is 1. Looked up by name, it was 2.
+pub fn analyze(form: MalType, env: &Env) -> Fallible<MalType> {
+    let (mut frames, mut named): (Vec<_>, Vec<_>) = env_frames(env.clone()).into_iter().unzip();
+    frames.reverse();
+    named.reverse();
+    Analyzer {
+        env,
+        frames,
+        named,
+        tail: false,
+        target: None,
+        enclosing: Vec::new(),
+        temps: Temps::default(),
+        destructured: false,
+    }
+    .analyze(form)
+}
+
+/// The `loop*` or `fn*` a `recur` re-enters, with the number of values it
+/// rebinds.
+struct Target {
+    arity: usize,
+    // whether a `recur` re-enters it
+    used: bool,
+}
+
+struct Analyzer<'a> {
+    env: &'a Env,
+    // slot names of the enclosing frames, outermost first
+    frames: Vec<Vec<Sym>>,
+    // names bound by name in the envs of the first frames, which shadow
+    // the slots of those further out
+    named: Vec<Vec<Sym>>,
+    // whether the form being analyzed is in tail position of `target`
+    tail: bool,
+    target: Option<Target>,
+    // the lists the form is in, for where a `recur` was written
+    enclosing: Vec<MalType>,
+    temps: Temps,
+    // whether a pattern was expanded in the list being analyzed
+    destructured: bool,
+}
+
+fn list(items: LinkedList<MalType>) -> MalType {
+    new_mal!(List(items, new_mal!(Nil)))
+}
+
+/// The form `(name args...)` of the special form `name`.
+fn special(name: &str, mut args: LinkedList<MalType>) -> MalType {
+    args.push_front(new_mal!(Symbol(Sym::new(name))));
+    list(args)
+}
+
+/// `bindings` as the name/value pairs of a `let*`.
+fn flatten(bindings: Bindings) -> Vec<MalType> {
+    bindings
+        .into_iter()
+        .flat_map(|(name, value)| vec![new_mal!(Symbol(name)), value])
+        .collect()
+}
+
+fn is_symbol_seq(mal: &MalType) -> bool {
+    (mal.is_list() || mal.is_vec()) && mal.to_items_ref().iter().all(|s| s.is_symbol())
+}
+
+impl<'a> Analyzer<'a> {
+    fn resolve(&self, name: Sym) -> Option<(usize, usize)> {
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            if let Some(slot) = frame.iter().rposition(|s| *s == name) {
+                return Some((depth, slot));
+            }
+            // looked up by name from here on
+            let idx = self.frames.len() - 1 - depth;
+            if idx < self.named.len() && self.named[idx].contains(&name) {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn analyze(&mut self, form: MalType) -> Fallible<MalType> {
+        let tail = mem::replace(&mut self.tail, false);
+        match *form {
+            InnerMalType::Symbol(name) => Ok(match self.resolve(name) {
+                Some((depth, slot)) => new_mal!(Local(name, depth, slot)),
+                None if split_qualified(&name).is_some() => {
+                    let binding = qualified_binding(self.env, &name)?;
+                    new_mal!(Global(name, RefCell::new(binding)))
+                }
+                None => new_mal!(Global(name, RefCell::new(None))),
+            }),
+            InnerMalType::List(ref items, _) if !items.is_empty() => {
+                self.enclosing.push(form.clone());
+                let outer = mem::replace(&mut self.destructured, false);
+                let analyzed = self.analyze_list(&form, tail)?;
+                self.enclosing.pop();
+                trace::copy_position(&form, &analyzed);
+                // printed, it would show the temps of the patterns in it
+                if self.destructured || trace::source(&form).is_some() {
+                    trace::set_source(&analyzed, &form);
+                }
+                // a call made of a pattern is where the pattern was written
+                if trace::source(&form).map(|s| s.is_list()) == Some(false) {
+                    let written = self
+                        .enclosing
+                        .iter()
+                        .rev()
+                        .find(|f| trace::position(f).is_some());
+                    if let Some(written) = written {
+                        trace::copy_position(written, &analyzed);
+                    }
+                }
+                self.destructured |= outer;
+                Ok(analyzed)
+            }
+            InnerMalType::Vec(ref items, ref meta) => {
+                Ok(new_mal!(Vec(self.analyze_all(items)?, meta.clone())))
+            }
+            InnerMalType::Hashmap(ref mapping, ref meta) => {
+                let mut new_mapping = HashMap::new();
+                for (k, v) in mapping {
+                    new_mapping.insert(k.clone(), self.analyze(v.clone())?);
+                }
+                Ok(new_mal!(Hashmap(new_mapping, meta.clone())))
+            }
+            // code built by macros with concat
+            InnerMalType::LazySeq(..) => self.analyze_in(list(seq_to_list(&form)?), tail),
+            _ => Ok(form.clone()),
+        }
+    }
+
+    /// Analyzes `form`, in tail position if `tail` is.
+    fn analyze_in(&mut self, form: MalType, tail: bool) -> Fallible<MalType> {
+        self.tail = tail;
+        self.analyze(form)
+    }
+
+    /// Analyzes `form` as the body of `target`, in tail position of it.
+    fn analyze_body(
+        &mut self,
+        form: MalType,
+        target: Option<Target>,
+    ) -> Fallible<(MalType, Option<Target>)> {
+        let outer = mem::replace(&mut self.target, target);
+        let analyzed = self.analyze_in(form, true)?;
+        let target = mem::replace(&mut self.target, outer);
+        Ok((analyzed, target))
+    }
+
+    fn analyze_all(&mut self, items: &LinkedList<MalType>) -> Fallible<LinkedList<MalType>> {
+        items
+            .iter()
+            .map(|item| self.analyze(item.clone()))
+            .collect()
+    }
+
+    fn analyze_list(&mut self, form: &MalType, tail: bool) -> Fallible<MalType> {
+        let items = form.to_items_ref();
+        let head = items.front().unwrap();
+        // a local can't shadow a special form, as `eval` dispatches on
+        // the head before looking it up
+        if head.is_symbol() && is_special_form(&head.to_symbol()) {
+            return self.analyze_special(form, tail);
+        }
+        if head.is_symbol() && self.resolve(head.to_symbol()).is_none() {
+            if let Some(f) = macro_named(head.to_symbol(), self.env) {
+                let mut args = items.clone();
+                args.pop_front();
+                let expanded = f.to_closure().call(args)?;
+                return self.analyze_in(expanded, tail);
+            }
+        }
+        Ok(list(self.analyze_all(items)?))
+    }
+
+    fn analyze_special(&mut self, form: &MalType, tail: bool) -> Fallible<MalType> {
+        let mut items = form.to_items();
+        let head = items.pop_front().unwrap();
+        let analyzed = match head.to_symbol().as_str() {
+            "def!" | "defmacro!" if !items.is_empty() => {
+                let name = items.pop_front().unwrap();
+                let mut rest = self.analyze_all(&items)?;
+                rest.push_front(name);
+                rest.push_front(head);
+                // the form as written is kept for :source
+                return Ok(new_mal!(List(rest, form.clone())));
+            }
+            "do" => self.analyze_do(items, tail)?,
+            "if" => self.analyze_if(items, tail)?,
+            "eval" | "apropos" | "find-doc" => Some(self.analyze_all(&items)?),
+            // its body is not in the fn it is written in
+            "lazy-seq" => {
+                let outer = self.target.take();
+                let analyzed = self.analyze_all(&items)?;
+                self.target = outer;
+                Some(analyzed)
+            }
+            "quasiquote" if items.len() == 1 => {
+                return self.analyze_in(quasiquote(items.pop_front().unwrap()), tail);
+            }
+            "let*" => self.analyze_let(items, tail)?,
+            "loop*" => match self.destructure_loop(&items)? {
+                Some(form) => return self.analyze_in(form, tail),
+                None => self.analyze_loop(items)?,
+            },
+            "recur" => Some(self.analyze_recur(items, tail)?),
+            "fn*" => self.analyze_fn(items)?,
+            "try*" => self.analyze_try(items)?,
+            "binding" => self.analyze_binding(items)?,
+            _ => None,
+        };
+        Ok(match analyzed {
+            // a `fn*` keeps its params as written for `:arglists`
+            Some(mut items) if head.to_symbol() == "fn*" => {
+                items.push_front(head);
+                new_mal!(List(items, form.clone()))
+            }
+            Some(mut items) => {
+                items.push_front(head);
+                list(items)
+            }
+            None => form.clone(),
+        })
+    }
+
+    fn analyze_do(
+        &mut self,
+        mut items: LinkedList<MalType>,
+        tail: bool,
+    ) -> Fallible<Option<LinkedList<MalType>>> {
+        let last = match items.pop_back() {
+            Some(last) => last,
+            None => return Ok(Some(items)),
+        };
+        let mut analyzed = self.analyze_all(&items)?;
+        analyzed.push_back(self.analyze_in(last, tail)?);
+        Ok(Some(analyzed))
+    }
+
+    fn analyze_if(
+        &mut self,
+        mut items: LinkedList<MalType>,
+        tail: bool,
+    ) -> Fallible<Option<LinkedList<MalType>>> {
+        let condition = match items.pop_front() {
+            Some(condition) => condition,
+            None => return Ok(None),
+        };
+        let mut analyzed = linked_list![self.analyze(condition)?];
+        for clause in items {
+            analyzed.push_back(self.analyze_in(clause, tail)?);
+        }
+        Ok(Some(analyzed))
+    }
+
+    /// The patterns and values of the bindings of a `let*` or `loop*`, and
+    /// its body, unless they are malformed.
+    fn parse_let(items: &LinkedList<MalType>) -> Option<(Vec<MalType>, MalType)> {
+        if items.len() != 2 {
+            return None;
+        }
+        let bindings = items.front().unwrap();
+        let body = items.back().unwrap().clone();
+        if !(bindings.is_list() || bindings.is_vec()) || !bindings.len().is_multiple_of(2) {
+            return None;
+        }
+        Some((bindings.to_items_ref().iter().cloned().collect(), body))
+    }
+
+    /// The patterns of `pairs`.
+    fn patterns(pairs: &[MalType]) -> Vec<MalType> {
+        pairs.iter().step_by(2).cloned().collect()
+    }
+
+    /// `e` with where the form it was raised for was written.
+    fn located(&self, e: Error) -> Error {
+        format_err!("{}{}", e, self.written_at())
+    }
+
+    /// Expands `pattern` bound to the value of `form` into `out`.
+    fn expand(&mut self, pattern: &MalType, form: MalType, out: &mut Bindings) -> Fallible<()> {
+        self.destructured = true;
+        destructure::expand(pattern, form, &mut self.temps, out).map_err(|e| self.located(e))
+    }
+
+    /// Name/value pairs binding the names of the patterns of `pairs`.
+    fn destructure(&mut self, pairs: &[MalType]) -> Fallible<Vec<MalType>> {
+        let mut bindings = Vec::new();
+        for pair in pairs.chunks(2) {
+            self.expand(&pair[0], pair[1].clone(), &mut bindings)?;
+        }
+        Ok(flatten(bindings))
+    }
+
+    /// Analyzes the bindings of a `let*` or `loop*` in a new frame, which
+    /// is left pushed for its body.
+    fn analyze_bindings(&mut self, pairs: &[MalType]) -> Fallible<MalType> {
+        self.frames.push(Vec::new());
+        let mut new_bindings = LinkedList::new();
+        for pair in pairs.chunks(2) {
+            new_bindings.push_back(pair[0].clone());
+            new_bindings.push_back(self.analyze(pair[1].clone())?);
+            self.frames.last_mut().unwrap().push(pair[0].to_symbol());
+        }
+        Ok(list(new_bindings))
+    }
+
+    fn analyze_let(
+        &mut self,
+        items: LinkedList<MalType>,
+        tail: bool,
+    ) -> Fallible<Option<LinkedList<MalType>>> {
+        let (mut pairs, body) = match Self::parse_let(&items) {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        if !destructure::is_plain(&Self::patterns(&pairs)) {
+            pairs = self.destructure(&pairs)?;
+        }
+        let bindings = self.analyze_bindings(&pairs)?;
+        let body = self.analyze_in(body, tail)?;
+        self.frames.pop();
+        Ok(Some(linked_list![bindings, body]))
+    }
+
+    /// A `loop*` binding patterns as a `let*` binding them, around a
+    /// `loop*` of the values they are taken from whose body binds them
+    /// again, unless it binds only names.
+    fn destructure_loop(&mut self, items: &LinkedList<MalType>) -> Fallible<Option<MalType>> {
+        let (pairs, body) = match Self::parse_let(items) {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        if destructure::is_plain(&Self::patterns(&pairs)) {
+            return Ok(None);
+        }
+        let (mut outer, mut values, mut inner) = (Vec::new(), Vec::new(), Vec::new());
+        for pair in pairs.chunks(2) {
+            if pair[0].is_symbol() {
+                outer.push((pair[0].to_symbol(), pair[1].clone()));
+                values.push(pair[0].to_symbol());
+                continue;
+            }
+            let value = self.temps.fresh("loop");
+            outer.push((value, pair[1].clone()));
+            self.expand(&pair[0], new_mal!(Symbol(value)), &mut outer)?;
+            values.push(value);
+            self.expand(&pair[0], new_mal!(Symbol(value)), &mut inner)?;
+        }
+        let rebound = values
+            .into_iter()
+            .flat_map(|value| vec![new_mal!(Symbol(value)), new_mal!(Symbol(value))])
+            .collect();
+        let body = special(
+            "let*",
+            linked_list![list(flatten(inner).into_iter().collect()), body],
+        );
+        let body = special("loop*", linked_list![list(rebound), body]);
+        Ok(Some(special(
+            "let*",
+            linked_list![list(flatten(outer).into_iter().collect()), body],
+        )))
+    }
+
+    /// Like `let*`, with its body the target of the `recur`s in tail
+    /// position of it.
+    fn analyze_loop(
+        &mut self,
+        items: LinkedList<MalType>,
+    ) -> Fallible<Option<LinkedList<MalType>>> {
+        let (pairs, body) = match Self::parse_let(&items) {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        let bindings = self.analyze_bindings(&pairs)?;
+        let target = Target {
+            arity: pairs.len() / 2,
+            used: false,
+        };
+        let (body, _) = self.analyze_body(body, Some(target))?;
+        self.frames.pop();
+        Ok(Some(linked_list![bindings, body]))
+    }
+
+    /// Where the form being analyzed was written, as far as known.
+    fn written_at(&self) -> String {
+        self.enclosing
+            .iter()
+            .rev()
+            .filter_map(trace::position)
+            .next()
+            .map(|p| format!(" ({}:{}:{})", p.file, p.line, p.column))
+            .unwrap_or_default()
+    }
+
+    fn analyze_recur(
+        &mut self,
+        items: LinkedList<MalType>,
+        tail: bool,
+    ) -> Fallible<LinkedList<MalType>> {
+        let arity = match self.target {
+            Some(ref target) => target.arity,
+            None => bail!("recur is not in a loop* or fn*{}", self.written_at()),
+        };
+        ensure!(tail, "recur is not in tail position{}", self.written_at());
+        ensure_arity!(
+            items.len() == arity,
+            "recur should have {} params, got {}{}",
+            arity,
+            items.len(),
+            self.written_at()
+        );
+        self.target.as_mut().unwrap().used = true;
+        self.analyze_all(&items)
+    }
+
+    /// A plain `fn*` as `(fn* params body)`, and any other as
+    /// `(fn* name-or-nil (params body) ...)`, the name bound around its
+    /// clauses.
+    fn analyze_fn(&mut self, items: LinkedList<MalType>) -> Fallible<Option<LinkedList<MalType>>> {
+        // a malformed `fn*` is left for `eval` to report
+        let parsed = match parse_fn(&items) {
+            Ok(parsed) => parsed,
+            Err(_) => return Ok(None),
+        };
+        let plain = parsed.is_plain();
+        let FnForm { name, mut clauses } = parsed;
+        if plain {
+            let Clause { params, body } = clauses.pop().unwrap();
+            return Ok(self
+                .analyze_clause(params, body)?
+                .map(|(params, body)| linked_list![params, body]));
+        }
+        if let Some(name) = name {
+            self.frames.push(vec![name]);
+        }
+        let mut analyzed = linked_list![match name {
+            Some(name) => new_mal!(Symbol(name)),
+            None => new_mal!(Nil),
+        }];
+        let mut complete = true;
+        for Clause { params, body } in clauses {
+            match self.analyze_clause(params, body)? {
+                Some((params, body)) => analyzed.push_back(list(linked_list![params, body])),
+                None => complete = false,
+            }
+        }
+        if name.is_some() {
+            self.frames.pop();
+        }
+        Ok(if complete { Some(analyzed) } else { None })
+    }
+
+    /// The params and body of a clause of a `fn*`, with its patterns bound
+    /// in the body, and its `recur`s re-entering it.
+    fn analyze_clause(
+        &mut self,
+        mut params: MalType,
+        mut body: MalType,
+    ) -> Fallible<Option<(MalType, MalType)>> {
+        // patterns are bound to the args of plain params in the body
+        if params.is_vec() || params.is_list() {
+            let written: Vec<_> = params.to_items_ref().iter().cloned().collect();
+            if !destructure::is_plain(&written) {
+                self.destructured = true;
+                let expanded = destructure::expand_params(&written, &mut self.temps);
+                let (plain, bindings) = expanded.map_err(|e| self.located(e))?;
+                params = new_mal!(Vec(plain.into_iter().collect(), new_mal!(Nil)));
+                let bindings = list(flatten(bindings).into_iter().collect());
+                body = special("let*", linked_list![bindings, body]);
+            }
+        }
+        if !is_symbol_seq(&params) {
+            return Ok(None);
+        }
+        let binds: Vec<Sym> = params
+            .to_symbol_list()
+            .into_iter()
+            .filter(|s| *s != "&")
+            .collect();
+        self.frames.push(binds.clone());
+        let target = Target {
+            arity: binds.len(),
+            used: false,
+        };
+        let body = match self.analyze_body(body.clone(), Some(target))? {
+            // a `recur` re-enters a `loop*` rebinding the params, with the
+            // rest of them as a seq
+            (_, Some(Target { used: true, .. })) => {
+                let mut bindings = LinkedList::new();
+                for bind in binds {
+                    bindings.push_back(new_mal!(Symbol(bind)));
+                    bindings.push_back(new_mal!(Symbol(bind)));
+                }
+                let body = list(linked_list![
+                    new_mal!(Symbol(Sym::new("loop*"))),
+                    list(bindings),
+                    body
+                ]);
+                self.analyze_body(body, None)?.0
+            }
+            (body, _) => body,
+        };
+        self.frames.pop();
+        Ok(Some((params, body)))
+    }
+
+    /// Only the values and the body: the names are vars, not locals.
+    fn analyze_binding(
+        &mut self,
+        mut items: LinkedList<MalType>,
+    ) -> Fallible<Option<LinkedList<MalType>>> {
+        let bindings = match items.pop_front() {
+            Some(ref b) if (b.is_list() || b.is_vec()) && b.len() % 2 == 0 => b.clone(),
+            _ => return Ok(None),
+        };
+        let mut new_bindings = LinkedList::new();
+        for (i, item) in bindings.to_items_ref().iter().enumerate() {
+            new_bindings.push_back(if i % 2 == 0 {
+                item.clone()
+            } else {
+                self.analyze(item.clone())?
+            });
+        }
+        let mut analyzed = self.analyze_all(&items)?;
+        analyzed.push_front(new_mal!(Vec(new_bindings, new_mal!(Nil))));
+        Ok(Some(analyzed))
+    }
+
+    fn analyze_try(&mut self, items: LinkedList<MalType>) -> Fallible<Option<LinkedList<MalType>>> {
+        let form = match parse_try(&items) {
+            Ok(form) => form,
+            Err(_) => return Ok(None),
+        };
+        let mut analyzed = linked_list![self.analyze(form.body)?];
+        for catch in form.catches {
+            let mut clause = linked_list![new_mal!(Symbol(Sym::new("catch*")))];
+            if let Some(selector) = catch.selector {
+                clause.push_back(self.analyze(selector)?);
+            }
+            self.frames.push(vec![catch.name.to_symbol()]);
+            let body = self.analyze(catch.body);
+            self.frames.pop();
+            clause.push_back(catch.name);
+            clause.push_back(body?);
+            analyzed.push_back(list(clause));
+        }
+        if let Some(finally) = form.finally {
+            let mut clause = finally.to_items();
+            let head = clause.pop_front().unwrap();
+            let mut clause = self.analyze_all(&clause)?;
+            clause.push_front(head);
+            analyzed.push_back(list(clause));
+        }
+        Ok(Some(analyzed))
+    }
+}
@@ -0,0 +1,245 @@
+//! `fn*`s of several arities, and named `fn*`s.
+//!
+//! `(fn* name ([x] ...) ([x y & more] ...))` has a clause of params and a
+//! body for each number of args it takes, at most one of them variadic,
+//! and a call runs the clause taking as many args as it has. The name, if
+//! any, is bound to the fn itself in each of its bodies. A clause, like a
+//! plain `fn*`, may have several forms for its body, as a `do` has.
+
+use error::ArityError;
+use failure::{Error, Fallible};
+use printer::pr_str;
+use std::collections::LinkedList;
+use std::fmt;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{Closure, ClosureEnv, InnerMalType, MalType};
+
+/// A clause of a `fn*`, with the forms of its body made one.
+pub struct Clause {
+    pub params: MalType,
+    pub body: MalType,
+}
+
+/// The parts of `(fn* name? [params] body...)` or
+/// `(fn* name? ([params] body...) ...)`.
+pub struct FnForm {
+    pub name: Option<Sym>,
+    pub clauses: Vec<Clause>,
+}
+
+impl FnForm {
+    /// Whether it is unnamed, of one clause, as `(fn* params body)` is.
+    pub fn is_plain(&self) -> bool {
+        self.name.is_none() && self.clauses.len() == 1
+    }
+}
+
+fn is_params(form: &MalType) -> bool {
+    form.is_vec() || form.is_list()
+}
+
+/// Whether `form` is a clause `(params body...)` rather than params.
+fn is_clause(form: &MalType) -> bool {
+    form.is_list() && form.to_items_ref().front().map(is_params) == Some(true)
+}
+
+/// The one form of the forms of a body, in a `do` unless there is one.
+pub fn body<'a, I: IntoIterator<Item = &'a MalType>>(forms: I) -> MalType {
+    let mut forms: LinkedList<MalType> = forms.into_iter().cloned().collect();
+    if forms.len() == 1 {
+        return forms.pop_front().unwrap();
+    }
+    forms.push_front(new_mal!(Symbol(Sym::new("do"))));
+    new_mal!(List(forms, new_mal!(Nil)))
+}
+
+/// How many args a clause of `params` takes, and whether it takes more.
+fn arity(params: &MalType) -> (usize, bool) {
+    let params = params.to_items_ref();
+    if params.iter().any(|p| p.is_symbol() && p.to_symbol() == "&") {
+        (params.len().saturating_sub(2), true)
+    } else {
+        (params.len(), false)
+    }
+}
+
+/// The parts of a `fn*` of `args`. A nil name is no name, as `analyze`
+/// writes a `fn*` of clauses without one.
+pub fn parse_fn<'a, I: IntoIterator<Item = &'a MalType>>(args: I) -> Fallible<FnForm> {
+    let args: Vec<&MalType> = args.into_iter().collect();
+    let (name, args) = match args.split_first() {
+        Some((name, rest)) if name.is_symbol() => (Some(name.to_symbol()), rest),
+        Some((name, rest)) if name.is_nil() => (None, rest),
+        _ => (None, &args[..]),
+    };
+    ensure!(!args.is_empty(), "fn* is missing its params");
+
+    let clauses: Vec<Clause> = if args.iter().all(|arg| is_clause(arg)) {
+        args.iter()
+            .map(|clause| {
+                let items = clause.to_items_ref();
+                Clause {
+                    params: items.front().unwrap().clone(),
+                    body: body(items.iter().skip(1)),
+                }
+            })
+            .collect()
+    } else {
+        ensure!(
+            is_params(args[0]),
+            "fn*'s params should be vector, got {}",
+            pr_str(args[0], true)
+        );
+        vec![Clause {
+            params: args[0].clone(),
+            body: body(args[1..].iter().cloned()),
+        }]
+    };
+
+    let arities: Vec<_> = clauses.iter().map(|c| arity(&c.params)).collect();
+    let mut variadic = arities.iter().filter(|a| a.1);
+    let most = variadic.next().map(|a| a.0);
+    ensure!(
+        variadic.next().is_none(),
+        "fn* can't have more than one variadic clause"
+    );
+    for (i, a) in arities.iter().enumerate() {
+        ensure!(
+            !arities[..i].contains(a),
+            "fn*'s clauses can't take the same number of params"
+        );
+        if let Some(most) = most {
+            ensure!(
+                a.1 || a.0 <= most,
+                "fn*'s clauses can't take more params than its variadic one"
+            );
+        }
+    }
+    Ok(FnForm { name, clauses })
+}
+
+/// The closures of the clauses of a `fn*`, and how many args each takes.
+pub struct Arities {
+    name: Option<Sym>,
+    clauses: Vec<(Closure, usize, bool)>,
+}
+
+impl Arities {
+    /// The arities of the closures made of the clauses of a `fn*`.
+    pub fn new(name: Option<Sym>, closures: Vec<Closure>) -> Self {
+        let clauses = closures
+            .into_iter()
+            .map(|closure| {
+                let (binds, variadic) = match closure.c_env {
+                    Some(ref c_env) => (c_env.binds.len(), c_env.variadic),
+                    None => unreachable!("a clause of a fn* is a closure of its own"),
+                };
+                let min = if variadic { binds - 1 } else { binds };
+                (closure, min, variadic)
+            })
+            .collect();
+        Arities { name, clauses }
+    }
+
+    pub fn closures(&self) -> impl Iterator<Item = &Closure> {
+        self.clauses.iter().map(|c| &c.0)
+    }
+
+    /// The closure of the clause taking `argc` args: the one taking exactly
+    /// as many if there is, else the variadic one.
+    pub fn closure_for(&self, argc: usize) -> Fallible<Closure> {
+        let fixed = self.clauses.iter().find(|c| !c.2 && c.1 == argc);
+        match fixed.or_else(|| self.clauses.iter().find(|c| c.2 && c.1 <= argc)) {
+            Some(clause) => Ok(clause.0.clone()),
+            None => Err(mismatch(self.name, &self.arities(), argc)),
+        }
+    }
+
+    fn arities(&self) -> Vec<(usize, bool)> {
+        self.clauses.iter().map(|c| (c.1, c.2)).collect()
+    }
+}
+
+/// The error of a call of the closure of `c_env` with `argc` args, which
+/// it does not take.
+pub fn arity_error(c_env: &ClosureEnv, argc: usize) -> Error {
+    let min = c_env.binds.len() - c_env.variadic as usize;
+    mismatch(c_env.name, &[(min, c_env.variadic)], argc)
+}
+
+fn mismatch(name: Option<Sym>, arities: &[(usize, bool)], argc: usize) -> Error {
+    ArityError(format!(
+        "{} should have {} params, got {}",
+        name.map_or_else(|| "fn*".to_string(), |name| name.to_string()),
+        accepted(arities),
+        argc
+    ))
+    .into()
+}
+
+/// The numbers of args of `arities`, like `1, 2 or at least 4`.
+fn accepted(arities: &[(usize, bool)]) -> String {
+    let mut arities = arities.to_vec();
+    arities.sort();
+    let mut arities: Vec<String> = arities
+        .into_iter()
+        .map(|(min, variadic)| {
+            if variadic {
+                format!("at least {}", min)
+            } else {
+                min.to_string()
+            }
+        })
+        .collect();
+    let last = arities.pop().unwrap();
+    if arities.is_empty() {
+        last
+    } else {
+        format!("{} or {}", arities.join(", "), last)
+    }
+}
+
+impl fmt::Debug for Arities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "Arities({})", name),
+            None => write!(f, "Arities({})", accepted(&self.arities())),
+        }
+    }
+}
+
+impl PartialEq for Arities {
+    fn eq(&self, other: &Arities) -> bool {
+        ::std::ptr::eq(self, other)
+    }
+}
+
+/// `f`, a fn just made by a `fn*` without a name, named `name` so that
+/// calls of it with args it does not take report that name.
+pub fn name_fn(f: MalType, name: Sym) -> MalType {
+    let (mut closure, meta) = match *f {
+        InnerMalType::Closure(ref closure, ref meta) => (closure.clone(), meta.clone()),
+        _ => return f,
+    };
+    if let Some(arities) = closure.arities.take() {
+        closure.arities = Some(match arities.name {
+            Some(_) => arities,
+            None => Rc::new(Arities {
+                name: Some(name),
+                clauses: arities.clauses.clone(),
+            }),
+        });
+    } else if let Some(ref mut c_env) = closure.c_env {
+        c_env.name = c_env.name.or(Some(name));
+    }
+    new_mal!(Closure(closure, meta))
+}
+
+/// The fn of the closures of the clauses of a `fn*` of several.
+pub fn new_fn(name: Option<Sym>, closures: Vec<Closure>) -> MalType {
+    new_mal!(Closure(
+        Closure::new_arities(Arities::new(name, closures)),
+        new_mal!(Nil)
+    ))
+}
@@ -0,0 +1,216 @@
+//! The C API of the `cdylib`, declared in `include/mal.h`.
+//!
+//! Values cross the boundary as text: results and the args of callbacks are
+//! printed readably, and what a callback returns is read back as a form.
+//! Every string the API returns is owned by the caller and freed with
+//! `mal_string_free`. No panic unwinds into C: one is caught at the boundary
+//! and reported as `MAL_PANIC`.
+//!
+//! Interpreters are confined to the thread that made them, and each has
+//! namespaces of its own.
+
+use failure::Fallible;
+use interpreter::Interpreter;
+use printer::try_pr_str;
+use reader::read_str;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use types::MalType;
+
+/// The call succeeded.
+pub const MAL_OK: c_int = 0;
+/// Mal raised an error, or the arguments were invalid.
+pub const MAL_ERROR: c_int = 1;
+/// The interpreter panicked. It should not be used anymore.
+pub const MAL_PANIC: c_int = 2;
+
+/// A mal interpreter, made by `mal_new` and freed by `mal_free`.
+pub struct MalInterpreter {
+    interpreter: Interpreter,
+}
+
+/// A mal function implemented in C. It gets the `user_data` it was
+/// registered with and its args printed readably, and returns the result as
+/// mal source, which is copied before the next call. On failure it returns
+/// NULL and points `error` at a message, copied as well.
+pub type MalFn = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        argc: usize,
+        argv: *const *const c_char,
+        error: *mut *const c_char,
+    ) -> *const c_char,
+>;
+
+/// Makes a new interpreter, or returns NULL if that panicked.
+#[no_mangle]
+pub extern "C" fn mal_new() -> *mut MalInterpreter {
+    match panic::catch_unwind(|| MalInterpreter {
+        interpreter: Interpreter::new(),
+    }) {
+        Ok(mal) => Box::into_raw(Box::new(mal)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Frees an interpreter made by `mal_new`, with its namespaces and what
+/// was defined in them. NULL is ignored.
+///
+/// # Safety
+///
+/// `mal` is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn mal_free(mal: *mut MalInterpreter) {
+    if !mal.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(mal))));
+    }
+}
+
+/// Evaluates the forms of `source`. Returns `MAL_OK` and points `result` at
+/// the printed value of the last form, or another status and points it at
+/// the error message.
+///
+/// # Safety
+///
+/// `mal` comes from `mal_new`, `source` is NUL-terminated, and `result` is
+/// NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn mal_eval_string(
+    mal: *mut MalInterpreter,
+    source: *const c_char,
+    result: *mut *mut c_char,
+) -> c_int {
+    let outcome = guard(|| {
+        let mal = interpreter(mal)?;
+        let value = mal.eval_str(text(source, "source")?)?;
+        try_pr_str(&value, true)
+    });
+    let (status, message) = match outcome {
+        Ok(printed) => (MAL_OK, printed),
+        Err(failure) => failure,
+    };
+    if !result.is_null() {
+        *result = c_string(message).into_raw();
+    }
+    status
+}
+
+/// Binds `name` in the current namespace to a mal function calling `f`
+/// with `user_data`. On failure, points `error` at the message unless it is
+/// NULL.
+///
+/// # Safety
+///
+/// `mal` comes from `mal_new`, `name` is NUL-terminated, `user_data` stays
+/// valid while the function can be called, and `error` is NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn mal_register_fn(
+    mal: *mut MalInterpreter,
+    name: *const c_char,
+    f: MalFn,
+    user_data: *mut c_void,
+    error: *mut *mut c_char,
+) -> c_int {
+    let outcome = guard(|| {
+        let mal = interpreter(mal)?;
+        let name = text(name, "name")?.to_string();
+        let f = match f {
+            Some(f) => f,
+            None => bail!("f is NULL"),
+        };
+        let fn_name = name.clone();
+        mal.register_fn(&name, move |args| {
+            call(&fn_name, f, user_data, args.into_iter().collect())
+        });
+        Ok(())
+    });
+    match outcome {
+        Ok(()) => MAL_OK,
+        Err((status, message)) => {
+            if !error.is_null() {
+                *error = c_string(message).into_raw();
+            }
+            status
+        }
+    }
+}
+
+/// Frees a string returned by the API. NULL is ignored.
+///
+/// # Safety
+///
+/// `s` comes from the API and is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn mal_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Runs `f`, turning an error or a panic into a status and a message.
+fn guard<T, F>(f: F) -> Result<T, (c_int, String)>
+where
+    F: FnOnce() -> Fallible<T>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err((MAL_ERROR, e.to_string())),
+        Err(payload) => {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => match payload.downcast_ref::<String>() {
+                    Some(s) => s.clone(),
+                    None => "unknown panic".to_string(),
+                },
+            };
+            Err((MAL_PANIC, format!("panic: {}", message)))
+        }
+    }
+}
+
+/// `s` without its NUL bytes, which C can not see.
+fn c_string(s: String) -> CString {
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+unsafe fn interpreter<'a>(mal: *mut MalInterpreter) -> Fallible<&'a Interpreter> {
+    match mal.as_ref() {
+        Some(mal) => Ok(&mal.interpreter),
+        None => bail!("the interpreter is NULL"),
+    }
+}
+
+unsafe fn text<'a>(s: *const c_char, what: &str) -> Fallible<&'a str> {
+    ensure!(!s.is_null(), "{} is NULL", what);
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(s),
+        Err(_) => bail!("{} is not UTF-8", what),
+    }
+}
+
+unsafe fn call(
+    name: &str,
+    f: unsafe extern "C" fn(*mut c_void, usize, *const *const c_char, *mut *const c_char)
+        -> *const c_char,
+    user_data: *mut c_void,
+    args: Vec<MalType>,
+) -> Fallible<MalType> {
+    let args = args
+        .iter()
+        .map(|arg| Ok(c_string(try_pr_str(arg, true)?)))
+        .collect::<Fallible<Vec<CString>>>()?;
+    let argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+    let mut error: *const c_char = ptr::null();
+    let result = f(user_data, argv.len(), argv.as_ptr(), &mut error);
+    if result.is_null() {
+        let message = if error.is_null() {
+            "failed".to_string()
+        } else {
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        };
+        bail!("{}: {}", name, message);
+    }
+    read_str(&CStr::from_ptr(result).to_string_lossy())
+}
@@ -0,0 +1,792 @@
+//! Compiles the analyzed body of a `fn*` to the bytecode run by `vm`.
+//!
+//! Locals of a compiled fn live in registers of its frame, and a nested
+//! `fn*` captures those it refers to by value when it is made, as its
+//! upvalues: locals never change once bound, but for those of a `loop*`,
+//! which a `recur` binds anew. Locals of the envs around the
+//! outermost compiled fn are still read from its env, like globals. A
+//! named `fn*` is bound to its name in an env of its own around its
+//! clauses, as `eval` binds it.
+//!
+//! A body whose forms need an env at run time, like `def!`, `binding` or
+//! `current-env`, or which refers to a name before its `let*` binds it,
+//! is compiled keeping its locals in envs instead, laid out as `eval`
+//! lays them out, and runs those forms in `eval` with the env of the
+//! frame.
+
+use analyze::is_special_form;
+use arity::{parse_fn, Clause, FnForm};
+use exception::{parse_try, TryForm};
+use fnv::FnvHashMap;
+#[cfg(jit)]
+use jit;
+use std::cell::{Cell, RefCell};
+use std::collections::LinkedList;
+use std::rc::{Rc, Weak};
+use symbol::Sym;
+use types::{HashKey, InnerMalType, MalType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Pushes a constant.
+    Const(u32),
+    /// Pushes a register of the frame.
+    Reg(u32),
+    /// Pushes an upvalue of the closure.
+    Upvalue(u32),
+    /// Pushes the slot of the env `depth` levels out of the closure's env.
+    Slot(u32, u32),
+    /// Pushes the global whose analyzed form is a constant.
+    Global(u32),
+    /// Pops into a register.
+    SetReg(u32),
+    Pop,
+    Jump(u32),
+    /// Pops, and jumps if it is nil or false.
+    JumpIfFalse(u32),
+    /// Calls the callee below that many args with them.
+    Call(u32),
+    /// Calls like `Call`, replacing the frame of the caller.
+    TailCall(u32),
+    Return,
+    /// Pushes a closure of a nested fn.
+    Closure(u32),
+    /// Pushes a lazy seq realised by a nested fn of no params.
+    LazySeq(u32),
+    Vec(u32),
+    /// Pops the values of the keys at that index of `keys`.
+    Map(u32),
+    /// Catches an error raised before the matching `EndTry` by putting the
+    /// value it is caught as in the register and jumping.
+    Try(u32, u32),
+    EndTry,
+    /// Pops a `catch*` selector, and pushes whether it selects the error
+    /// caught in the register.
+    Selects(u32),
+    /// Drops the error caught last, which a `catch*` clause handles.
+    Handled,
+    /// Raises the error caught last again.
+    Rethrow,
+    /// `eval` of the form, and of the env when given.
+    Eval(u32),
+    /// Pushes a fn of several clauses, or a named one, of that index of
+    /// `fns`.
+    Fn(u32),
+    /// Pushes the value of the constant form, run in `eval` in the env of
+    /// the frame.
+    Interp(u32),
+    /// Pops the values of the names at that index of `names` into a new
+    /// env of the frame, around its env.
+    PushEnv(u32),
+    /// Pops into a new slot of the env of the frame, named by the constant.
+    Bind(u32),
+    /// Leaves that many envs of the frame.
+    PopEnv(u32),
+}
+
+/// Where a nested fn takes an upvalue from when it is made.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Reg(u32),
+    Upvalue(u32),
+}
+
+/// A compiled fn.
+#[derive(Debug)]
+pub struct Proto {
+    pub params: MalType,
+    pub binds: Rc<Vec<Sym>>,
+    pub variadic: bool,
+    pub body: MalType,
+    pub nregs: usize,
+    pub code: Vec<Op>,
+    pub consts: Vec<MalType>,
+    pub keys: Vec<Vec<HashKey>>,
+    pub protos: Vec<Rc<Proto>>,
+    /// The name and the indexes in `protos` of the clauses of a fn `Fn`
+    /// makes.
+    pub fns: Vec<(Option<Sym>, Vec<u32>)>,
+    pub captures: Vec<Capture>,
+    /// The forms of the calls at those ops, in order, for backtraces.
+    pub sites: Vec<(u32, MalType)>,
+    /// Whether its locals are kept in envs rather than registers.
+    pub in_env: bool,
+    /// Whether a call binds the params in a frame, which the body of a
+    /// `lazy-seq` does not.
+    pub frame: bool,
+    /// The names of the envs `PushEnv` makes.
+    pub names: Vec<Rc<Vec<Sym>>>,
+    #[cfg(jit)]
+    pub jit: jit::Slot,
+}
+
+impl Proto {
+    /// The form of the call at the op at `ip`, if it is one.
+    pub fn site(&self, ip: usize) -> Option<&MalType> {
+        self.sites
+            .binary_search_by_key(&(ip as u32), |&(at, _)| at)
+            .ok()
+            .map(|idx| &self.sites[idx].1)
+    }
+}
+
+/// Protos are only equal to themselves.
+impl PartialEq for Proto {
+    fn eq(&self, other: &Proto) -> bool {
+        ::std::ptr::eq(self, other)
+    }
+}
+
+enum Cached {
+    Compiled(Weak<Proto>),
+    // the params and body, keeping their addresses from being reused
+    Interpreted(Weak<InnerMalType>, Weak<InnerMalType>),
+}
+
+/// Cache entries before the first sweep of those freed.
+const MIN_CACHED: usize = 1024;
+
+thread_local! {
+    static CACHE: RefCell<FnvHashMap<(usize, usize), Cached>> = RefCell::new(FnvHashMap::default());
+    static SWEEP_AT: Cell<usize> = const { Cell::new(MIN_CACHED) };
+}
+
+/// The compiled fn of the analyzed `params` and `body`, or `None` if it
+/// runs in `eval`. A `fn*` evaluated again reuses what it compiled to.
+pub fn compile_fn(params: &MalType, body: &MalType) -> Option<Rc<Proto>> {
+    let key = (
+        &**params as *const InnerMalType as usize,
+        &**body as *const InnerMalType as usize,
+    );
+    let cached = CACHE.with(|cache| match cache.borrow().get(&key) {
+        Some(Cached::Compiled(proto)) => proto.upgrade().map(Some),
+        Some(Cached::Interpreted(p, b)) if p.upgrade().is_some() && b.upgrade().is_some() => {
+            Some(None)
+        }
+        _ => None,
+    });
+    if let Some(proto) = cached {
+        return proto;
+    }
+    let proto = Compiler::new(false)
+        .compile(params, body, true, false)
+        .or_else(|| Compiler::new(true).compile(params, body, true, false))
+        .map(Rc::new);
+    let entry = match proto {
+        Some(ref proto) => Cached::Compiled(Rc::downgrade(proto)),
+        None => Cached::Interpreted(Rc::downgrade(params), Rc::downgrade(body)),
+    };
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.insert(key, entry);
+        SWEEP_AT.with(|at| {
+            if cache.len() >= at.get() {
+                cache.retain(|_, entry| match entry {
+                    Cached::Compiled(proto) => proto.upgrade().is_some(),
+                    Cached::Interpreted(p, b) => p.upgrade().is_some() && b.upgrade().is_some(),
+                });
+                at.set((cache.len() * 2).max(MIN_CACHED));
+            }
+        });
+    });
+    proto
+}
+
+/// Where a local is read from.
+enum Loc {
+    Reg(u32),
+    Upvalue(u32),
+    Slot(u32, u32),
+}
+
+/// A `loop*` a `recur` jumps back to.
+struct Loop {
+    // the registers of its locals, or the index of their names in an env
+    regs: Vec<u32>,
+    names: u32,
+    arity: usize,
+    // the ip of its body, and the envs the fn was in when it began
+    header: u32,
+    envs: u32,
+}
+
+/// A fn being compiled.
+#[derive(Default)]
+struct FnState {
+    // registers of the slots of its frames, outermost first
+    frames: Vec<Vec<u32>>,
+    // whether it is bound to its name in an env around it
+    named: bool,
+    // the envs it made which it is in
+    envs: u32,
+    next_reg: u32,
+    nregs: usize,
+    code: Vec<Op>,
+    consts: Vec<MalType>,
+    keys: Vec<Vec<HashKey>>,
+    protos: Vec<Rc<Proto>>,
+    fns: Vec<(Option<Sym>, Vec<u32>)>,
+    captures: Vec<Capture>,
+    sites: Vec<(u32, MalType)>,
+    names: Vec<Rc<Vec<Sym>>>,
+    // the `loop*`s it is in, outermost first
+    loops: Vec<Loop>,
+}
+
+struct Compiler {
+    // the fn being compiled and those it is nested in, outermost first
+    fns: Vec<FnState>,
+    // names of `let*` bindings whose values are being compiled, bound
+    // after them
+    pending: Vec<Vec<Sym>>,
+    // whether locals are kept in envs, as `eval` keeps them
+    in_env: bool,
+}
+
+/// Whether `form` has a `recur` of a compiled `loop*`, which a form run in
+/// `eval` could not jump back to. Those of its own `loop*`s are fine.
+fn has_recur(form: &MalType) -> bool {
+    match **form {
+        InnerMalType::List(ref items, _) => {
+            let head = items
+                .front()
+                .filter(|head| head.is_symbol())
+                .map(|h| h.to_symbol());
+            match head {
+                Some(head) if head == "recur" => true,
+                Some(head) if head == "loop*" || head == "fn*" => false,
+                _ => items.iter().any(has_recur),
+            }
+        }
+        InnerMalType::Vec(ref items, _) => items.iter().any(has_recur),
+        InnerMalType::Hashmap(ref mapping, _) => mapping.values().any(has_recur),
+        _ => false,
+    }
+}
+
+fn is_symbol_seq(mal: &MalType) -> bool {
+    (mal.is_list() || mal.is_vec()) && mal.to_items_ref().iter().all(|s| s.is_symbol())
+}
+
+/// The names bound by `params` of a `fn*`, and whether it is variadic,
+/// unless `&` is misplaced.
+fn parse_params(params: &MalType) -> Option<(Vec<Sym>, bool)> {
+    if !is_symbol_seq(params) {
+        return None;
+    }
+    let mut binds = params.to_symbol_list();
+    match binds.iter().position(|e| *e == "&") {
+        Some(idx) if binds.len() == idx + 2 => {
+            binds.remove(idx);
+            Some((binds, true))
+        }
+        Some(_) => None,
+        None => Some((binds, false)),
+    }
+}
+
+impl Compiler {
+    fn new(in_env: bool) -> Self {
+        Compiler {
+            fns: Vec::new(),
+            pending: Vec::new(),
+            in_env,
+        }
+    }
+
+    /// Compiles a fn, which is bound to its name around it when `named`.
+    /// Unlike `fn*`, the body of a `lazy-seq` binds no frame of params.
+    fn compile(
+        &mut self,
+        params: &MalType,
+        body: &MalType,
+        frame: bool,
+        named: bool,
+    ) -> Option<Proto> {
+        let (binds, variadic) = parse_params(params)?;
+        // the params of a fn in an env are bound in the env of its frame
+        let nparams = if self.in_env { 0 } else { binds.len() as u32 };
+        self.fns.push(FnState {
+            frames: if frame {
+                vec![(0..nparams).collect()]
+            } else {
+                Vec::new()
+            },
+            named,
+            next_reg: nparams,
+            nregs: nparams as usize,
+            ..FnState::default()
+        });
+        let compiled = self.expr(body, true);
+        let state = self.fns.pop().unwrap();
+        compiled?;
+        let mut code = state.code;
+        code.push(Op::Return);
+        Some(Proto {
+            params: params.clone(),
+            binds: Rc::new(binds),
+            variadic,
+            body: body.clone(),
+            nregs: state.nregs,
+            code,
+            consts: state.consts,
+            keys: state.keys,
+            protos: state.protos,
+            fns: state.fns,
+            captures: state.captures,
+            sites: state.sites,
+            in_env: self.in_env,
+            frame,
+            names: state.names,
+            #[cfg(jit)]
+            jit: jit::Slot::default(),
+        })
+    }
+
+    fn state(&mut self) -> &mut FnState {
+        self.fns.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let code = &mut self.state().code;
+        code.push(op);
+        code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next op.
+    fn patch(&mut self, at: usize) {
+        let code = &mut self.state().code;
+        let target = code.len() as u32;
+        code[at] = match code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::Try(_, reg) => Op::Try(target, reg),
+            op => unreachable!("{:?} is not a jump", op),
+        };
+    }
+
+    fn constant(&mut self, value: MalType) -> u32 {
+        let consts = &mut self.state().consts;
+        consts.push(value);
+        (consts.len() - 1) as u32
+    }
+
+    /// The index of `names` in those of the envs `PushEnv` makes.
+    fn env_names(&mut self, names: Vec<Sym>) -> u32 {
+        let all = &mut self.state().names;
+        all.push(Rc::new(names));
+        (all.len() - 1) as u32
+    }
+
+    fn alloc_reg(&mut self) -> u32 {
+        let state = self.state();
+        let reg = state.next_reg;
+        state.next_reg += 1;
+        state.nregs = state.nregs.max(state.next_reg as usize);
+        reg
+    }
+
+    /// Where slot `slot` of the frame `depth` levels out from the innermost
+    /// frame of the fn at `level` is.
+    fn resolve(&mut self, level: usize, depth: usize, slot: usize) -> Loc {
+        let len = self.fns[level].frames.len();
+        if depth < len {
+            return Loc::Reg(self.fns[level].frames[len - 1 - depth][slot]);
+        }
+        let mut depth = depth - len;
+        // the env its name is bound in is the env of its closure
+        let named = self.fns[level].named;
+        if named {
+            if depth == 0 {
+                return Loc::Slot(0, slot as u32);
+            }
+            depth -= 1;
+        }
+        if level == 0 {
+            return Loc::Slot(depth as u32, slot as u32);
+        }
+        let capture = match self.resolve(level - 1, depth, slot) {
+            Loc::Reg(reg) => Capture::Reg(reg),
+            Loc::Upvalue(idx) => Capture::Upvalue(idx),
+            Loc::Slot(depth, slot) => return Loc::Slot(depth + named as u32, slot),
+        };
+        let captures = &mut self.fns[level].captures;
+        let idx = match captures.iter().position(|c| *c == capture) {
+            Some(idx) => idx,
+            None => {
+                captures.push(capture);
+                captures.len() - 1
+            }
+        };
+        Loc::Upvalue(idx as u32)
+    }
+
+    /// Compiles `form` to push its value. In tail position, a call replaces
+    /// the frame.
+    fn expr(&mut self, form: &MalType, tail: bool) -> Option<()> {
+        match **form {
+            // the envs of a fn in an env are those the analyzer saw
+            InnerMalType::Local(_, depth, slot) if self.in_env => {
+                self.emit(Op::Slot(depth as u32, slot as u32));
+            }
+            InnerMalType::Local(_, depth, slot) => {
+                let level = self.fns.len() - 1;
+                let op = match self.resolve(level, depth, slot) {
+                    Loc::Reg(reg) => Op::Reg(reg),
+                    Loc::Upvalue(idx) => Op::Upvalue(idx),
+                    Loc::Slot(depth, slot) => Op::Slot(depth, slot),
+                };
+                self.emit(op);
+            }
+            InnerMalType::Global(name, _) => {
+                // which is looked up by name in an env
+                if !self.in_env && self.pending.iter().any(|names| names.contains(&name)) {
+                    return None;
+                }
+                let idx = self.constant(form.clone());
+                self.emit(Op::Global(idx));
+            }
+            InnerMalType::Symbol(_) | InnerMalType::LazySeq(..) => return None,
+            InnerMalType::List(ref items, _) if !items.is_empty() => {
+                return self.list(form, items, tail)
+            }
+            InnerMalType::List(..) => {
+                let idx = self.constant(new_mal!(List(LinkedList::new(), new_mal!(Nil))));
+                self.emit(Op::Const(idx));
+            }
+            InnerMalType::Vec(ref items, _) => {
+                for item in items {
+                    self.expr(item, false)?;
+                }
+                self.emit(Op::Vec(items.len() as u32));
+            }
+            InnerMalType::Hashmap(ref mapping, _) => {
+                let mut keys = Vec::with_capacity(mapping.len());
+                for (k, v) in mapping {
+                    keys.push(k.clone());
+                    self.expr(v, false)?;
+                }
+                let state = self.state();
+                state.keys.push(keys);
+                let idx = (state.keys.len() - 1) as u32;
+                self.emit(Op::Map(idx));
+            }
+            _ => {
+                let idx = self.constant(form.clone());
+                self.emit(Op::Const(idx));
+            }
+        }
+        Some(())
+    }
+
+    fn list(&mut self, form: &MalType, items: &LinkedList<MalType>, tail: bool) -> Option<()> {
+        let head = items.front().unwrap();
+        if head.is_symbol() {
+            let args: Vec<&MalType> = items.iter().skip(1).collect();
+            return match head.to_symbol().as_str() {
+                "do" => self.do_form(&args, tail),
+                "if" => self.if_form(&args, tail),
+                "let*" => self.let_form(&args, tail, false),
+                "loop*" => self.let_form(&args, tail, true),
+                "recur" => self.recur(&args),
+                "fn*" => self.fn_form(&args),
+                "quote" if args.len() == 1 => {
+                    let idx = self.constant(args[0].clone());
+                    self.emit(Op::Const(idx));
+                    Some(())
+                }
+                "try*" => self.try_form(&args, tail),
+                "lazy-seq" => {
+                    let mut body: LinkedList<MalType> = args.into_iter().cloned().collect();
+                    body.push_front(new_mal!(Symbol(Sym::new("do"))));
+                    let params = new_mal!(List(LinkedList::new(), new_mal!(Nil)));
+                    let body = new_mal!(List(body, new_mal!(Nil)));
+                    let idx = self.nested(&params, &body, false, false)?;
+                    self.emit(Op::LazySeq(idx));
+                    Some(())
+                }
+                "eval" if args.len() == 1 || args.len() == 2 => {
+                    for arg in &args {
+                        self.expr(arg, false)?;
+                    }
+                    self.emit(Op::Eval(args.len() as u32));
+                    Some(())
+                }
+                // the other special forms need an env
+                name if self.in_env && is_special_form(name) && !has_recur(form) => {
+                    let idx = self.constant(form.clone());
+                    self.emit(Op::Interp(idx));
+                    Some(())
+                }
+                _ => None,
+            };
+        }
+        for item in items {
+            self.expr(item, false)?;
+        }
+        let argc = (items.len() - 1) as u32;
+        let ip = self.emit(if tail {
+            Op::TailCall(argc)
+        } else {
+            Op::Call(argc)
+        });
+        self.state().sites.push((ip as u32, form.clone()));
+        Some(())
+    }
+
+    fn do_form(&mut self, args: &[&MalType], tail: bool) -> Option<()> {
+        match args.split_last() {
+            Some((last, leading)) => {
+                for form in leading {
+                    self.expr(form, false)?;
+                    self.emit(Op::Pop);
+                }
+                self.expr(last, tail)
+            }
+            None => self.expr(&new_mal!(Nil), tail),
+        }
+    }
+
+    fn if_form(&mut self, args: &[&MalType], tail: bool) -> Option<()> {
+        if args.len() < 2 || args.len() > 3 {
+            return None;
+        }
+        self.expr(args[0], false)?;
+        let to_else = self.emit(Op::JumpIfFalse(0));
+        self.expr(args[1], tail)?;
+        let to_end = self.emit(Op::Jump(0));
+        self.patch(to_else);
+        match args.get(2) {
+            Some(else_clause) => self.expr(else_clause, tail)?,
+            None => self.expr(&new_mal!(Nil), tail)?,
+        }
+        self.patch(to_end);
+        Some(())
+    }
+
+    /// Compiles a `let*`, or a `loop*` whose body a `recur` jumps back to.
+    fn let_form(&mut self, args: &[&MalType], tail: bool, is_loop: bool) -> Option<()> {
+        if args.len() != 2 || !args[0].is_list() || !args[0].len().is_multiple_of(2) {
+            return None;
+        }
+        let pairs: Vec<&MalType> = args[0].to_items_ref().iter().collect();
+        if pairs.iter().step_by(2).any(|k| !k.is_symbol()) {
+            return None;
+        }
+        let names: Vec<Sym> = pairs.iter().step_by(2).map(|k| k.to_symbol()).collect();
+        if self.in_env {
+            return self.env_let_form(&pairs, names, args[1], tail, is_loop);
+        }
+        let saved_reg = self.state().next_reg;
+        self.state().frames.push(Vec::new());
+        for (i, pair) in pairs.chunks(2).enumerate() {
+            self.pending.push(names[i..].to_vec());
+            let compiled = self.expr(pair[1], false);
+            self.pending.pop();
+            compiled?;
+            let reg = self.alloc_reg();
+            self.emit(Op::SetReg(reg));
+            self.state().frames.last_mut().unwrap().push(reg);
+        }
+        if is_loop {
+            let state = self.state();
+            let regs = state.frames.last().unwrap().clone();
+            let header = state.code.len() as u32;
+            let envs = state.envs;
+            state.loops.push(Loop {
+                arity: regs.len(),
+                regs,
+                names: 0,
+                header,
+                envs,
+            });
+        }
+        self.expr(args[1], tail)?;
+        let state = self.state();
+        if is_loop {
+            state.loops.pop();
+        }
+        state.frames.pop();
+        state.next_reg = saved_reg;
+        Some(())
+    }
+
+    /// Compiles a `let*` or `loop*` of a fn in an env, which binds each
+    /// name in an env of its own once its value is known, as `eval` does.
+    fn env_let_form(
+        &mut self,
+        pairs: &[&MalType],
+        names: Vec<Sym>,
+        body: &MalType,
+        tail: bool,
+        is_loop: bool,
+    ) -> Option<()> {
+        let empty = self.env_names(Vec::new());
+        self.emit(Op::PushEnv(empty));
+        self.state().envs += 1;
+        for (pair, name) in pairs.chunks(2).zip(&names) {
+            self.expr(pair[1], false)?;
+            let idx = self.constant(new_mal!(Symbol(*name)));
+            self.emit(Op::Bind(idx));
+        }
+        if is_loop {
+            let arity = names.len();
+            let names = self.env_names(names);
+            let state = self.state();
+            let header = state.code.len() as u32;
+            let envs = state.envs - 1;
+            state.loops.push(Loop {
+                regs: Vec::new(),
+                names,
+                arity,
+                header,
+                envs,
+            });
+        }
+        self.expr(body, tail)?;
+        self.emit(Op::PopEnv(1));
+        let state = self.state();
+        if is_loop {
+            state.loops.pop();
+        }
+        state.envs -= 1;
+        Some(())
+    }
+
+    /// Compiles a `recur`, which `analyze` put in tail position of the
+    /// innermost `loop*`, to rebind its registers, or its names in a new
+    /// env, and jump back.
+    fn recur(&mut self, args: &[&MalType]) -> Option<()> {
+        let (regs, names, header, envs) = match self.state().loops.last() {
+            Some(l) if l.arity == args.len() => (l.regs.clone(), l.names, l.header, l.envs),
+            _ => return None,
+        };
+        for arg in args {
+            self.expr(arg, false)?;
+        }
+        if self.in_env {
+            // the env of the loop is made anew around the one it began in
+            let envs = self.state().envs - envs;
+            self.emit(Op::PopEnv(envs));
+            self.emit(Op::PushEnv(names));
+        }
+        for reg in regs.into_iter().rev() {
+            self.emit(Op::SetReg(reg));
+        }
+        self.emit(Op::Jump(header));
+        Some(())
+    }
+
+    fn try_form(&mut self, args: &[&MalType], tail: bool) -> Option<()> {
+        let form = parse_try(args.iter().cloned()).ok()?;
+        let finally = match form.finally {
+            Some(ref finally) => finally,
+            None => return self.catches(&form, tail),
+        };
+        // the `finally` clause runs after the rest, and when it raises
+        let reg = self.alloc_reg();
+        let to_finally = self.emit(Op::Try(0, reg));
+        self.catches(&form, false)?;
+        self.emit(Op::EndTry);
+        self.finally(finally)?;
+        let to_end = self.emit(Op::Jump(0));
+        self.patch(to_finally);
+        self.finally(finally)?;
+        self.emit(Op::Rethrow);
+        self.patch(to_end);
+        self.state().next_reg = reg;
+        Some(())
+    }
+
+    /// Compiles the body of a `try*` and its `catch*` clauses. The error
+    /// none of them selects is raised again.
+    fn catches(&mut self, form: &TryForm, tail: bool) -> Option<()> {
+        if form.catches.is_empty() {
+            return self.expr(&form.body, tail);
+        }
+        let reg = self.alloc_reg();
+        let to_catch = self.emit(Op::Try(0, reg));
+        self.expr(&form.body, false)?;
+        self.emit(Op::EndTry);
+        let mut to_end = vec![self.emit(Op::Jump(0))];
+        self.patch(to_catch);
+        for catch in &form.catches {
+            let to_next = match catch.selector {
+                Some(ref selector) => {
+                    self.expr(selector, false)?;
+                    self.emit(Op::Selects(reg));
+                    Some(self.emit(Op::JumpIfFalse(0)))
+                }
+                None => None,
+            };
+            self.emit(Op::Handled);
+            if self.in_env {
+                let names = self.env_names(vec![catch.name.to_symbol()]);
+                self.emit(Op::Reg(reg));
+                self.emit(Op::PushEnv(names));
+                self.state().envs += 1;
+                self.expr(&catch.body, tail)?;
+                self.emit(Op::PopEnv(1));
+                self.state().envs -= 1;
+            } else {
+                self.state().frames.push(vec![reg]);
+                self.expr(&catch.body, tail)?;
+                self.state().frames.pop();
+            }
+            to_end.push(self.emit(Op::Jump(0)));
+            if let Some(at) = to_next {
+                self.patch(at);
+            }
+        }
+        self.emit(Op::Rethrow);
+        for at in to_end {
+            self.patch(at);
+        }
+        self.state().next_reg = reg;
+        Some(())
+    }
+
+    /// Compiles the forms of a `finally` clause for their effects.
+    fn finally(&mut self, clause: &MalType) -> Option<()> {
+        for form in clause.to_items_ref().iter().skip(1) {
+            self.expr(form, false)?;
+            self.emit(Op::Pop);
+        }
+        Some(())
+    }
+
+    /// Compiles a `fn*` form nested in the current fn.
+    fn fn_form(&mut self, args: &[&MalType]) -> Option<()> {
+        let FnForm { name, clauses } = parse_fn(args.iter().cloned()).ok()?;
+        if name.is_none() && clauses.len() == 1 {
+            let Clause { params, body } = clauses.into_iter().next().unwrap();
+            let idx = self.nested(&params, &body, true, false)?;
+            self.emit(Op::Closure(idx));
+            return Some(());
+        }
+        let mut idxs = Vec::with_capacity(clauses.len());
+        for Clause { params, body } in clauses {
+            idxs.push(self.nested(&params, &body, true, name.is_some())?);
+        }
+        let fns = &mut self.state().fns;
+        fns.push((name, idxs));
+        let idx = (fns.len() - 1) as u32;
+        self.emit(Op::Fn(idx));
+        Some(())
+    }
+
+    /// Compiles a fn nested in the current one. Returns its index in
+    /// `protos`.
+    fn nested(
+        &mut self,
+        params: &MalType,
+        body: &MalType,
+        frame: bool,
+        named: bool,
+    ) -> Option<u32> {
+        let proto = self.compile(params, body, frame, named)?;
+        let protos = &mut self.state().protos;
+        protos.push(Rc::new(proto));
+        Some((protos.len() - 1) as u32)
+    }
+}
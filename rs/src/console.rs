@@ -0,0 +1,36 @@
+use failure::Fallible;
+use runtime::{self, Runtime};
+use std::io::{BufRead, Write};
+
+/// Sends what `prn`, `println` and the like print in `runtime` to `out`
+/// instead of the process's stdout.
+pub fn set_stdout(runtime: &Runtime, out: Box<dyn Write>) {
+    *runtime.stdout.borrow_mut() = out;
+}
+
+/// Makes `readline` in `runtime` read from `input` instead of the
+/// process's stdin.
+pub fn set_stdin(runtime: &Runtime, input: Box<dyn BufRead>) {
+    *runtime.stdin.borrow_mut() = input;
+}
+
+pub fn print(s: &str) -> Fallible<()> {
+    let runtime = runtime::current();
+    let mut stdout = runtime.stdout.borrow_mut();
+    stdout.write_all(s.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+pub fn println(s: &str) -> Fallible<()> {
+    print(&format!("{}\n", s))
+}
+
+/// The next line of input without its line break, or `None` at the end.
+pub fn read_line() -> Fallible<Option<String>> {
+    let mut buf = String::new();
+    if runtime::current().stdin.borrow_mut().read_line(&mut buf)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(buf.trim_end().to_string()))
+}
@@ -0,0 +1,353 @@
+//! Conversions between Rust values and mal values.
+//!
+//! Sequences become vectors, `HashMap<String, T>` a map with string keys,
+//! `None` nil and tuples vectors of their elements. `#[derive(IntoMal,
+//! FromMal)]` from `rs_derive` maps a struct with named fields to a map
+//! keyed by keywords of its field names, with `_` spelled `-`, and an enum
+//! to a tagged value: a keyword of the variant name in kebab-case for a
+//! unit variant, `[:tag field...]` for a tuple variant and `[:tag {...}]`
+//! for one with named fields. A newtype struct converts as its field.
+//!
+//! The functions below the traits are what the derived impls call.
+
+use error::ConversionError;
+use failure::Error;
+pub use failure::Fallible;
+use lazy::seq_to_list;
+use printer::pr_str;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{HashKey, InnerMalType, Kind, MalType};
+
+pub trait IntoMal {
+    fn into_mal(self) -> MalType;
+}
+
+pub trait FromMal: Sized {
+    fn from_mal(mal: &MalType) -> Fallible<Self>;
+}
+
+/// Fails to convert `found`, which is not the `expected` kind of value.
+pub fn mismatch<T>(expected: &str, found: &MalType) -> Fallible<T> {
+    Err(ConversionError {
+        path: String::new(),
+        message: format!(
+            "expected {}, got {} {}",
+            expected,
+            Kind::of(found).name(),
+            pr_str(found, true)
+        ),
+    }.into())
+}
+
+/// `result`, with `segment` prepended to the path of a conversion error.
+/// Any other error becomes a conversion error at `segment`.
+pub fn at<T>(segment: &str, result: Fallible<T>) -> Fallible<T> {
+    result.map_err(|e| -> Error {
+        match e.downcast::<ConversionError>() {
+            Ok(mut e) => {
+                e.path.insert_str(0, segment);
+                e.into()
+            }
+            Err(e) => ConversionError {
+                path: segment.to_string(),
+                message: e.to_string(),
+            }.into(),
+        }
+    })
+}
+
+/// A map of the keywords of `fields` to their values.
+pub fn keyword_map(fields: Vec<(&str, MalType)>) -> MalType {
+    let map = fields
+        .into_iter()
+        .map(|(k, v)| (HashKey::Keyword(Sym::new(k)), v))
+        .collect();
+    Rc::new(InnerMalType::Hashmap(map, Rc::new(InnerMalType::Nil)))
+}
+
+/// The entries of a map or record.
+pub fn entries<'a>(mal: &'a MalType, expected: &str) -> Fallible<&'a HashMap<HashKey, MalType>> {
+    match **mal {
+        InnerMalType::Hashmap(ref map, _) | InnerMalType::Record(_, ref map, _) => Ok(map),
+        _ => mismatch(expected, mal),
+    }
+}
+
+/// The value of the keyword `key` in `map`, converted. A missing key
+/// converts from nil, so it is fine for an `Option`.
+pub fn field<T: FromMal>(map: &HashMap<HashKey, MalType>, key: &str) -> Fallible<T> {
+    let value = map
+        .get(&HashKey::Keyword(Sym::new(key)))
+        .cloned()
+        .unwrap_or_else(|| Rc::new(InnerMalType::Nil));
+    at(&format!(".{}", &key[1..]), T::from_mal(&value))
+}
+
+/// The items of a list or vector, or of a lazy seq, which is realized.
+pub fn items(mal: &MalType, expected: &str) -> Fallible<Vec<MalType>> {
+    match **mal {
+        InnerMalType::List(ref l, _) | InnerMalType::Vec(ref l, _) => {
+            Ok(l.iter().cloned().collect())
+        }
+        InnerMalType::LazySeq(..) => Ok(seq_to_list(mal)?.into_iter().collect()),
+        _ => mismatch(expected, mal),
+    }
+}
+
+/// The `index`th of `items`, converted.
+pub fn element<T: FromMal>(items: &[MalType], index: usize) -> Fallible<T> {
+    at(&format!("[{}]", index), T::from_mal(&items[index]))
+}
+
+/// The items of a vector `[a b ...]` which has exactly `len` of them.
+pub fn tuple(mal: &MalType, len: usize) -> Fallible<Vec<MalType>> {
+    let expected = format!("a vector of {}", len);
+    let items = items(mal, &expected)?;
+    if items.len() != len {
+        return mismatch(&expected, mal);
+    }
+    Ok(items)
+}
+
+pub fn vector(items: Vec<MalType>) -> MalType {
+    Rc::new(InnerMalType::Vec(items.into_iter().collect(), Rc::new(InnerMalType::Nil)))
+}
+
+/// A tagged value, `tag` alone or `[tag value...]`.
+pub fn tagged(tag: &str, values: Vec<MalType>) -> MalType {
+    let tag = Rc::new(InnerMalType::Keyword(Sym::new(tag)));
+    if values.is_empty() {
+        return tag;
+    }
+    vector(Some(tag).into_iter().chain(values).collect())
+}
+
+/// The tag of a tagged value and the values after it.
+pub fn untag(mal: &MalType, expected: &str) -> Fallible<(Sym, Vec<MalType>)> {
+    if let InnerMalType::Keyword(tag) = **mal {
+        return Ok((tag, Vec::new()));
+    }
+    if let Ok(items) = items(mal, expected) {
+        if let Some(&InnerMalType::Keyword(tag)) = items.first().map(|i| &**i) {
+            return Ok((tag, items[1..].to_vec()));
+        }
+    }
+    mismatch(expected, mal)
+}
+
+/// Fails unless the variant `tag` of `mal` was tagged with `len` values.
+pub fn check_arity(tag: Sym, values: &[MalType], len: usize, mal: &MalType) -> Fallible<()> {
+    if values.len() != len {
+        return mismatch(&format!("{} with {} value(s)", tag, len), mal);
+    }
+    Ok(())
+}
+
+/// Fails to convert the tagged value `mal`, whose tag is none of `tags`.
+pub fn unknown_variant<T>(tags: &[&str], mal: &MalType) -> Fallible<T> {
+    mismatch(&format!("one of {}", tags.join(" ")), mal)
+}
+
+impl IntoMal for MalType {
+    fn into_mal(self) -> MalType {
+        self
+    }
+}
+
+impl FromMal for MalType {
+    fn from_mal(mal: &MalType) -> Fallible<MalType> {
+        Ok(mal.clone())
+    }
+}
+
+impl IntoMal for () {
+    fn into_mal(self) -> MalType {
+        Rc::new(InnerMalType::Nil)
+    }
+}
+
+impl FromMal for () {
+    fn from_mal(mal: &MalType) -> Fallible<()> {
+        match **mal {
+            InnerMalType::Nil => Ok(()),
+            _ => mismatch("nil", mal),
+        }
+    }
+}
+
+impl IntoMal for bool {
+    fn into_mal(self) -> MalType {
+        Rc::new(InnerMalType::Bool(self))
+    }
+}
+
+impl FromMal for bool {
+    fn from_mal(mal: &MalType) -> Fallible<bool> {
+        match **mal {
+            InnerMalType::Bool(b) => Ok(b),
+            _ => mismatch("Boolean", mal),
+        }
+    }
+}
+
+macro_rules! convert_float {
+    ($($t:ty)*) => {$(
+        impl IntoMal for $t {
+            fn into_mal(self) -> MalType {
+                Rc::new(InnerMalType::Num(self as f64))
+            }
+        }
+
+        impl FromMal for $t {
+            fn from_mal(mal: &MalType) -> Fallible<$t> {
+                match **mal {
+                    InnerMalType::Num(n) => Ok(n as $t),
+                    _ => mismatch("Number", mal),
+                }
+            }
+        }
+    )*};
+}
+
+convert_float!(f32 f64);
+
+macro_rules! convert_int {
+    ($($t:ident)*) => {$(
+        impl IntoMal for $t {
+            fn into_mal(self) -> MalType {
+                Rc::new(InnerMalType::Num(self as f64))
+            }
+        }
+
+        impl FromMal for $t {
+            fn from_mal(mal: &MalType) -> Fallible<$t> {
+                // `MAX as f64` of a 64-bit type rounds up to a power of two
+                // past it, so a num fits if it is below `MAX + 1`
+                match **mal {
+                    InnerMalType::Num(n)
+                        if n.fract() == 0.0 && n >= $t::MIN as f64 && n < $t::MAX as f64 + 1.0 =>
+                    {
+                        Ok(n as $t)
+                    }
+                    _ => mismatch(stringify!($t), mal),
+                }
+            }
+        }
+    )*};
+}
+
+convert_int!(i8 i16 i32 i64 isize u8 u16 u32 u64 usize);
+
+impl IntoMal for String {
+    fn into_mal(self) -> MalType {
+        Rc::new(InnerMalType::String(self))
+    }
+}
+
+impl IntoMal for &str {
+    fn into_mal(self) -> MalType {
+        Rc::new(InnerMalType::String(self.to_string()))
+    }
+}
+
+impl FromMal for String {
+    fn from_mal(mal: &MalType) -> Fallible<String> {
+        match **mal {
+            InnerMalType::String(ref s) => Ok(s.clone()),
+            _ => mismatch("String", mal),
+        }
+    }
+}
+
+impl<T: IntoMal> IntoMal for Option<T> {
+    fn into_mal(self) -> MalType {
+        match self {
+            Some(v) => v.into_mal(),
+            None => Rc::new(InnerMalType::Nil),
+        }
+    }
+}
+
+impl<T: FromMal> FromMal for Option<T> {
+    fn from_mal(mal: &MalType) -> Fallible<Option<T>> {
+        match **mal {
+            InnerMalType::Nil => Ok(None),
+            _ => T::from_mal(mal).map(Some),
+        }
+    }
+}
+
+impl<T: IntoMal> IntoMal for Vec<T> {
+    fn into_mal(self) -> MalType {
+        vector(self.into_iter().map(IntoMal::into_mal).collect())
+    }
+}
+
+/// From a list, vector or lazy seq, or nil for none.
+impl<T: FromMal> FromMal for Vec<T> {
+    fn from_mal(mal: &MalType) -> Fallible<Vec<T>> {
+        if mal.is_nil() {
+            return Ok(Vec::new());
+        }
+        let items = items(mal, "List or Vector")?;
+        (0..items.len()).map(|i| element(&items, i)).collect()
+    }
+}
+
+impl<T: IntoMal, S: BuildHasher> IntoMal for HashMap<String, T, S> {
+    fn into_mal(self) -> MalType {
+        let map = self
+            .into_iter()
+            .map(|(k, v)| (HashKey::String(k), v.into_mal()))
+            .collect();
+        Rc::new(InnerMalType::Hashmap(map, Rc::new(InnerMalType::Nil)))
+    }
+}
+
+/// From a map with string or keyword keys, a keyword `:k` becoming `"k"`,
+/// or nil for none.
+impl<T: FromMal, S: BuildHasher + Default> FromMal for HashMap<String, T, S> {
+    fn from_mal(mal: &MalType) -> Fallible<HashMap<String, T, S>> {
+        let mut map = HashMap::default();
+        if mal.is_nil() {
+            return Ok(map);
+        }
+        for (k, v) in entries(mal, "Map")? {
+            let key = match k {
+                HashKey::String(s) => s.clone(),
+                HashKey::Keyword(s) => s[1..].to_string(),
+                HashKey::Symbol(_) => return mismatch("a String or Keyword key", &k.to_mal_type()),
+            };
+            let value = at(&format!("[{:?}]", key), T::from_mal(v))?;
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+}
+
+macro_rules! convert_tuple {
+    ($len:expr => $($t:ident $i:tt)*) => {
+        impl<$($t: IntoMal),*> IntoMal for ($($t,)*) {
+            fn into_mal(self) -> MalType {
+                vector(vec![$(self.$i.into_mal()),*])
+            }
+        }
+
+        impl<$($t: FromMal),*> FromMal for ($($t,)*) {
+            fn from_mal(mal: &MalType) -> Fallible<($($t,)*)> {
+                let items = tuple(mal, $len)?;
+                Ok(($(element::<$t>(&items, $i)?,)*))
+            }
+        }
+    };
+}
+
+convert_tuple!(1 => A 0);
+convert_tuple!(2 => A 0 B 1);
+convert_tuple!(3 => A 0 B 1 C 2);
+convert_tuple!(4 => A 0 B 1 C 2 D 3);
+convert_tuple!(5 => A 0 B 1 C 2 D 3 E 4);
+convert_tuple!(6 => A 0 B 1 C 2 D 3 E 4 F 5);
@@ -1,49 +1,70 @@
-use error::MalExceptionError;
+use console;
+use doc::native_meta;
+use env::{env_get, env_interns, env_names, env_new, env_outer, env_set, Env};
+use error::{MalExceptionError, TypeError};
+use exception::{ex_info, ex_info_field};
 use failure::Fallible;
-use printer::pr_str;
+use gc;
+use lazy::{cell, lazy_source, seq_iter, seq_step, seq_to_list, LazySeq};
+use printer::{set_print_length, try_pr_str};
+use multi;
+use multi::MultiFn;
+use module;
+use namespace;
+use protocol::Protocol;
 use reader::read_str;
-use std::cell::RefCell;
+use record::{define_record_type, RecordType};
+use runtime;
+use regex::{Captures, Match, Regex};
+use stack;
 use std::collections::HashMap;
 use std::collections::LinkedList;
 use std::fs::File;
-use std::io::stdout;
-use std::io::Write;
-use std::io::{stdin, Read};
+use std::io::Read;
+use std::mem;
 use std::rc::Rc;
+use symbol::Sym;
 use time;
-use types::{Closure, MalType, InnerMalType, ClosureEnv};
+use trace;
+use types::{Closure, ClosureFunc, MalType, InnerMalType, ClosureEnv, HashKey, Kind, Pattern, Var};
 
-fn add(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "add should have 2 params");
-    Ok(new_mal!(Num(
-        params.pop_front().unwrap().to_number() + params.pop_front().unwrap().to_number()
-    )))
+/// The two nums of the params of the builtin `name`.
+fn two_nums(name: &str, mut params: LinkedList<MalType>) -> Fallible<(f64, f64)> {
+    ensure_arity!(params.len() == 2, "{} should have 2 params", name);
+    let left = params.pop_front().unwrap();
+    let right = params.pop_front().unwrap();
+    ensure_type!(
+        left.is_num() && right.is_num(),
+        "{}'s params should be num, got {} and {}",
+        name,
+        try_pr_str(&left, true)?,
+        try_pr_str(&right, true)?
+    );
+    Ok((left.to_number(), right.to_number()))
 }
 
-fn minus(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "minus should have 2 params");
-    Ok(new_mal!(Num(
-        params.pop_front().unwrap().to_number() - params.pop_front().unwrap().to_number()
-    )))
+fn add(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (left, right) = two_nums("add", params)?;
+    Ok(new_mal!(Num(left + right)))
 }
 
-fn multiply(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "multiply should have 2 params");
-    Ok(new_mal!(Num(
-        params.pop_front().unwrap().to_number() * params.pop_front().unwrap().to_number()
-    )))
+fn minus(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (left, right) = two_nums("minus", params)?;
+    Ok(new_mal!(Num(left - right)))
 }
 
-fn divide(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "divide should have 2 params");
-    Ok(new_mal!(Num(
-        params.pop_front().unwrap().to_number() / params.pop_front().unwrap().to_number()
-    )))
+fn multiply(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (left, right) = two_nums("multiply", params)?;
+    Ok(new_mal!(Num(left * right)))
+}
+
+fn divide(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (left, right) = two_nums("divide", params)?;
+    Ok(new_mal!(Num(left / right)))
 }
 
 fn prn(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    println!("{}", pr_str2(params, None)?.to_string());
-    stdout().flush()?;
+    console::println(&pr_str2(params, None)?.to_string())?;
     Ok(new_mal!(Nil))
 }
 
@@ -51,8 +72,8 @@ fn pr_str2(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<
     Ok(new_mal!(String(
         params
             .into_iter()
-            .map(|p| pr_str(&p, true))
-            .collect::<Vec<String>>()
+            .map(|p| try_pr_str(&p, true))
+            .collect::<Fallible<Vec<String>>>()?
             .join(" ")
     )))
 }
@@ -61,22 +82,20 @@ pub fn str2(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible
     Ok(new_mal!(String(
         params
             .into_iter()
-            .map(|p| pr_str(&p, false))
-            .collect::<Vec<String>>()
+            .map(|p| try_pr_str(&p, false))
+            .collect::<Fallible<Vec<String>>>()?
             .join("")
     )))
 }
 
 fn println2(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    println!(
-        "{}",
-        params
+    console::println(
+        &params
             .into_iter()
-            .map(|p| pr_str(&p, false))
-            .collect::<Vec<String>>()
-            .join(" ")
-    );
-    stdout().flush()?;
+            .map(|p| try_pr_str(&p, false))
+            .collect::<Fallible<Vec<String>>>()?
+            .join(" "),
+    )?;
     Ok(new_mal!(Nil))
 }
 
@@ -86,111 +105,140 @@ fn list(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible
 }
 
 fn is_list(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "list? should have 1 params");
+    ensure_arity!(params.len() == 1, "list? should have 1 params");
     Ok(new_mal!(Bool(params.pop_front().unwrap().is_list())))
 }
 
 fn is_empty(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "empty? should have 1 params");
-    Ok(new_mal!(Bool(
-        params.pop_front().unwrap().is_empty_collection()
-    )))
+    ensure_arity!(params.len() == 1, "empty? should have 1 params");
+    let param = params.pop_front().unwrap();
+    if param.is_lazy_seq() {
+        return Ok(new_mal!(Bool(seq_step(&param)?.is_none())));
+    }
+    Ok(new_mal!(Bool(param.is_empty_collection())))
 }
 
 fn count(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "count should have 1 params");
+    ensure_arity!(params.len() == 1, "count should have 1 params");
     let param = params.pop_front().unwrap();
     if param.is_nil() {
         return Ok(new_mal!(Num(0f64)));
     }
-    ensure!(param.is_collection(), "param should be list");
+    if param.is_lazy_seq() {
+        let mut n = 0;
+        for item in seq_iter(&param)? {
+            let _ = item?;
+            n += 1;
+        }
+        return Ok(new_mal!(Num(n as f64)));
+    }
+    ensure_type!(param.is_collection(), "param should be list");
     Ok(new_mal!(Num(param.len() as f64)))
 }
 
 fn equal(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "= should have 2 params");
+    ensure_arity!(params.len() == 2, "= should have 2 params");
     let left = params.pop_front().unwrap();
     let right = params.pop_front().unwrap();
-    Ok(new_mal!(Bool(eq(left, right))))
+    Ok(new_mal!(Bool(eq(left, right)?)))
 }
 
-fn eq(left: MalType, right: MalType) -> bool {
-    if left.is_collection() && right.is_collection() {
+pub fn eq(left: MalType, right: MalType) -> Fallible<bool> {
+    if left.is_lazy_seq() || right.is_lazy_seq() {
+        if !left.is_sequential() || !right.is_sequential() {
+            return Ok(false);
+        }
+        let mut inner_left = seq_iter(&left)?;
+        let mut inner_right = seq_iter(&right)?;
+        loop {
+            match (inner_left.next(), inner_right.next()) {
+                (None, None) => return Ok(true),
+                (Some(l), Some(r)) => {
+                    if !eq(l?, r?)? {
+                        return Ok(false);
+                    }
+                }
+                _ => return Ok(false),
+            }
+        }
+    } else if left.is_collection() && right.is_collection() {
         let inner_left = left.to_items_ref();
         let inner_right = right.to_items_ref();
         if inner_left.len() != inner_right.len() {
-            return false;
+            return Ok(false);
         }
 
-        inner_left
-            .into_iter()
-            .zip(inner_right)
-            .all(|(l, r)| eq(l.clone(), r.clone()))
+        for (l, r) in inner_left.into_iter().zip(inner_right) {
+            if !eq(l.clone(), r.clone())? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
     } else if left.is_hashmap() && right.is_hashmap() {
+        match (&*left, &*right) {
+            (InnerMalType::Record(lt, ..), InnerMalType::Record(rt, ..)) if Rc::ptr_eq(lt, rt) => {}
+            (InnerMalType::Hashmap(..), InnerMalType::Hashmap(..)) => {}
+            _ => return Ok(false),
+        }
         let inner_left = left.to_hashmap_ref();
         let inner_right = right.to_hashmap_ref();
         if inner_left.len() != inner_right.len() {
-            return false;
+            return Ok(false);
         }
 
-        inner_right
-            .into_iter()
-            .zip(inner_right)
-            .all(|((lk, lv), (rk, rv))| lk == rk && eq(lv.clone(), rv.clone()))
+        for (k, lv) in inner_left {
+            match inner_right.get(k) {
+                Some(rv) if eq(lv.clone(), rv.clone())? => {}
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
     } else {
-        return left == right;
+        return Ok(left == right);
     }
 }
 
-fn less_than(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "< should have 2 params");
-    let left = params.pop_front().unwrap();
-    let right = params.pop_front().unwrap();
-    Ok(new_mal!(Bool(left.to_number() < right.to_number())))
+fn less_than(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (left, right) = two_nums("<", params)?;
+    Ok(new_mal!(Bool(left < right)))
 }
 
 fn less_than_equal(
-    mut params: LinkedList<MalType>,
+    params: LinkedList<MalType>,
     _c_env: Option<ClosureEnv>,
 ) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "<= should have 2 params");
-    let left = params.pop_front().unwrap();
-    let right = params.pop_front().unwrap();
-    Ok(new_mal!(Bool(left.to_number() <= right.to_number())))
+    let (left, right) = two_nums("<=", params)?;
+    Ok(new_mal!(Bool(left <= right)))
 }
 
 fn greater_than(
-    mut params: LinkedList<MalType>,
+    params: LinkedList<MalType>,
     _c_env: Option<ClosureEnv>,
 ) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "> should have 2 params");
-    let left = params.pop_front().unwrap();
-    let right = params.pop_front().unwrap();
-    Ok(new_mal!(Bool(left.to_number() > right.to_number())))
+    let (left, right) = two_nums(">", params)?;
+    Ok(new_mal!(Bool(left > right)))
 }
 
 fn greater_than_equal(
-    mut params: LinkedList<MalType>,
+    params: LinkedList<MalType>,
     _c_env: Option<ClosureEnv>,
 ) -> Fallible<MalType> {
-    ensure!(params.len() == 2, ">= should have 2 params");
-    let left = params.pop_front().unwrap();
-    let right = params.pop_front().unwrap();
-    Ok(new_mal!(Bool(left.to_number() >= right.to_number())))
+    let (left, right) = two_nums(">=", params)?;
+    Ok(new_mal!(Bool(left >= right)))
 }
 
 fn read_string(
     mut params: LinkedList<MalType>,
     _c_env: Option<ClosureEnv>,
 ) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "read_string should have 1 params");
+    ensure_arity!(params.len() == 1, "read_string should have 1 params");
     let p = params.pop_front().unwrap();
     let s = p.to_string();
     read_str(&s)
 }
 
 fn slurp(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "slurp should have 1 params");
+    ensure_arity!(params.len() == 1, "slurp should have 1 params");
     let p = params.pop_front().unwrap();
     let file_name = p.to_string();
     let mut file = File::open(&file_name)?;
@@ -199,22 +247,30 @@ fn slurp(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallibl
     Ok(new_mal!(String(content)))
 }
 
+fn load_file(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "load-file should have 1 params");
+    let f = params.pop_front().unwrap();
+    ensure_type!(f.is_string(), "load-file's param should be string");
+    module::load_file(&module::resolve_path(&f.to_string()))
+}
+
 fn atom(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "atom should have 1 params");
-    Ok(new_mal!(Atom(RefCell::new(
-        params.pop_front().unwrap(),
-    ))))
+    ensure_arity!(params.len() == 1, "atom should have 1 params");
+    Ok(gc::new_atom(params.pop_front().unwrap()))
 }
 
 fn is_atom(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "is_atom should have 1 params");
+    ensure_arity!(params.len() == 1, "is_atom should have 1 params");
     Ok(new_mal!(Bool(params.pop_front().unwrap().is_atom())))
 }
 
 fn deref(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "deref should have 1 params");
+    ensure_arity!(params.len() == 1, "deref should have 1 params");
     let p = params.pop_front().unwrap();
-    ensure!(
+    if let InnerMalType::Var(ref var) = *p {
+        return Ok(var.get());
+    }
+    ensure_type!(
         p.is_atom(),
         "deref should have 1 param which is of type atom"
     );
@@ -222,10 +278,10 @@ fn deref(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallibl
 }
 
 fn reset(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "reset should have 2 params");
+    ensure_arity!(params.len() == 2, "reset should have 2 params");
     let atom = params.pop_front().unwrap();
     let new_value = params.pop_front().unwrap();
-    ensure!(atom.is_atom(), "reset's first param should be of type atom");
+    ensure_type!(atom.is_atom(), "reset's first param should be of type atom");
     if let InnerMalType::Atom(a) = &*atom {
         let _ = a.replace(new_value.clone());
         return Ok(new_value);
@@ -235,159 +291,596 @@ fn reset(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallibl
 }
 
 fn cons(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "cons should have 2 params");
+    ensure_arity!(params.len() == 2, "cons should have 2 params");
     let first = params.pop_front().unwrap();
     let list = params.pop_front().unwrap();
-    ensure!(list.is_collection(), "cons' second param should be list");
+    if list.is_lazy_seq() {
+        return Ok(cell(Some((first, list))));
+    }
+    ensure_type!(list.is_collection(), "cons' second param should be list");
     let mut l = list.to_items();
     l.push_front(first);
     Ok(new_mal!(List(l, new_mal!(Nil))))
 }
 
 fn concat(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(
-        params.iter().all(|el| el.is_collection()),
+    ensure_type!(
+        params.iter().all(|el| el.is_sequential() || el.is_nil()),
         "concat's all params should be list"
     );
+    // Already realised collections are joined eagerly; laziness only
+    // matters once a lazy seq is involved.
+    if params.iter().any(|el| el.is_lazy_seq()) {
+        return lazy_concat(params.into_iter().collect());
+    }
     let mut l = LinkedList::new();
-    for mal in params {
+    for mal in params.iter().filter(|el| !el.is_nil()) {
         l.extend(&mut mal.to_items_ref().iter().cloned())
     }
 
     Ok(new_mal!(List(l, new_mal!(Nil))))
 }
 
+fn lazy_concat(mut colls: Vec<MalType>) -> Fallible<MalType> {
+    if colls.is_empty() {
+        return Ok(new_mal!(List(LinkedList::new(), new_mal!(Nil))));
+    }
+    let head = lazy_source(&colls.remove(0))?;
+    Ok(new_mal!(LazySeq(
+        LazySeq::step_on(vec![head], move |mut steps| match steps.remove(0) {
+            Some((first, rest)) => {
+                let mut colls = colls.clone();
+                colls.insert(0, rest);
+                Ok(cell(Some((first, lazy_concat(colls)?))))
+            }
+            None => lazy_concat(colls.clone()),
+        }),
+        new_mal!(Nil)
+    )))
+}
+
 fn nth(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "nth should have 2 params");
+    ensure_arity!(params.len() == 2, "nth should have 2 params");
     let list = params.pop_front().unwrap();
     let index_mal = params.pop_front().unwrap();
-    ensure!(index_mal.is_num(), "nth's first param should be num");
+    ensure_type!(index_mal.is_num(), "nth's first param should be num");
     let float_index = index_mal.to_number();
-    ensure!(
+    ensure_type!(
         float_index.trunc() == float_index,
         "nth index should be int"
     );
     let index = float_index.trunc() as usize;
-    ensure!(list.is_collection(), "nth's second param should be list");
+    if list.is_lazy_seq() {
+        let item = seq_iter(&list)?.nth(index);
+        ensure!(item.is_some(), "nth no enough items in list");
+        return item.unwrap();
+    }
+    ensure_type!(list.is_collection(), "nth's second param should be list");
     let l = list.to_items_ref();
     ensure!(l.len() > index, "nth no enough items in list");
     Ok(l.iter().nth(index).unwrap().clone())
 }
 
 fn first(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "first should have 1 params");
+    ensure_arity!(params.len() == 1, "first should have 1 params");
     let list = params.pop_front().unwrap();
     if list.is_nil() || list.is_empty_collection() {
         return Ok(new_mal!(Nil));
     }
-    ensure!(list.is_collection(), "first's param should be list or nil");
+    if list.is_lazy_seq() {
+        return Ok(seq_step(&list)?.map_or_else(|| new_mal!(Nil), |(first, _)| first));
+    }
+    ensure_type!(list.is_collection(), "first's param should be list or nil");
     let l = list.to_items_ref();
     Ok(l.front().unwrap().clone())
 }
 
 fn rest(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "rest should have 1 params");
+    ensure_arity!(params.len() == 1, "rest should have 1 params");
     let list = params.pop_front().unwrap();
     if list.is_nil() || list.is_empty_collection() {
         return Ok(new_mal!(List(LinkedList::new(), new_mal!(Nil))));
     }
-    ensure!(list.is_collection(), "rest's param should be list or nil");
+    if list.is_lazy_seq() {
+        return Ok(match seq_step(&list)? {
+            Some((_, ref rest)) if rest.is_lazy_seq() => rest.clone(),
+            _ => new_mal!(List(LinkedList::new(), new_mal!(Nil))),
+        });
+    }
+    ensure_type!(list.is_collection(), "rest's param should be list or nil");
     let mut l = list.to_items();
     l.pop_front().unwrap();
     Ok(new_mal!(List(l, new_mal!(Nil))))
 }
 
 fn throw(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "throw should have 1 params");
+    ensure_arity!(params.len() == 1, "throw should have 1 params");
+    let e = params.pop_front().unwrap();
+    let printed = try_pr_str(&e, true)?;
+    Err(MalExceptionError::new(e, printed).into())
+}
+
+fn ex_info_fn(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(
+        params.len() == 2 || params.len() == 3,
+        "ex-info should have 2 or 3 params"
+    );
+    let message = params.pop_front().unwrap();
+    ensure_type!(message.is_string(), "ex-info's first param should be string");
+    let data = params.pop_front().unwrap();
+    ensure_type!(data.is_hashmap(), "ex-info's second param should be map");
+    let cause = params.pop_front().unwrap_or_else(|| new_mal!(Nil));
+    Ok(ex_info(message, data, cause))
+}
+
+fn ex_data(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "ex-data should have 1 params");
+    let e = params.pop_front().unwrap();
+    Ok(ex_info_field(&e, "data").unwrap_or_else(|| new_mal!(Nil)))
+}
+
+fn ex_message(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "ex-message should have 1 params");
+    let e = params.pop_front().unwrap();
+    Ok(ex_info_field(&e, "message").unwrap_or_else(|| new_mal!(Nil)))
+}
+
+fn ex_cause(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "ex-cause should have 1 params");
+    let e = params.pop_front().unwrap();
+    Ok(ex_info_field(&e, "cause").unwrap_or_else(|| new_mal!(Nil)))
+}
+
+fn ex_stack(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "ex-stack should have 1 params");
     let e = params.pop_front().unwrap();
-    Err(MalExceptionError(pr_str(&e, true)).into())
+    let frames = trace::stack(&e)
+        .into_iter()
+        .map(|frame| {
+            let (file, line, column) = match frame.position {
+                Some(p) => (
+                    new_mal!(String(p.file)),
+                    new_mal!(Num(p.line as f64)),
+                    new_mal!(Num(p.column as f64)),
+                ),
+                None => (new_mal!(Nil), new_mal!(Nil), new_mal!(Nil)),
+            };
+            let map = vec![
+                ("fn", new_mal!(String(frame.name))),
+                ("form", new_mal!(String(frame.form))),
+                ("file", file),
+                ("line", line),
+                ("column", column),
+            ].into_iter()
+            .map(|(k, v)| (HashKey::Keyword(Sym::new(&format!(":{}", k))), v))
+            .collect();
+            new_mal!(Hashmap(map, new_mal!(Nil)))
+        })
+        .collect();
+    Ok(new_mal!(Vec(frames, new_mal!(Nil))))
 }
 
-fn apply(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() >= 2, "apply should have at least 2 params");
+/// The func `(apply f x & args)` calls and its params, so that `eval` and
+/// the VM can call it in place of `apply`.
+pub fn apply_params(
+    mut params: LinkedList<MalType>,
+) -> Fallible<(MalType, LinkedList<MalType>)> {
+    ensure_arity!(params.len() >= 2, "apply should have at least 2 params");
     let func = params.pop_front().unwrap();
-    ensure!(func.is_closure(), "apply's first param should be func");
+    ensure_type!(func.is_closure(), "apply's first param should be func");
     let list = params.pop_back().unwrap();
-    ensure!(list.is_collection(), "apply's last param should be list");
-    params.extend(list.to_items_ref().iter().cloned());
+    ensure_type!(
+        list.is_sequential() || list.is_nil(),
+        "apply's last param should be list"
+    );
+    params.extend(seq_to_list(&list)?);
+    Ok((func, params))
+}
+
+fn apply(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (func, params) = apply_params(params)?;
     func.to_closure().call(params)
 }
 
+fn trampoline(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(!params.is_empty(), "trampoline should have at least 1 params");
+    let func = params.pop_front().unwrap();
+    ensure_type!(func.is_closure(), "trampoline's first param should be func");
+    let mut value = func.to_closure().call(params)?;
+    while value.is_closure() {
+        value = value.to_closure().call(LinkedList::new())?;
+    }
+    Ok(value)
+}
+
+pub fn is_apply(func: ClosureFunc) -> bool {
+    func as usize == (apply as ClosureFunc) as usize
+}
+
 fn map(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() >= 2, "map should have 2 params");
+    ensure_arity!(params.len() >= 2, "map should have at least 2 params");
     let func = params.pop_front().unwrap();
-    ensure!(func.is_closure(), "map's first param should be func");
-    let list = params.pop_front().unwrap();
-    ensure!(list.is_collection(), "map's last param should be list");
-    let f = func.to_closure();
-    Ok(new_mal!(List(
-        list.to_items_ref()
-            .into_iter()
-            .map(|mal| f.call(linked_list![mal.clone()]))
-            .collect::<Fallible<LinkedList<MalType>>>()?,
+    ensure_type!(func.is_closure(), "map's first param should be func");
+    let colls = params
+        .iter()
+        .map(lazy_source)
+        .collect::<Fallible<Vec<MalType>>>()
+        .map_err(|_| TypeError("map's colls should be seqs".to_string()))?;
+    Ok(lazy_map(func.to_closure(), colls))
+}
+
+fn lazy_map(f: Closure, colls: Vec<MalType>) -> MalType {
+    new_mal!(LazySeq(
+        LazySeq::step_on(colls, move |steps| {
+            let mut args = LinkedList::new();
+            let mut rests = Vec::new();
+            for step in steps {
+                match step {
+                    Some((first, rest)) => {
+                        args.push_back(first);
+                        rests.push(rest);
+                    }
+                    None => return Ok(new_mal!(Nil)),
+                }
+            }
+            Ok(cell(Some((f.call(args)?, lazy_map(f.clone(), rests)))))
+        }),
         new_mal!(Nil)
-    )))
+    ))
+}
+
+fn filter(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "filter should have 2 params");
+    let func = params.pop_front().unwrap();
+    ensure_type!(func.is_closure(), "filter's first param should be func");
+    let coll = lazy_source(&params.pop_front().unwrap())?;
+    Ok(lazy_filter(func.to_closure(), coll, true))
+}
+
+fn remove(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "remove should have 2 params");
+    let func = params.pop_front().unwrap();
+    ensure_type!(func.is_closure(), "remove's first param should be func");
+    let coll = lazy_source(&params.pop_front().unwrap())?;
+    Ok(lazy_filter(func.to_closure(), coll, false))
+}
+
+fn lazy_filter(pred: Closure, coll: MalType, keep: bool) -> MalType {
+    new_mal!(LazySeq(
+        LazySeq::step_on(vec![coll], move |mut steps| {
+            Ok(match steps.remove(0) {
+                Some((first, rest)) => {
+                    if is_truthy(&pred.call(linked_list![first.clone()])?) == keep {
+                        cell(Some((first, lazy_filter(pred.clone(), rest, keep))))
+                    } else {
+                        lazy_filter(pred.clone(), rest, keep)
+                    }
+                }
+                None => new_mal!(Nil),
+            })
+        }),
+        new_mal!(Nil)
+    ))
+}
+
+fn take(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "take should have 2 params");
+    let n = params.pop_front().unwrap();
+    ensure_type!(n.is_num(), "take's first param should be num");
+    let coll = lazy_source(&params.pop_front().unwrap())?;
+    Ok(lazy_take(n.to_number(), coll))
+}
+
+fn lazy_take(n: f64, coll: MalType) -> MalType {
+    if n <= 0f64 {
+        return new_mal!(List(LinkedList::new(), new_mal!(Nil)));
+    }
+    new_mal!(LazySeq(
+        LazySeq::step_on(vec![coll], move |mut steps| {
+            Ok(match steps.remove(0) {
+                Some((first, rest)) => cell(Some((first, lazy_take(n - 1f64, rest)))),
+                None => new_mal!(Nil),
+            })
+        }),
+        new_mal!(Nil)
+    ))
+}
+
+fn drop(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "drop should have 2 params");
+    let n = params.pop_front().unwrap();
+    ensure_type!(n.is_num(), "drop's first param should be num");
+    let n = n.to_number();
+    let coll = params.pop_front().unwrap();
+    ensure_type!(
+        coll.is_sequential() || coll.is_nil(),
+        "drop's second param should be seq"
+    );
+    Ok(lazy_drop(n, lazy_source(&coll)?))
+}
+
+/// Drops one item at a time, each a step of its own, so that realising a
+/// drop of a drop of ... does not recurse.
+fn lazy_drop(n: f64, coll: MalType) -> MalType {
+    new_mal!(LazySeq(
+        LazySeq::step_on(vec![coll], move |mut steps| {
+            Ok(match steps.remove(0) {
+                Some((_, rest)) if n > 0f64 => lazy_drop(n - 1f64, rest),
+                step => cell(step),
+            })
+        }),
+        new_mal!(Nil)
+    ))
+}
+
+fn take_while(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "take-while should have 2 params");
+    let func = params.pop_front().unwrap();
+    ensure_type!(func.is_closure(), "take-while's first param should be func");
+    let coll = lazy_source(&params.pop_front().unwrap())?;
+    Ok(lazy_take_while(func.to_closure(), coll))
+}
+
+fn lazy_take_while(pred: Closure, coll: MalType) -> MalType {
+    new_mal!(LazySeq(
+        LazySeq::step_on(vec![coll], move |mut steps| {
+            Ok(match steps.remove(0) {
+                Some((ref first, ref rest)) if is_truthy(&pred.call(linked_list![first.clone()])?) => {
+                    cell(Some((first.clone(), lazy_take_while(pred.clone(), rest.clone()))))
+                }
+                _ => new_mal!(Nil),
+            })
+        }),
+        new_mal!(Nil)
+    ))
+}
+
+fn drop_while(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "drop-while should have 2 params");
+    let func = params.pop_front().unwrap();
+    ensure_type!(func.is_closure(), "drop-while's first param should be func");
+    let pred = func.to_closure();
+    let coll = params.pop_front().unwrap();
+    ensure_type!(
+        coll.is_sequential() || coll.is_nil(),
+        "drop-while's second param should be seq"
+    );
+    Ok(lazy_drop_while(pred, lazy_source(&coll)?))
+}
+
+fn lazy_drop_while(pred: Closure, coll: MalType) -> MalType {
+    new_mal!(LazySeq(
+        LazySeq::step_on(vec![coll], move |mut steps| {
+            Ok(match steps.remove(0) {
+                Some((ref first, ref rest)) if is_truthy(&pred.call(linked_list![first.clone()])?) => {
+                    lazy_drop_while(pred.clone(), rest.clone())
+                }
+                step => cell(step),
+            })
+        }),
+        new_mal!(Nil)
+    ))
+}
+
+fn range(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() <= 3, "range should have at most 3 params");
+    ensure_type!(
+        params.iter().all(|p| p.is_num()),
+        "range's params should be num"
+    );
+    let nums: Vec<f64> = params.iter().map(|p| p.to_number()).collect();
+    let (start, end, step) = match nums.len() {
+        0 => (0f64, None, 1f64),
+        1 => (0f64, Some(nums[0]), 1f64),
+        2 => (nums[0], Some(nums[1]), 1f64),
+        _ => (nums[0], Some(nums[1]), nums[2]),
+    };
+    Ok(lazy_range(start, end, step))
+}
+
+fn lazy_range(start: f64, end: Option<f64>, step: f64) -> MalType {
+    new_mal!(LazySeq(
+        LazySeq::new(move || {
+            let done = match end {
+                Some(end) => (step > 0f64 && start >= end) || (step <= 0f64 && start <= end),
+                None => false,
+            };
+            Ok(if done {
+                new_mal!(Nil)
+            } else {
+                cell(Some((new_mal!(Num(start)), lazy_range(start + step, end, step))))
+            })
+        }),
+        new_mal!(Nil)
+    ))
+}
+
+fn iterate(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "iterate should have 2 params");
+    let func = params.pop_front().unwrap();
+    ensure_type!(func.is_closure(), "iterate's first param should be func");
+    let x = params.pop_front().unwrap();
+    Ok(cell(Some((x.clone(), lazy_iterate(func.to_closure(), x)))))
+}
+
+fn lazy_iterate(f: Closure, x: MalType) -> MalType {
+    new_mal!(LazySeq(
+        LazySeq::new(move || {
+            let next = f.call(linked_list![x.clone()])?;
+            Ok(cell(Some((next.clone(), lazy_iterate(f.clone(), next)))))
+        }),
+        new_mal!(Nil)
+    ))
+}
+
+fn repeat(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(
+        params.len() == 1 || params.len() == 2,
+        "repeat should have 1 or 2 params"
+    );
+    let x = params.pop_back().unwrap();
+    let infinite = lazy_repeat(x);
+    match params.pop_front() {
+        Some(n) => {
+            ensure_type!(n.is_num(), "repeat's first param should be num");
+            Ok(lazy_take(n.to_number(), infinite))
+        }
+        None => Ok(infinite),
+    }
+}
+
+fn lazy_repeat(x: MalType) -> MalType {
+    new_mal!(LazySeq(
+        LazySeq::new(move || Ok(cell(Some((x.clone(), lazy_repeat(x.clone())))))),
+        new_mal!(Nil)
+    ))
+}
+
+fn cycle(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "cycle should have 1 params");
+    let coll = params.pop_front().unwrap();
+    ensure_type!(
+        coll.is_sequential() || coll.is_nil(),
+        "cycle's param should be seq"
+    );
+    let coll = lazy_source(&coll)?;
+    Ok(lazy_cycle(coll.clone(), coll))
+}
+
+fn lazy_cycle(coll: MalType, current: MalType) -> MalType {
+    new_mal!(LazySeq(
+        LazySeq::step_on(vec![current, coll.clone()], move |mut steps| {
+            let current = steps.remove(0);
+            Ok(match current.or_else(|| steps.remove(0)) {
+                Some((first, rest)) => cell(Some((first, lazy_cycle(coll.clone(), rest)))),
+                None => new_mal!(Nil),
+            })
+        }),
+        new_mal!(Nil)
+    ))
+}
+
+fn doall(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "doall should have 1 params");
+    let coll = params.pop_front().unwrap();
+    for item in seq_iter(&coll)? {
+        let _ = item?;
+    }
+    Ok(coll)
+}
+
+fn dorun(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "dorun should have 1 params");
+    for item in seq_iter(&params.pop_front().unwrap())? {
+        let _ = item?;
+    }
+    Ok(new_mal!(Nil))
+}
+
+fn is_realized(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "realized? should have 1 params");
+    Ok(new_mal!(Bool(match *params.pop_front().unwrap() {
+        InnerMalType::LazySeq(ref seq, ..) => seq.is_realized(),
+        _ => true,
+    })))
+}
+
+fn is_lazy_seq(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "lazy-seq? should have 1 params");
+    Ok(new_mal!(Bool(params.pop_front().unwrap().is_lazy_seq())))
+}
+
+fn print_length(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "set-print-length! should have 1 params");
+    let length = params.pop_front().unwrap();
+    if length.is_nil() {
+        set_print_length(None);
+    } else {
+        ensure_type!(length.is_num(), "set-print-length!'s param should be num or nil");
+        set_print_length(Some(length.to_number().max(0f64) as usize));
+    }
+    Ok(length)
+}
+
+fn set_max_depth(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "set-max-depth! should have 1 params");
+    let depth = params.pop_front().unwrap();
+    if depth.is_nil() {
+        stack::set_max_depth(&runtime::current(), None);
+    } else {
+        ensure_type!(depth.is_num(), "set-max-depth!'s param should be num or nil");
+        let max = depth.to_number().max(0f64) as usize;
+        stack::set_max_depth(&runtime::current(), Some(max));
+    }
+    Ok(depth)
+}
+
+pub fn is_truthy(mal: &MalType) -> bool {
+    match **mal {
+        InnerMalType::Nil | InnerMalType::Bool(false) => false,
+        _ => true,
+    }
 }
 
 fn is_nil(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "nil? should have 1 params");
+    ensure_arity!(params.len() == 1, "nil? should have 1 params");
     Ok(new_mal!(Bool(*params.pop_front().unwrap() == InnerMalType::Nil)))
 }
 
 fn is_true(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "true? should have 1 params");
+    ensure_arity!(params.len() == 1, "true? should have 1 params");
     Ok(new_mal!(Bool(
         *params.pop_front().unwrap() == InnerMalType::Bool(true)
     )))
 }
 
 fn is_false(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "false? should have 1 params");
+    ensure_arity!(params.len() == 1, "false? should have 1 params");
     Ok(new_mal!(Bool(
         *params.pop_front().unwrap() == InnerMalType::Bool(false)
     )))
 }
 
 fn is_symbol(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "symbol? should have 1 params");
+    ensure_arity!(params.len() == 1, "symbol? should have 1 params");
     Ok(new_mal!(Bool(params.pop_front().unwrap().is_symbol())))
 }
 
 fn is_number(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "number? should have 1 params");
+    ensure_arity!(params.len() == 1, "number? should have 1 params");
     Ok(new_mal!(Bool(params.pop_front().unwrap().is_num())))
 }
 
 fn is_fn(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "fn? should have 1 params");
+    ensure_arity!(params.len() == 1, "fn? should have 1 params");
     let p = params.pop_front().unwrap();
     Ok(new_mal!(Bool(p.is_closure() && !p.is_macro_closure())))
 }
 
 fn is_macro(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "macro? should have 1 params");
+    ensure_arity!(params.len() == 1, "macro? should have 1 params");
     Ok(new_mal!(Bool(
         params.pop_front().unwrap().is_macro_closure()
     )))
 }
 
 fn symbol(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "symbol should have 1 param");
+    ensure_arity!(params.len() == 1, "symbol should have 1 param");
     let s = params.pop_front().unwrap().to_string();
-    Ok(new_mal!(Symbol(s)))
+    Ok(new_mal!(Symbol(Sym::new(&s))))
 }
 
 fn keyword(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "keyword should have 1 param");
+    ensure_arity!(params.len() == 1, "keyword should have 1 param");
     let s = params.pop_front().unwrap().to_string();
-    Ok(new_mal!(Keyword(format!(":{}", s))))
+    Ok(new_mal!(Keyword(Sym::new(&format!(":{}", s)))))
 }
 
 fn is_keyword(
     mut params: LinkedList<MalType>,
     _c_env: Option<ClosureEnv>,
 ) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "is_keyword should have 1 params");
+    ensure_arity!(params.len() == 1, "is_keyword should have 1 params");
     Ok(new_mal!(Bool(params.pop_front().unwrap().is_keyword())))
 }
 
@@ -396,12 +889,12 @@ fn vector(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<M
 }
 
 fn is_vector(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "is_vector should have 1 params");
+    ensure_arity!(params.len() == 1, "is_vector should have 1 params");
     Ok(new_mal!(Bool(params.pop_front().unwrap().is_vec())))
 }
 
 fn hashmap(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(
+    ensure_arity!(
         params.len() % 2 == 0,
         "hashmap should have even number of params"
     );
@@ -414,46 +907,70 @@ fn hashmap(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Falli
 }
 
 fn is_map(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "is_map should have 1 params");
+    ensure_arity!(params.len() == 1, "is_map should have 1 params");
     Ok(new_mal!(Bool(params.pop_front().unwrap().is_hashmap())))
 }
 
 fn is_string(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "string? should have 1 params");
+    ensure_arity!(params.len() == 1, "string? should have 1 params");
     Ok(new_mal!(Bool(params.pop_front().unwrap().is_string())))
 }
 
 fn assoc(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(
+    ensure_arity!(
         params.len() > 0 && params.len() % 2 == 1,
         "assoc should have odd params"
     );
-    let mut map = params.pop_front().unwrap().to_hashmap();
+    let source = params.pop_front().unwrap();
+    let mut map = source.to_hashmap();
     while let Some(key) = params.pop_front() {
         let value = params.pop_front().expect("get value");
         map.insert(key.to_hash_key(), value);
     }
-    Ok(new_mal!(Hashmap(map, new_mal!(Nil))))
+    Ok(match *source {
+        InnerMalType::Record(ref rtype, _, ref metadata) => {
+            new_mal!(Record(rtype.clone(), map, metadata.clone()))
+        }
+        _ => new_mal!(Hashmap(map, new_mal!(Nil))),
+    })
 }
 
 fn dissoc(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    let mut map = params.pop_front().unwrap().to_hashmap();
-    let keys = params;
-    for k in keys {
-        map.remove(&k.to_hash_key());
+    let source = params.pop_front().unwrap();
+    let mut map = source.to_hashmap();
+    let keys: Vec<HashKey> = params.iter().map(|k| k.to_hash_key()).collect();
+    for k in &keys {
+        map.remove(k);
     }
-    Ok(new_mal!(Hashmap(map, new_mal!(Nil))))
+    Ok(match *source {
+        // a record without one of its fields is just a map
+        InnerMalType::Record(ref rtype, _, ref metadata)
+            if !keys.iter().any(|k| rtype.has_field(k)) =>
+        {
+            new_mal!(Record(rtype.clone(), map, metadata.clone()))
+        }
+        _ => new_mal!(Hashmap(map, new_mal!(Nil))),
+    })
 }
 
 fn get(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(
+        params.len() == 2 || params.len() == 3,
+        "get should have 2 or 3 params"
+    );
     let el = params.pop_front().unwrap();
+    let key = params.pop_front().unwrap();
+    let not_found = params.pop_front().unwrap_or_else(|| new_mal!(Nil));
     if el.is_nil() {
-        return Ok(new_mal!(Nil));
+        return Ok(not_found);
     }
-    let mut map = el.to_hashmap();
-    let key = params.pop_front().unwrap();
+    ensure_type!(el.is_hashmap(), "get's first param should be map");
 
-    Ok(map.remove(&key.to_hash_key()).unwrap_or(new_mal!(Nil)))
+    Ok(el
+        .to_hashmap_ref()
+        .get(&key.to_hash_key())
+        .cloned()
+        .unwrap_or(not_found))
 }
 
 fn contains(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
@@ -483,44 +1000,45 @@ fn is_sequential(
     _c_env: Option<ClosureEnv>,
 ) -> Fallible<MalType> {
     let l = params.pop_front().unwrap();
-    Ok(new_mal!(Bool(l.is_collection())))
+    Ok(new_mal!(Bool(l.is_sequential())))
 }
 
 fn readline(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "readline should have 1 params");
+    ensure_arity!(params.len() == 1, "readline should have 1 params");
     let prompt = params.pop_front().unwrap();
-    print!("{}", prompt.to_string());
-    stdout().flush()?;
-    let mut buf = String::new();
-    let _ = stdin().read_line(&mut buf)?;
-    if buf == "" {
-        return Ok(new_mal!(Nil));
-    }
-    Ok(new_mal!(String(buf.trim_right().to_string())))
+    console::print(&prompt.to_string())?;
+    Ok(match console::read_line()? {
+        Some(line) => new_mal!(String(line)),
+        None => new_mal!(Nil),
+    })
 }
 
 fn meta(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "meta should have 1 params");
+    ensure_arity!(params.len() == 1, "meta should have 1 params");
     let s = params.pop_front().unwrap();
     Ok(s.get_metadata())
 }
 
 fn with_meta(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 2, "with_meta should have 2 params");
+    ensure_arity!(params.len() == 2, "with_meta should have 2 params");
     let source = params.pop_front().unwrap();
-    let source = Rc::try_unwrap(source).unwrap_or_else(|source|(*source).clone());
+    let mut source = Rc::try_unwrap(source).unwrap_or_else(|source|(*source).clone());
     let metadata = params.pop_front().unwrap();
     Ok(match source {
-        InnerMalType::List(l, ..) => new_mal!(List(l, metadata)),
-        InnerMalType::Vec(l, ..) => new_mal!(Vec(l, metadata)),
-        InnerMalType::Hashmap(l, ..) => new_mal!(Hashmap(l, metadata)),
-        InnerMalType::Closure(l, ..) => new_mal!(Closure(l, metadata)),
+        InnerMalType::List(ref mut l, ..) => new_mal!(List(mem::take(l), metadata)),
+        InnerMalType::Vec(ref mut l, ..) => new_mal!(Vec(mem::take(l), metadata)),
+        InnerMalType::LazySeq(ref l, ..) => new_mal!(LazySeq(l.clone(), metadata)),
+        InnerMalType::Hashmap(ref mut l, ..) => new_mal!(Hashmap(mem::take(l), metadata)),
+        InnerMalType::Record(ref t, ref mut l, ..) => {
+            new_mal!(Record(t.clone(), mem::take(l), metadata))
+        }
+        InnerMalType::Closure(ref l, ..) => new_mal!(Closure(l.clone(), metadata)),
         _ => unreachable!(),
     })
 }
 
 fn time_ms(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 0, "time_ms should have 0 params");
+    ensure_arity!(params.len() == 0, "time_ms should have 0 params");
     let t = time::get_time();
     Ok(new_mal!(Num(
         t.sec as f64 * 1000.0 + (t.nsec / 1000 / 1000) as f64
@@ -528,29 +1046,38 @@ fn time_ms(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<
 }
 
 fn conj(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    let collection = Rc::try_unwrap(params.pop_front().unwrap()).unwrap_or_else(|s| (*s).clone());
+    let mut collection =
+        Rc::try_unwrap(params.pop_front().unwrap()).unwrap_or_else(|s| (*s).clone());
     Ok(match collection {
-        InnerMalType::Vec(l, meta) => {
-            let mut l = l.clone();
+        InnerMalType::Vec(ref mut l, ref meta) => {
+            let mut l = mem::take(l);
             l.extend(params);
             new_mal!(Vec(l, meta.clone()))
         }
-        InnerMalType::List(mut l, meta) => {
+        InnerMalType::List(ref mut l, ref meta) => {
+            let mut l = mem::take(l);
             for i in params {
                 l.push_front(i);
             }
             new_mal!(List(l, meta.clone()))
         }
+        InnerMalType::LazySeq(ref l, ref meta) => {
+            let mut seq = new_mal!(LazySeq(l.clone(), meta.clone()));
+            for i in params {
+                seq = cell(Some((i, seq)));
+            }
+            seq
+        }
         _ => unreachable!(),
     })
 }
 
 fn swap(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() >= 2, "swap! should have more than 2 params");
+    ensure_arity!(params.len() >= 2, "swap! should have more than 2 params");
     let atom = params.pop_front().unwrap();
     let func = params.pop_front().unwrap();
-    ensure!(atom.is_atom(), "swap!'s first param should be of type atom");
-    ensure!(func.is_closure(), "swap!'s second param should be a func");
+    ensure_type!(atom.is_atom(), "swap!'s first param should be of type atom");
+    ensure_type!(func.is_closure(), "swap!'s second param should be a func");
 
     let old_mal = atom.to_atom();
     params.push_front(old_mal);
@@ -559,25 +1086,32 @@ fn swap(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible
 }
 
 fn seq(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
-    ensure!(params.len() == 1, "seq should have 1 params");
-    let p = Rc::try_unwrap(params.pop_front().unwrap()).unwrap_or_else(|s| (*s).clone());
+    ensure_arity!(params.len() == 1, "seq should have 1 params");
+    let mut p = Rc::try_unwrap(params.pop_front().unwrap()).unwrap_or_else(|s| (*s).clone());
     Ok(match p {
-        InnerMalType::List(l, m) => {
+        InnerMalType::List(ref mut l, ref m) => {
             if !l.is_empty() {
-                new_mal!(List(l, m))
+                new_mal!(List(mem::take(l), m.clone()))
             } else {
                 new_mal!(Nil)
             }
         }
-        InnerMalType::Vec(l, m) => {
+        InnerMalType::Vec(ref mut l, ref m) => {
             if !l.is_empty() {
-                new_mal!(List(l, m))
+                new_mal!(List(mem::take(l), m.clone()))
             } else {
                 new_mal!(Nil)
             }
         }
+        InnerMalType::LazySeq(ref l, ref m) => {
+            let seq = new_mal!(LazySeq(l.clone(), m.clone()));
+            match seq_step(&seq)? {
+                Some(_) => seq,
+                None => new_mal!(Nil),
+            }
+        }
         InnerMalType::Nil => new_mal!(Nil),
-        InnerMalType::String(s) => {
+        InnerMalType::String(ref s) => {
             if !s.is_empty() {
                 new_mal!(List(
                     s.chars().map(|c| new_mal!(String(c.to_string()))).collect(),
@@ -591,74 +1125,778 @@ fn seq(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<
     })
 }
 
+fn re_pattern(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "re-pattern should have 1 params");
+    let p = params.pop_front().unwrap();
+    if p.is_regex() {
+        return Ok(p);
+    }
+    ensure_type!(p.is_string(), "re-pattern's param should be string");
+    Ok(new_mal!(Regex(Pattern::new(&p.to_string())?)))
+}
+
+fn is_regex(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "regex? should have 1 params");
+    Ok(new_mal!(Bool(params.pop_front().unwrap().is_regex())))
+}
+
+/// Accepts either a compiled pattern or a string to compile.
+fn to_regex(mal: &MalType) -> Fallible<Regex> {
+    if mal.is_regex() {
+        return Ok(mal.to_regex());
+    }
+    ensure_type!(mal.is_string(), "{:?} is not a regex", mal);
+    Ok(Regex::new(&mal.to_string())?)
+}
+
+/// Accepts either a compiled pattern or a string to compile.
+fn to_pattern(mal: &MalType) -> Fallible<Pattern> {
+    if mal.is_regex() {
+        return Ok(mal.to_pattern().clone());
+    }
+    ensure_type!(mal.is_string(), "{:?} is not a regex", mal);
+    Pattern::new(&mal.to_string())
+}
+
+/// A match of `re`: a map of keywords of the names of its groups when it
+/// has named groups, else the whole match when it has no groups, else a
+/// vector of the whole match followed by each group. Groups that did not
+/// match are nil.
+fn captures_to_mal(re: &Regex, caps: &Captures) -> MalType {
+    fn text(m: Option<Match>) -> MalType {
+        m.map_or_else(|| new_mal!(Nil), |m| new_mal!(String(m.as_str().to_string())))
+    }
+    if re.capture_names().any(|name| name.is_some()) {
+        let map = re
+            .capture_names()
+            .flatten()
+            .map(|name| (HashKey::Keyword(Sym::new(&format!(":{}", name))), text(caps.name(name))))
+            .collect();
+        return new_mal!(Hashmap(map, new_mal!(Nil)));
+    }
+    if caps.len() == 1 {
+        return text(caps.get(0));
+    }
+    new_mal!(Vec(caps.iter().map(text).collect(), new_mal!(Nil)))
+}
+
+fn re_args(params: &mut LinkedList<MalType>, name: &str) -> Fallible<(Pattern, String)> {
+    ensure_arity!(params.len() == 2, "{} should have 2 params", name);
+    let pattern = to_pattern(&params.pop_front().unwrap())?;
+    let s = params.pop_front().unwrap();
+    ensure_type!(s.is_string(), "{}'s second param should be string", name);
+    Ok((pattern, s.to_string()))
+}
+
+/// The first match of `re` in `s`, or nil.
+fn first_match(re: &Regex, s: &str) -> MalType {
+    re.captures(s)
+        .map_or_else(|| new_mal!(Nil), |caps| captures_to_mal(re, &caps))
+}
+
+fn re_find(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (pattern, s) = re_args(&mut params, "re-find")?;
+    Ok(first_match(&pattern.regex, &s))
+}
+
+fn re_matches(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (pattern, s) = re_args(&mut params, "re-matches")?;
+    Ok(first_match(pattern.anchored()?, &s))
+}
+
+fn re_seq(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (pattern, s) = re_args(&mut params, "re-seq")?;
+    let re = &pattern.regex;
+    let matches: LinkedList<MalType> =
+        re.captures_iter(&s).map(|caps| captures_to_mal(re, &caps)).collect();
+    if matches.is_empty() {
+        return Ok(new_mal!(Nil));
+    }
+    Ok(new_mal!(List(matches, new_mal!(Nil))))
+}
+
+fn re_groups(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let (pattern, s) = re_args(&mut params, "re-groups")?;
+    Ok(first_match(&pattern.regex, &s))
+}
+
+fn replace(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 3, "replace should have 3 params");
+    let s = params.pop_front().unwrap();
+    ensure_type!(s.is_string(), "replace's first param should be string");
+    let s = s.to_string();
+    let pattern = params.pop_front().unwrap();
+    let replacement = params.pop_front().unwrap();
+
+    if pattern.is_string() && replacement.is_string() {
+        return Ok(new_mal!(String(
+            s.replace(pattern.to_string().as_str(), &replacement.to_string())
+        )));
+    }
+    let re = if pattern.is_string() {
+        Regex::new(&regex::escape(&pattern.to_string()))?
+    } else {
+        to_regex(&pattern)?
+    };
+
+    if replacement.is_string() {
+        let replacement = replacement.to_string();
+        return Ok(new_mal!(String(
+            re.replace_all(&s, replacement.as_str()).into_owned()
+        )));
+    }
+
+    ensure_type!(
+        replacement.is_closure(),
+        "replace's third param should be string or func"
+    );
+    let f = replacement.to_closure();
+    let mut error = None;
+    let replaced = re
+        .replace_all(&s, |caps: &Captures| {
+            if error.is_some() {
+                return String::new();
+            }
+            match f.call(linked_list![captures_to_mal(&re, caps)]) {
+                Ok(ref r) if r.is_string() => r.to_string(),
+                Ok(r) => try_pr_str(&r, false).unwrap_or_default(),
+                Err(e) => {
+                    error = Some(e);
+                    String::new()
+                }
+            }
+        })
+        .into_owned();
+    match error {
+        Some(e) => Err(e),
+        None => Ok(new_mal!(String(replaced))),
+    }
+}
+
+fn split(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(
+        params.len() == 2 || params.len() == 3,
+        "split should have 2 or 3 params"
+    );
+    let s = params.pop_front().unwrap();
+    ensure_type!(s.is_string(), "split's first param should be string");
+    let s = s.to_string();
+    let re = to_regex(&params.pop_front().unwrap())?;
+    let mut parts: Vec<&str> = match params.pop_front() {
+        Some(limit) => {
+            ensure_type!(limit.is_num(), "split's limit should be num");
+            re.splitn(&s, limit.to_number().max(1f64) as usize).collect()
+        }
+        None => {
+            let mut parts: Vec<&str> = re.split(&s).collect();
+            // like Java's String.split, trailing empty strings are dropped
+            while parts.len() > 1 && parts.last() == Some(&"") {
+                parts.pop();
+            }
+            parts
+        }
+    };
+    Ok(new_mal!(Vec(
+        parts.drain(..).map(|p| new_mal!(String(p.to_string()))).collect(),
+        new_mal!(Nil)
+    )))
+}
+
+fn record_type(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "record-type should have 2 params");
+    let name = params.pop_front().unwrap();
+    ensure_type!(name.is_string(), "record-type's first param should be string");
+    let fields = seq_to_list(&params.pop_front().unwrap())?;
+    ensure_type!(
+        fields.iter().all(|f| f.is_keyword()),
+        "record-type's fields should be keywords"
+    );
+    let fields = fields.iter().map(|f| f.to_hash_key()).collect();
+    Ok(new_mal!(Type(Kind::Record(define_record_type(name.to_string(), fields)))))
+}
+
+fn make_record(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "make-record should have 2 params");
+    let rtype = match *params.pop_front().unwrap() {
+        InnerMalType::Type(Kind::Record(ref rtype)) => rtype.clone(),
+        ref t => bail_type!("{:?} is not a record type", t),
+    };
+    let init = params.pop_front().unwrap();
+    if init.is_hashmap() {
+        Ok(RecordType::instantiate(&rtype, init.to_hashmap()))
+    } else if init.is_collection() {
+        RecordType::from_values(&rtype, init.to_items().into_iter().collect())
+    } else {
+        bail_type!("make-record's second param should be map or vector")
+    }
+}
+
+fn is_record(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "record? should have 1 params");
+    Ok(new_mal!(Bool(params.pop_front().unwrap().is_record())))
+}
+
+fn is_instance(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "instance? should have 2 params");
+    let kind = Kind::from_mal(&params.pop_front().unwrap())?;
+    let x = params.pop_front().unwrap();
+    Ok(new_mal!(Bool(Kind::of(&x).supers().contains(&kind))))
+}
+
+fn type_of(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "type should have 1 params");
+    Ok(new_mal!(Type(Kind::of(&params.pop_front().unwrap()))))
+}
+
+fn to_protocol(mal: &MalType) -> Fallible<Rc<Protocol>> {
+    ensure_type!(mal.is_protocol(), "{:?} is not a protocol", mal);
+    Ok(mal.to_protocol())
+}
+
+fn protocol_method(
+    mut params: LinkedList<MalType>,
+    _c_env: Option<ClosureEnv>,
+) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 3, "protocol-method should have 3 params");
+    let protocol = to_protocol(&params.pop_front().unwrap())?;
+    let method = params.pop_front().unwrap();
+    ensure_type!(method.is_string(), "protocol-method's method should be string");
+    let method = method.to_string();
+    let args = params.pop_front().unwrap();
+    let items = args.to_items_ref();
+    ensure_arity!(!items.is_empty(), "{} should have at least 1 params", method);
+    protocol.find_method(&method, &Kind::of(items.front().unwrap()))
+}
+
+fn satisfies(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "satisfies? should have 2 params");
+    let protocol = to_protocol(&params.pop_front().unwrap())?;
+    let x = params.pop_front().unwrap();
+    let kind = match *x {
+        InnerMalType::Type(ref kind) => kind.clone(),
+        _ => Kind::of(&x),
+    };
+    Ok(new_mal!(Bool(protocol.is_satisfied_by(&kind))))
+}
+
+fn extend(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure!(
+        params.len() % 2 == 1,
+        "extend should have a type followed by protocol/method-map pairs"
+    );
+    let kind = Kind::from_mal(&params.pop_front().unwrap())?;
+    let mut extensions = Vec::new();
+    while let Some(protocol) = params.pop_front() {
+        let protocol = to_protocol(&protocol)?;
+        let methods = params.pop_front().unwrap();
+        ensure_type!(methods.is_hashmap(), "extend's method maps should be maps");
+        let fns = methods
+            .to_hashmap()
+            .into_iter()
+            .map(|(k, f)| match k {
+                HashKey::Keyword(name) => (name[1..].to_string(), f),
+                HashKey::String(name) => (name, f),
+                HashKey::Symbol(name) => (name.to_string(), f),
+            }).collect();
+        protocol.check(&fns)?;
+        extensions.push((protocol, fns));
+    }
+    for (protocol, fns) in extensions {
+        protocol.extend(kind.clone(), fns)?;
+    }
+    Ok(new_mal!(Nil))
+}
+
+fn make_multi(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(
+        params.len() == 2 || params.len() == 4,
+        "make-multi should have 2 or 4 params"
+    );
+    let name = params.pop_front().unwrap();
+    ensure_type!(name.is_string(), "make-multi's name should be string");
+    let dispatch = params.pop_front().unwrap();
+    ensure_type!(
+        dispatch.is_closure() || dispatch.is_keyword(),
+        "make-multi's dispatch should be func or keyword"
+    );
+    let mut default = new_mal!(Keyword(Sym::new(":default")));
+    if let Some(option) = params.pop_front() {
+        ensure!(
+            *option == InnerMalType::Keyword(Sym::new(":default")),
+            "unknown option {:?} for make-multi",
+            option
+        );
+        default = params.pop_front().unwrap();
+    }
+    // a defmulti evaluated again, as when its file is reloaded, keeps the
+    // multimethod it made and so its methods
+    let own = Sym::new(&name.to_string());
+    let existing = env_interns(&namespace::current().env)
+        .into_iter()
+        .find(|(k, _)| *k == own)
+        .map(|(_, binding)| binding.get());
+    if let Some(existing) = existing {
+        if to_multi(&existing).is_ok() {
+            return Ok(existing);
+        }
+    }
+    Ok(new_mal!(Closure(
+        Closure::new_multi(MultiFn::new(name.to_string(), dispatch, default)),
+        new_mal!(Nil)
+    )))
+}
+
+fn to_multi(mal: &MalType) -> Fallible<Rc<MultiFn>> {
+    match **mal {
+        InnerMalType::Closure(Closure { multi: Some(ref multi), .. }, ..) => Ok(multi.clone()),
+        _ => bail_type!("{:?} is not a multimethod", mal),
+    }
+}
+
+fn add_method(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 3, "add-method should have 3 params");
+    let multi = params.pop_front().unwrap();
+    let value = params.pop_front().unwrap();
+    to_multi(&multi)?.add_method(value, params.pop_front().unwrap())?;
+    Ok(multi)
+}
+
+fn remove_method(
+    mut params: LinkedList<MalType>,
+    _c_env: Option<ClosureEnv>,
+) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "remove-method should have 2 params");
+    let multi = params.pop_front().unwrap();
+    to_multi(&multi)?.remove_method(&params.pop_front().unwrap())?;
+    Ok(multi)
+}
+
+fn methods(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "methods should have 1 params");
+    let multi = to_multi(&params.pop_front().unwrap())?;
+    Ok(new_mal!(List(
+        multi
+            .methods()
+            .into_iter()
+            .map(|(value, f)| new_mal!(Vec(linked_list![value, f], new_mal!(Nil))))
+            .collect(),
+        new_mal!(Nil)
+    )))
+}
+
+fn prefer_method(
+    mut params: LinkedList<MalType>,
+    _c_env: Option<ClosureEnv>,
+) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 3, "prefer-method should have 3 params");
+    let multi = params.pop_front().unwrap();
+    let x = params.pop_front().unwrap();
+    to_multi(&multi)?.prefer_method(x, params.pop_front().unwrap())?;
+    Ok(multi)
+}
+
+fn derive(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "derive should have 2 params");
+    let child = params.pop_front().unwrap();
+    multi::derive(child, params.pop_front().unwrap())?;
+    Ok(new_mal!(Nil))
+}
+
+fn isa(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "isa? should have 2 params");
+    let child = params.pop_front().unwrap();
+    Ok(new_mal!(Bool(multi::isa(&child, &params.pop_front().unwrap())?)))
+}
+
+fn parents(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "parents should have 1 params");
+    let found = multi::parents(&params.pop_front().unwrap())?;
+    if found.is_empty() {
+        return Ok(new_mal!(Nil));
+    }
+    Ok(new_mal!(List(found.into_iter().collect(), new_mal!(Nil))))
+}
+
+fn ancestors(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "ancestors should have 1 params");
+    let found = multi::ancestors(&params.pop_front().unwrap())?;
+    if found.is_empty() {
+        return Ok(new_mal!(Nil));
+    }
+    Ok(new_mal!(List(found.into_iter().collect(), new_mal!(Nil))))
+}
+
+fn in_ns(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "in-ns should have 1 params");
+    let name = params.pop_front().unwrap();
+    ensure_type!(name.is_symbol(), "in-ns's param should be symbol");
+    let ns = namespace::create_ns(name.to_symbol());
+    namespace::set_current(ns.clone());
+    Ok(new_mal!(Namespace(ns)))
+}
+
+fn is_keyword_named(mal: &MalType, name: &str) -> bool {
+    **mal == InnerMalType::Keyword(Sym::new(name))
+}
+
+/// Each spec is a namespace name, or `[name :as alias :refer [names]]` where
+/// `:refer :all` refers every public binding. Modules not loaded yet are
+/// loaded from `*load-path*`, and a `:reload` flag loads them again.
+fn require(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    let current = namespace::current();
+    let reload = params.iter().any(|p| is_keyword_named(p, ":reload"));
+    for spec in params.into_iter().filter(|p| !is_keyword_named(p, ":reload")) {
+        let mut items = if spec.is_symbol() {
+            linked_list![spec]
+        } else {
+            ensure!(
+                spec.is_vec() || spec.is_list(),
+                "require's spec should be symbol or [name & options]"
+            );
+            spec.to_items()
+        };
+        let name = items.pop_front();
+        ensure!(
+            name.as_ref().map(|n| n.is_symbol()) == Some(true),
+            "require's spec should start with the namespace name"
+        );
+        let name = name.unwrap();
+        module::require(name.to_symbol(), reload)?;
+        let ns = namespace::to_ns(&name)?;
+        while let Some(option) = items.pop_front() {
+            let value = items.pop_front();
+            ensure!(value.is_some(), "require's option {} should have a value", try_pr_str(&option, true)?);
+            let value = value.unwrap();
+            if is_keyword_named(&option, ":as") {
+                ensure!(value.is_symbol(), "require's :as should be followed by symbol");
+                current.add_alias(value.to_symbol(), ns.clone());
+            } else if is_keyword_named(&option, ":refer") {
+                let names = if is_keyword_named(&value, ":all") {
+                    None
+                } else {
+                    ensure!(
+                        (value.is_vec() || value.is_list())
+                            && value.to_items_ref().iter().all(|n| n.is_symbol()),
+                        "require's :refer should be followed by :all or a vector of symbols"
+                    );
+                    Some(value.to_symbol_list())
+                };
+                current.refer(&ns, names)?;
+            } else {
+                bail!("unknown require option {}", try_pr_str(&option, true)?);
+            }
+        }
+    }
+    Ok(new_mal!(Nil))
+}
+
+fn ns_name(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "ns-name should have 1 params");
+    let ns = namespace::to_ns(&params.pop_front().unwrap())?;
+    Ok(new_mal!(Symbol(ns.name)))
+}
+
+fn find_ns(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "find-ns should have 1 params");
+    let name = params.pop_front().unwrap();
+    ensure_type!(name.is_symbol(), "find-ns's param should be symbol");
+    Ok(match namespace::find_ns(name.to_symbol()) {
+        Some(ns) => new_mal!(Namespace(ns)),
+        None => new_mal!(Nil),
+    })
+}
+
+fn ns_publics(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "ns-publics should have 1 params");
+    let ns = namespace::to_ns(&params.pop_front().unwrap())?;
+    let map = ns
+        .publics()
+        .into_iter()
+        .map(|(name, _)| {
+            (
+                HashKey::Symbol(name),
+                new_mal!(Var(Var::new(ns.env.clone(), name))),
+            )
+        }).collect();
+    Ok(new_mal!(Hashmap(map, new_mal!(Nil))))
+}
+
+fn ns_resolve(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "ns-resolve should have 2 params");
+    let ns = namespace::to_ns(&params.pop_front().unwrap())?;
+    let name = params.pop_front().unwrap();
+    ensure_type!(name.is_symbol(), "ns-resolve's second param should be symbol");
+    Ok(match namespace::resolve_var(&ns.env, name.to_symbol())? {
+        Some((env, name)) => new_mal!(Var(Var::new(env, name))),
+        None => new_mal!(Nil),
+    })
+}
+
+fn to_env(mal: &MalType, f: &str) -> Fallible<Env> {
+    ensure_type!(mal.is_env(), "{}'s first param should be env", f);
+    Ok(mal.to_env())
+}
+
+fn is_env(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "env? should have 1 params");
+    Ok(new_mal!(Bool(params.pop_front().unwrap().is_env())))
+}
+
+fn make_env(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() <= 1, "make-env should have 0 or 1 params");
+    let parent = match params.pop_front() {
+        Some(ref p) if !p.is_nil() => Some(to_env(p, "make-env")?),
+        _ => None,
+    };
+    let env = env_new(parent, Vec::new(), Vec::new());
+    gc::track_env(&env);
+    Ok(new_mal!(Env(env)))
+}
+
+fn env_get_fn(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 2, "env-get should have 2 params");
+    let env = to_env(&params.pop_front().unwrap(), "env-get")?;
+    let name = params.pop_front().unwrap();
+    ensure_type!(name.is_symbol(), "env-get's second param should be symbol");
+    Ok(env_get(env, name.to_symbol()).unwrap_or_else(|| new_mal!(Nil)))
+}
+
+fn env_set_fn(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 3, "env-set! should have 3 params");
+    let env = to_env(&params.pop_front().unwrap(), "env-set!")?;
+    let name = params.pop_front().unwrap();
+    ensure_type!(name.is_symbol(), "env-set!'s second param should be symbol");
+    let value = params.pop_front().unwrap();
+    env_set(env, name.to_symbol(), value.clone());
+    Ok(value)
+}
+
+fn env_keys(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "env-keys should have 1 params");
+    let env = to_env(&params.pop_front().unwrap(), "env-keys")?;
+    Ok(new_mal!(List(
+        env_names(&env).into_iter().map(|k| new_mal!(Symbol(k))).collect(),
+        new_mal!(Nil)
+    )))
+}
+
+fn env_parent(mut params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.len() == 1, "env-parent should have 1 params");
+    let env = to_env(&params.pop_front().unwrap(), "env-parent")?;
+    Ok(match env_outer(&env) {
+        Some(outer) => {
+            gc::track_env(&outer);
+            new_mal!(Env(outer))
+        }
+        None => new_mal!(Nil),
+    })
+}
+
+fn gc(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.is_empty(), "gc should have 0 params");
+    Ok(new_mal!(Num(gc::collect() as f64)))
+}
+
+fn room(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.is_empty(), "room should have 0 params");
+    let room = gc::room();
+    let stats = gc::stats();
+    let map = vec![
+        ("envs", room.envs),
+        ("bindings", room.bindings),
+        ("values", room.values),
+        ("tracked", room.tracked),
+        ("collections", stats.collections),
+        ("reclaimed-envs", stats.reclaimed_envs),
+        ("reclaimed", stats.reclaimed),
+    ].into_iter()
+    .map(|(k, n)| (HashKey::Keyword(Sym::new(&format!(":{}", k))), new_mal!(Num(n as f64))))
+    .collect();
+    Ok(new_mal!(Hashmap(map, new_mal!(Nil))))
+}
+
+#[cfg(jit)]
+fn jit_stats(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.is_empty(), "jit-stats should have 0 params");
+    Ok(::jit::stats())
+}
+
+/// Without the `jit` feature nothing is compiled.
+#[cfg(not(jit))]
+fn jit_stats(params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure_arity!(params.is_empty(), "jit-stats should have 0 params");
+    let map = vec![
+        ("enabled", new_mal!(Bool(false))),
+        ("compiled", new_mal!(Vec(LinkedList::new(), new_mal!(Nil)))),
+        ("rejected", new_mal!(Num(0f64))),
+        ("native-calls", new_mal!(Num(0f64))),
+        ("deopts", new_mal!(Num(0f64))),
+    ].into_iter()
+    .map(|(k, v)| (HashKey::Keyword(Sym::new(&format!(":{}", k))), v))
+    .collect();
+    Ok(new_mal!(Hashmap(map, new_mal!(Nil))))
+}
+
+/// The name of the numeric builtin `func` is, which compiled code may
+/// inline.
+pub fn numeric_builtin(func: ClosureFunc) -> Option<&'static str> {
+    let builtins: [(ClosureFunc, &'static str); 9] = [
+        (add, "+"),
+        (minus, "-"),
+        (multiply, "*"),
+        (divide, "/"),
+        (equal, "="),
+        (less_than, "<"),
+        (less_than_equal, "<="),
+        (greater_than, ">"),
+        (greater_than_equal, ">="),
+    ];
+    builtins
+        .iter()
+        .find(|&&(f, _)| f as usize == func as usize)
+        .map(|&(_, name)| name)
+}
+
 pub struct Ns {
     pub map: HashMap<String, Closure>,
+    pub meta: HashMap<String, MalType>,
 }
 
 impl Ns {
+    fn insert(&mut self, name: &str, func: ClosureFunc, arglists: &str, doc: &str) {
+        self.map.insert(name.to_string(), Closure::new(func, None));
+        self.meta.insert(name.to_string(), native_meta(name, arglists, doc));
+    }
+
     pub fn new() -> Self {
-        let mut mapping: HashMap<String, Closure> = HashMap::new();
-        mapping.insert("+".to_string(), Closure::new(add, None));
-        mapping.insert("-".to_string(), Closure::new(minus, None));
-        mapping.insert("*".to_string(), Closure::new(multiply, None));
-        mapping.insert("/".to_string(), Closure::new(divide, None));
-        mapping.insert("prn".to_string(), Closure::new(prn, None));
-        mapping.insert("str".to_string(), Closure::new(str2, None));
-        mapping.insert("pr-str".to_string(), Closure::new(pr_str2, None));
-        mapping.insert("println".to_string(), Closure::new(println2, None));
-        mapping.insert("list".to_string(), Closure::new(list, None));
-        mapping.insert("list?".to_string(), Closure::new(is_list, None));
-        mapping.insert("empty?".to_string(), Closure::new(is_empty, None));
-        mapping.insert("count".to_string(), Closure::new(count, None));
-        mapping.insert("=".to_string(), Closure::new(equal, None));
-        mapping.insert("<".to_string(), Closure::new(less_than, None));
-        mapping.insert("<=".to_string(), Closure::new(less_than_equal, None));
-        mapping.insert(">".to_string(), Closure::new(greater_than, None));
-        mapping.insert(">=".to_string(), Closure::new(greater_than_equal, None));
-        mapping.insert("read-string".to_string(), Closure::new(read_string, None));
-        mapping.insert("slurp".to_string(), Closure::new(slurp, None));
-        mapping.insert("atom".to_string(), Closure::new(atom, None));
-        mapping.insert("atom?".to_string(), Closure::new(is_atom, None));
-        mapping.insert("deref".to_string(), Closure::new(deref, None));
-        mapping.insert("reset!".to_string(), Closure::new(reset, None));
-        mapping.insert("cons".to_string(), Closure::new(cons, None));
-        mapping.insert("concat".to_string(), Closure::new(concat, None));
-        mapping.insert("nth".to_string(), Closure::new(nth, None));
-        mapping.insert("first".to_string(), Closure::new(first, None));
-        mapping.insert("rest".to_string(), Closure::new(rest, None));
-        mapping.insert("throw".to_string(), Closure::new(throw, None));
-        mapping.insert("map".to_string(), Closure::new(map, None));
-        mapping.insert("apply".to_string(), Closure::new(apply, None));
-        mapping.insert("nil?".to_string(), Closure::new(is_nil, None));
-        mapping.insert("true?".to_string(), Closure::new(is_true, None));
-        mapping.insert("false?".to_string(), Closure::new(is_false, None));
-        mapping.insert("symbol?".to_string(), Closure::new(is_symbol, None));
-        mapping.insert("symbol".to_string(), Closure::new(symbol, None));
-        mapping.insert("keyword".to_string(), Closure::new(keyword, None));
-        mapping.insert("keyword?".to_string(), Closure::new(is_keyword, None));
-        mapping.insert("vector".to_string(), Closure::new(vector, None));
-        mapping.insert("vector?".to_string(), Closure::new(is_vector, None));
-        mapping.insert("hash-map".to_string(), Closure::new(hashmap, None));
-        mapping.insert("map?".to_string(), Closure::new(is_map, None));
-        mapping.insert("number?".to_string(), Closure::new(is_number, None));
-        mapping.insert("string?".to_string(), Closure::new(is_string, None));
-        mapping.insert("assoc".to_string(), Closure::new(assoc, None));
-        mapping.insert("dissoc".to_string(), Closure::new(dissoc, None));
-        mapping.insert("get".to_string(), Closure::new(get, None));
-        mapping.insert("contains?".to_string(), Closure::new(contains, None));
-        mapping.insert("keys".to_string(), Closure::new(keys, None));
-        mapping.insert("vals".to_string(), Closure::new(vals, None));
-        mapping.insert("sequential?".to_string(), Closure::new(is_sequential, None));
-        mapping.insert("readline".to_string(), Closure::new(readline, None));
-        mapping.insert("meta".to_string(), Closure::new(meta, None));
-        mapping.insert("with-meta".to_string(), Closure::new(with_meta, None));
-        mapping.insert("conj".to_string(), Closure::new(conj, None));
-        mapping.insert("seq".to_string(), Closure::new(seq, None));
-        mapping.insert("fn?".to_string(), Closure::new(is_fn, None));
-        mapping.insert("macro?".to_string(), Closure::new(is_macro, None));
-        mapping.insert("time-ms".to_string(), Closure::new(time_ms, None));
-        mapping.insert("swap!".to_string(), Closure::new(swap, None));
-
-        Ns { map: mapping }
+        let mut ns = Ns {
+            map: HashMap::new(),
+            meta: HashMap::new(),
+        };
+        ns.insert("+", add, "[a b]", "Returns the sum of a and b.");
+        ns.insert("-", minus, "[a b]", "Returns a minus b.");
+        ns.insert("*", multiply, "[a b]", "Returns the product of a and b.");
+        ns.insert("/", divide, "[a b]", "Returns a divided by b.");
+        ns.insert("prn", prn, "[& xs]", "Prints xs readably, separated by spaces, followed by a newline. Returns nil.");
+        ns.insert("str", str2, "[& xs]", "Returns the concatenation of xs printed non-readably.");
+        ns.insert("pr-str", pr_str2, "[& xs]", "Returns xs printed readably and separated by spaces.");
+        ns.insert("println", println2, "[& xs]", "Prints xs non-readably, separated by spaces, followed by a newline. Returns nil.");
+        ns.insert("list", list, "[& items]", "Returns a new list containing the items.");
+        ns.insert("list?", is_list, "[x]", "Returns true if x is a list.");
+        ns.insert("empty?", is_empty, "[coll]", "Returns true if coll has no items.");
+        ns.insert("count", count, "[coll]", "Returns the number of items in coll. (count nil) is 0.");
+        ns.insert("=", equal, "[a b]", "Returns true if a and b are equal. Lists and vectors with equal items are equal.");
+        ns.insert("<", less_than, "[a b]", "Returns true if a is less than b.");
+        ns.insert("<=", less_than_equal, "[a b]", "Returns true if a is less than or equal to b.");
+        ns.insert(">", greater_than, "[a b]", "Returns true if a is greater than b.");
+        ns.insert(">=", greater_than_equal, "[a b]", "Returns true if a is greater than or equal to b.");
+        ns.insert("read-string", read_string, "[s]", "Reads one form from the string s.");
+        ns.insert("slurp", slurp, "[filename]", "Returns the content of the file as a string.");
+        ns.insert("load-file", load_file, "[f]", "Reads and evaluates the forms in the file f, relative to the file being loaded if it is there. The current namespace is restored afterwards.");
+        ns.insert("atom", atom, "[x]", "Returns a new atom holding x.");
+        ns.insert("atom?", is_atom, "[x]", "Returns true if x is an atom.");
+        ns.insert("deref", deref, "[ref]", "Returns the value held by an atom or the value bound to a var.");
+        ns.insert("reset!", reset, "[atom x]", "Sets the value of atom to x. Returns x.");
+        ns.insert("cons", cons, "[x seq]", "Returns a new seq with x as the first item and seq as the rest.");
+        ns.insert("concat", concat, "[& colls]", "Returns a seq of the items of all colls, lazy if any of them is lazy.");
+        ns.insert("nth", nth, "[coll index]", "Returns the item of coll at index. Throws if index is out of bounds.");
+        ns.insert("first", first, "[coll]", "Returns the first item of coll, or nil if coll is empty or nil.");
+        ns.insert("rest", rest, "[coll]", "Returns a seq of the items after the first. Returns () if there are none.");
+        ns.insert("throw", throw, "[x]", "Throws x as an exception.");
+        ns.insert("ex-info", ex_info_fn, "[msg map] [msg map cause]", "Returns an exception with the message msg and the data map, thrown for the exception cause if given.");
+        ns.insert("ex-data", ex_data, "[e]", "Returns the data map of the exception e made by ex-info, or nil.");
+        ns.insert("ex-message", ex_message, "[e]", "Returns the message of the exception e made by ex-info, or nil for any other value.");
+        ns.insert("ex-cause", ex_cause, "[e]", "Returns the exception e made by ex-info was thrown for, or nil.");
+        ns.insert("ex-stack", ex_stack, "[e]", "Returns the calls the caught exception e was thrown in, innermost first, as maps of the :fn called, the :form calling it and its :file, :line and :column if known.");
+        ns.insert("map", map, "[f coll] [f coll & colls]", "Returns a lazy seq of applying f to the first items of each coll, then the second items, until any coll is exhausted.");
+        ns.insert("apply", apply, "[f args] [f x & args]", "Calls f with the items of the last argument appended to the others.");
+        ns.insert("trampoline", trampoline, "[f & args]", "Calls f with args, then the fn it returns with no args while it returns one. Returns the first value which is not a fn, so that mutually recursive fns returning their tail calls as fns run in constant stack.");
+        ns.insert("nil?", is_nil, "[x]", "Returns true if x is nil.");
+        ns.insert("true?", is_true, "[x]", "Returns true if x is true.");
+        ns.insert("false?", is_false, "[x]", "Returns true if x is false.");
+        ns.insert("symbol?", is_symbol, "[x]", "Returns true if x is a symbol.");
+        ns.insert("symbol", symbol, "[name]", "Returns the symbol with the given name.");
+        ns.insert("keyword", keyword, "[name]", "Returns the keyword with the given name.");
+        ns.insert("keyword?", is_keyword, "[x]", "Returns true if x is a keyword.");
+        ns.insert("vector", vector, "[& items]", "Returns a new vector containing the items.");
+        ns.insert("vector?", is_vector, "[x]", "Returns true if x is a vector.");
+        ns.insert("hash-map", hashmap, "[& keyvals]", "Returns a new map with the given keys and values.");
+        ns.insert("map?", is_map, "[x]", "Returns true if x is a map.");
+        ns.insert("number?", is_number, "[x]", "Returns true if x is a number.");
+        ns.insert("string?", is_string, "[x]", "Returns true if x is a string.");
+        ns.insert("assoc", assoc, "[map key val & kvs]", "Returns a new map with the keys mapped to the vals.");
+        ns.insert("dissoc", dissoc, "[map & keys]", "Returns a new map without the keys.");
+        ns.insert("get", get, "[map key] [map key not-found]", "Returns the value mapped to key, or not-found or nil if absent.");
+        ns.insert("contains?", contains, "[map key]", "Returns true if key is present in map.");
+        ns.insert("keys", keys, "[map]", "Returns a list of the keys of map.");
+        ns.insert("vals", vals, "[map]", "Returns a list of the values of map.");
+        ns.insert("sequential?", is_sequential, "[x]", "Returns true if x is a list, vector or lazy seq.");
+        ns.insert("readline", readline, "[prompt]", "Prints prompt and reads a line from stdin. Returns nil at end of input.");
+        ns.insert("meta", meta, "[x]", "Returns the metadata of x, or of the binding if x is a var.");
+        ns.insert("with-meta", with_meta, "[x meta]", "Returns a copy of x with meta as its metadata.");
+        ns.insert("conj", conj, "[coll & xs]", "Adds xs to coll: to the front of lists and seqs, to the end of vectors.");
+        ns.insert("seq", seq, "[coll]", "Returns a seq of coll, or nil if coll is empty.");
+        ns.insert("fn?", is_fn, "[x]", "Returns true if x is a function that is not a macro.");
+        ns.insert("macro?", is_macro, "[x]", "Returns true if x is a macro.");
+        ns.insert("time-ms", time_ms, "[]", "Returns the current time in milliseconds.");
+        ns.insert("swap!", swap, "[atom f & args]", "Sets the value of atom to (apply f current-value args). Returns the new value.");
+        ns.insert("filter", filter, "[pred coll]", "Returns a lazy seq of the items in coll for which pred returns logical true.");
+        ns.insert("remove", remove, "[pred coll]", "Returns a lazy seq of the items in coll for which pred returns logical false.");
+        ns.insert("take", take, "[n coll]", "Returns a lazy seq of the first n items of coll.");
+        ns.insert("drop", drop, "[n coll]", "Returns a lazy seq of all but the first n items of coll.");
+        ns.insert("take-while", take_while, "[pred coll]", "Returns a lazy seq of the items of coll while pred returns logical true.");
+        ns.insert("drop-while", drop_while, "[pred coll]", "Returns a lazy seq of the items of coll starting from the first for which pred returns logical false.");
+        ns.insert("range", range, "[] [end] [start end] [start end step]", "Returns a lazy seq of numbers from start (inclusive, default 0) to end (exclusive, default infinity) by step (default 1).");
+        ns.insert("iterate", iterate, "[f x]", "Returns a lazy seq of x, (f x), (f (f x)) etc.");
+        ns.insert("repeat", repeat, "[x] [n x]", "Returns a lazy seq of x, n times or infinitely.");
+        ns.insert("cycle", cycle, "[coll]", "Returns an infinite lazy seq repeating the items of coll.");
+        ns.insert("doall", doall, "[coll]", "Realises all of the lazy seq coll and returns it.");
+        ns.insert("dorun", dorun, "[coll]", "Realises all of the lazy seq coll for its side effects. Returns nil.");
+        ns.insert("realized?", is_realized, "[x]", "Returns true if the first cell of lazy seq x has been realised.");
+        ns.insert("lazy-seq?", is_lazy_seq, "[x]", "Returns true if x is a lazy seq.");
+        ns.insert("set-max-depth!", set_max_depth, "[n]", "Limits evaluation to n frames nested at once, past which a StackOverflow error is thrown; nil leaves memory as the only limit.");
+        ns.insert("set-print-length!", print_length, "[n]", "Limits the number of items printed for each sequence to n; nil removes the limit.");
+        ns.insert("re-pattern", re_pattern, "[s]", "Returns a compiled regex for the string s.");
+        ns.insert("regex?", is_regex, "[x]", "Returns true if x is a compiled regex.");
+        ns.insert("re-find", re_find, "[re s]", "Returns the first match of re in s, or nil. With groups the match is a vector of the whole match and each group, or a map keyed by name when re has named groups.");
+        ns.insert("re-matches", re_matches, "[re s]", "Returns the match of re against the whole of s, or nil.");
+        ns.insert("re-seq", re_seq, "[re s]", "Returns a list of all matches of re in s, or nil.");
+        ns.insert("re-groups", re_groups, "[re s]", "Returns the groups of the first match of re in s: a map keyed by name when re has named groups.");
+        ns.insert("replace", replace, "[s match replacement]", "Replaces all matches of a string or regex in s. replacement is a string, which may refer to groups as $1, or a function of the match.");
+        ns.insert("split", split, "[s re] [s re limit]", "Splits s on matches of re into a vector of at most limit parts.");
+        ns.insert("record-type", record_type, "[name fields]", "Defines a new record type with the given name and vector of field keywords. Used by defrecord.");
+        ns.insert("make-record", make_record, "[type map] [type values]", "Returns an instance of the record type from a map, or from a vector of the field values in declared order.");
+        ns.insert("record?", is_record, "[x]", "Returns true if x is a record.");
+        ns.insert("type", type_of, "[x]", "Returns the type of x.");
+        ns.insert("protocol-method", protocol_method, "[protocol method args]", "Returns the implementation of the protocol method for the type of the first of args. Used by the functions defprotocol defines.");
+        ns.insert("satisfies?", satisfies, "[protocol x]", "Returns true if the type x, or the type of the value x, implements protocol.");
+        ns.insert("extend", extend, "[type & protocol+method-maps]", "Implements protocols for type, each with a map from method name to function.");
+        ns.insert("make-multi", make_multi, "[name dispatch-fn] [name dispatch-fn :default value]", "Returns a new multimethod, or the one the current namespace already binds name to. Used by defmulti.");
+        ns.insert("add-method", add_method, "[multifn dispatch-val f]", "Installs f as the method of multifn for dispatch-val. Used by defmethod.");
+        ns.insert("remove-method", remove_method, "[multifn dispatch-val]", "Removes the method of multifn for dispatch-val.");
+        ns.insert("methods", methods, "[multifn]", "Returns a list of the [dispatch-val method] pairs of multifn.");
+        ns.insert("prefer-method", prefer_method, "[multifn x y]", "Makes multifn prefer the method for x over the method for y when both match.");
+        ns.insert("derive", derive, "[child parent]", "Establishes a parent/child relationship in the global hierarchy. child is a keyword, symbol or type, parent a keyword or symbol.");
+        ns.insert("isa?", isa, "[child parent]", "Returns true if child equals parent or derives from it, directly or indirectly. Vectors are compared item by item.");
+        ns.insert("parents", parents, "[x]", "Returns a list of the direct parents of x, or nil.");
+        ns.insert("ancestors", ancestors, "[x]", "Returns a list of the direct and indirect parents of x, or nil.");
+        ns.insert("in-ns", in_ns, "[name]", "Makes the namespace named by the symbol name current, creating it if needed.");
+        ns.insert("require", require, "[& specs]", "Makes namespaces available to the current one. Each spec is a namespace name or [name :as alias :refer [names]], where :refer :all refers every public name.");
+        ns.insert("ns-name", ns_name, "[ns]", "Returns the name of ns as a symbol.");
+        ns.insert("find-ns", find_ns, "[name]", "Returns the namespace named by the symbol name, or nil.");
+        ns.insert("ns-publics", ns_publics, "[ns]", "Returns a map from the names of the public definitions of ns to their vars.");
+        ns.insert("ns-resolve", ns_resolve, "[ns sym]", "Returns the var sym names in ns, or nil.");
+        ns.insert("gc", gc, "[]", "Frees the reference cycles nothing refers to any more, like closures defined in the env they close over, and returns how many objects were reclaimed.");
+        ns.insert("room", room, "[]", "Returns a map of the envs, bindings and values now reachable, the objects :tracked as possible parts of cycles, and the :collections run and objects :reclaimed so far.");
+        ns.insert("jit-stats", jit_stats, "[]", "Returns a map telling whether the JIT is :enabled, the fns it :compiled, how many it :rejected, and the :native-calls of compiled code and :deopts back to the interpreter.");
+        ns.insert("env?", is_env, "[x]", "Returns true if x is an env.");
+        ns.insert("make-env", make_env, "[] [parent]", "Returns a new empty env whose bindings shadow those of the env parent, if given.");
+        ns.insert("env-get", env_get_fn, "[env sym]", "Returns the value sym is bound to in env or its parents, or nil.");
+        ns.insert("env-set!", env_set_fn, "[env sym val]", "Binds sym to val in env itself and returns val.");
+        ns.insert("env-keys", env_keys, "[env]", "Returns a list of the symbols bound in env itself, not its parents.");
+        ns.insert("env-parent", env_parent, "[env]", "Returns the env enclosing env, or nil for a root env like that of a namespace.");
+        ns.insert("instance?", is_instance, "[type x]", "Returns true if x is an instance of type: its own type, Map for records, or Object.");
+
+        ns
     }
 }
@@ -0,0 +1,445 @@
+//! Deserializes mal values with serde, and any serde type from a mal value.
+//! The encoding is described in `ser`.
+//!
+//! `from_mal` reads a struct from a map keyed by keywords or strings of its
+//! field names, and an enum from a keyword or string naming a unit variant,
+//! a vector tagged with the variant, `[:Variant field...]`, or a map of the
+//! variant to its fields, `{:Variant fields}`, as JSON has it.
+
+use error::SerdeError;
+use failure::Fallible;
+use lazy::seq_to_list;
+use printer::pr_str;
+use ser::encode_text;
+use serde::de::{self, DeserializeOwned, Error, IntoDeserializer, Visitor};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{HashKey, InnerMalType, Kind, MalType};
+
+/// The newtype a mal value is deserialized as, so that `from_mal` gives it
+/// strings, keywords and symbols with the encoding.
+pub const VALUE: &str = "$mal::Value";
+
+/// Converts `mal` to a `T`.
+pub fn from_mal<T: DeserializeOwned>(mal: &MalType) -> Fallible<T> {
+    Ok(T::deserialize(Deserializer::new(mal.clone()))?)
+}
+
+/// The string, keyword or symbol `text` is in serde's data model.
+pub fn decode_text(text: &str) -> MalType {
+    match text.chars().next() {
+        Some(':') => new_mal!(Keyword(Sym::new(text))),
+        Some('\'') => new_mal!(Symbol(Sym::new(&text[1..]))),
+        Some('\\') => new_mal!(String(text[1..].to_string())),
+        _ => new_mal!(String(text.to_string())),
+    }
+}
+
+impl<'de> de::Deserialize<'de> for InnerMalType {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<InnerMalType, D::Error> {
+        let value = deserializer.deserialize_newtype_struct(VALUE, ValueVisitor)?;
+        // a fresh value is not shared yet
+        Ok(Rc::try_unwrap(value).unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = MalType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a mal value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<MalType, E> {
+        Ok(new_mal!(Bool(v)))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<MalType, E> {
+        Ok(new_mal!(Num(v as f64)))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<MalType, E> {
+        Ok(new_mal!(Num(v as f64)))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<MalType, E> {
+        Ok(new_mal!(Num(v)))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<MalType, E> {
+        Ok(decode_text(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<MalType, E> {
+        let items = v.iter().map(|b| new_mal!(Num(f64::from(*b)))).collect();
+        Ok(new_mal!(Vec(items, new_mal!(Nil))))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<MalType, E> {
+        Ok(new_mal!(Nil))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<MalType, E> {
+        Ok(new_mal!(Nil))
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<MalType, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<MalType, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<MalType, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(new_mal!(Vec(items.into_iter().collect(), new_mal!(Nil))))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<MalType, A::Error> {
+        let mut entries = HashMap::new();
+        while let Some((key, value)) = map.next_entry::<MalType, MalType>()? {
+            let key = match *key {
+                InnerMalType::String(ref s) => HashKey::String(s.clone()),
+                InnerMalType::Keyword(k) => HashKey::Keyword(k),
+                InnerMalType::Symbol(s) => HashKey::Symbol(s),
+                // numbers and the like, as JSON has them
+                _ => HashKey::String(pr_str(&key, false)),
+            };
+            entries.insert(key, value);
+        }
+        Ok(new_mal!(Hashmap(entries, new_mal!(Nil))))
+    }
+}
+
+/// Deserializes from a mal value.
+pub struct Deserializer {
+    value: MalType,
+    // whether strings, keywords and symbols are given encoded
+    encoded: bool,
+    // whether it is a map key, which may be a number in a string
+    key: bool,
+}
+
+impl Deserializer {
+    pub fn new(value: MalType) -> Deserializer {
+        Deserializer {
+            value,
+            encoded: false,
+            key: false,
+        }
+    }
+
+    fn of(&self, value: MalType) -> Deserializer {
+        Deserializer {
+            value,
+            encoded: self.encoded,
+            key: false,
+        }
+    }
+
+    fn key_of(&self, value: MalType) -> Deserializer {
+        Deserializer {
+            key: true,
+            ..self.of(value)
+        }
+    }
+
+    fn mismatch(&self, expected: &str) -> SerdeError {
+        SerdeError::custom(format!(
+            "expected {}, got {} {}",
+            expected,
+            Kind::of(&self.value).name(),
+            pr_str(&self.value, true)
+        ))
+    }
+}
+
+/// Reads a number, from a string when it is a map key since `to_mal` makes
+/// number keys strings.
+macro_rules! deserialize_number {
+    ($($method:ident $visit:ident $t:ty)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+            if let (true, InnerMalType::String(ref s)) = (self.key, &*self.value) {
+                let n: $t = s.parse().map_err(|_| self.mismatch(stringify!($t)))?;
+                return visitor.$visit(n);
+            }
+            self.deserialize_any(visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match *self.value {
+            InnerMalType::Nil => visitor.visit_unit(),
+            InnerMalType::Bool(b) => visitor.visit_bool(b),
+            InnerMalType::Num(n) if n.fract() == 0.0 && n.abs() <= (1u64 << 53) as f64 => {
+                visitor.visit_i64(n as i64)
+            }
+            InnerMalType::Num(n) => visitor.visit_f64(n),
+            InnerMalType::String(ref s) if !self.encoded => visitor.visit_str(s),
+            InnerMalType::Keyword(k) if !self.encoded => visitor.visit_str(&k[1..]),
+            InnerMalType::Symbol(s) if !self.encoded => visitor.visit_str(&s),
+            InnerMalType::String(_) | InnerMalType::Keyword(_) | InnerMalType::Symbol(_) => {
+                visitor.visit_string(encode_text(&self.value))
+            }
+            InnerMalType::List(..) | InnerMalType::Vec(..) | InnerMalType::LazySeq(..) => {
+                let items = seq_to_list(&self.value).map_err(SerdeError::custom)?;
+                visitor.visit_seq(SeqAccess {
+                    items: items.into_iter().map(|item| self.of(item)).collect::<Vec<_>>().into_iter(),
+                })
+            }
+            InnerMalType::Hashmap(ref map, _) | InnerMalType::Record(_, ref map, _) => {
+                visitor.visit_map(MapAccess {
+                    entries: map
+                        .iter()
+                        .map(|(k, v)| (self.key_of(k.to_mal_type()), self.of(v.clone())))
+                        .collect::<Vec<_>>()
+                        .into_iter(),
+                    value: None,
+                })
+            }
+            _ => Err(SerdeError::custom(format!(
+                "can't deserialize {}",
+                Kind::of(&self.value).name()
+            ))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match *self.value {
+            InnerMalType::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        if name == VALUE {
+            let value = self.value;
+            return visitor.visit_newtype_struct(Deserializer {
+                value,
+                encoded: true,
+                key: self.key,
+            });
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        let expected = "a keyword, a tagged vector or a map of one entry";
+        let (tag, content) = match *self.value {
+            InnerMalType::Keyword(k) => (k[1..].to_string(), Content::Values(Vec::new())),
+            InnerMalType::String(ref s) => (s.clone(), Content::Values(Vec::new())),
+            InnerMalType::List(ref l, _) | InnerMalType::Vec(ref l, _) => {
+                let mut items = l.iter().cloned();
+                match items.next().as_deref() {
+                    Some(InnerMalType::Keyword(k)) => {
+                        (k[1..].to_string(), Content::Values(items.collect()))
+                    }
+                    Some(InnerMalType::String(s)) => (s.clone(), Content::Values(items.collect())),
+                    _ => return Err(self.mismatch(expected)),
+                }
+            }
+            InnerMalType::Hashmap(ref map, _) if map.len() == 1 => {
+                let (key, value) = map.iter().next().unwrap();
+                let tag = match *key {
+                    HashKey::Keyword(k) => k[1..].to_string(),
+                    HashKey::String(ref s) => s.clone(),
+                    HashKey::Symbol(s) => s.to_string(),
+                };
+                (tag, Content::Value(value.clone()))
+            }
+            _ => return Err(self.mismatch(expected)),
+        };
+        visitor.visit_enum(EnumAccess {
+            tag,
+            variant: Variant {
+                de: self,
+                content,
+            },
+        })
+    }
+
+    deserialize_number! {
+        deserialize_i8 visit_i8 i8
+        deserialize_i16 visit_i16 i16
+        deserialize_i32 visit_i32 i32
+        deserialize_i64 visit_i64 i64
+        deserialize_u8 visit_u8 u8
+        deserialize_u16 visit_u16 u16
+        deserialize_u32 visit_u32 u32
+        deserialize_u64 visit_u64 u64
+        deserialize_f32 visit_f32 f32
+        deserialize_f64 visit_f64 f64
+    }
+
+    forward_to_deserialize_any! {
+        bool i128 u128 char str string bytes byte_buf unit unit_struct seq
+        tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct SeqAccess {
+    items: ::std::vec::IntoIter<Deserializer>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, SerdeError> {
+        match self.items.next() {
+            Some(item) => seed.deserialize(item).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct MapAccess {
+    entries: ::std::vec::IntoIter<(Deserializer, Deserializer)>,
+    value: Option<Deserializer>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, SerdeError> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(key).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, SerdeError> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(SerdeError::custom("map value read before its key")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+/// What follows the tag of an enum value.
+enum Content {
+    // the rest of a tagged vector
+    Values(Vec<MalType>),
+    // the value of a map of one entry
+    Value(MalType),
+}
+
+struct EnumAccess {
+    tag: String,
+    variant: Variant,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = SerdeError;
+    type Variant = Variant;
+
+    fn variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<(T::Value, Variant), SerdeError> {
+        let tag = seed.deserialize(self.tag.into_deserializer())?;
+        Ok((tag, self.variant))
+    }
+}
+
+struct Variant {
+    de: Deserializer,
+    content: Content,
+}
+
+impl Variant {
+    /// The one value after the tag.
+    fn value(self, what: &str) -> Result<Deserializer, SerdeError> {
+        match self.content {
+            Content::Value(value) => Ok(self.de.of(value)),
+            Content::Values(mut values) if values.len() == 1 => Ok(self.de.of(values.remove(0))),
+            Content::Values(_) => Err(self.de.mismatch(what)),
+        }
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        match self.content {
+            Content::Values(ref values) if values.is_empty() => Ok(()),
+            Content::Value(ref value) if value.is_nil() => Ok(()),
+            _ => Err(self.de.mismatch("a unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, SerdeError> {
+        seed.deserialize(self.value("a variant with one value")?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.content {
+            Content::Value(value) => de::Deserializer::deserialize_seq(self.de.of(value), visitor),
+            Content::Values(ref values) if values.len() == len => {
+                let items: Vec<Deserializer> =
+                    values.iter().map(|v| self.de.of(v.clone())).collect();
+                visitor.visit_seq(SeqAccess {
+                    items: items.into_iter(),
+                })
+            }
+            Content::Values(_) => Err(self
+                .de
+                .mismatch(&format!("a variant with {} values", len))),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        de::Deserializer::deserialize_map(self.value("a variant with a map")?, visitor)
+    }
+}
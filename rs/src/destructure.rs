@@ -0,0 +1,266 @@
+//! Destructuring of the names `let*`, `loop*` and `fn*` bind.
+//!
+//! A pattern is either a name, a vector `[a b & rest :as all]` binding the
+//! items of a seq, or a map `{:keys [x y] :strs [s] :or {y 0} :as m}` or
+//! `{sym :key}` binding the values of a map, nested in vectors as deep as
+//! needed. `analyze` expands a pattern to plain bindings of its names, and
+//! of temps holding the values they are taken from, so nothing else needs
+//! to know about patterns. The calls taking the values apart keep their
+//! pattern as their source, which is what backtraces show of them.
+
+use failure::Fallible;
+use printer::pr_str;
+use std::collections::{HashMap, LinkedList};
+use std::rc::Rc;
+use symbol::Sym;
+use trace;
+use types::{HashKey, InnerMalType, MalType};
+
+/// Names the temps of the patterns expanded by one analysis apart. A name
+/// has a space in it, so the reader never makes one that could refer to it.
+#[derive(Default)]
+pub struct Temps(usize);
+
+impl Temps {
+    pub fn fresh(&mut self, prefix: &str) -> Sym {
+        self.0 += 1;
+        Sym::new(&format!("{} {}", prefix, self.0))
+    }
+}
+
+/// Plain bindings of names to forms, in order.
+pub type Bindings = Vec<(Sym, MalType)>;
+
+fn symbol(name: &str) -> MalType {
+    new_mal!(Symbol(Sym::new(name)))
+}
+
+/// A call of the builtin `name`, which no local can shadow, taking apart
+/// the value of `pattern`.
+fn call(pattern: &MalType, name: &str, args: Vec<MalType>) -> MalType {
+    let mut items: LinkedList<MalType> = args.into_iter().collect();
+    items.push_front(symbol(&format!("mal.core/{}", name)));
+    let form = new_mal!(List(items, new_mal!(Nil)));
+    trace::set_source(&form, pattern);
+    form
+}
+
+fn is_keyword(form: &MalType, name: &str) -> bool {
+    match **form {
+        InnerMalType::Keyword(k) => k == name,
+        _ => false,
+    }
+}
+
+/// Whether `bindings` of a `let*` or `loop*`, or `params` of a `fn*`,
+/// have only plain names.
+pub fn is_plain(names: &[MalType]) -> bool {
+    names.iter().all(|name| name.is_symbol())
+}
+
+/// Adds to `out` the bindings binding `pattern` to the value of `form`.
+pub fn expand(
+    pattern: &MalType,
+    form: MalType,
+    temps: &mut Temps,
+    out: &mut Bindings,
+) -> Fallible<()> {
+    match **pattern {
+        InnerMalType::Symbol(name) if name != "&" => out.push((name, form)),
+        InnerMalType::Vec(ref items, _) => {
+            let items: Vec<_> = items.iter().collect();
+            expand_seq(pattern, &items, form, temps, out)?;
+        }
+        InnerMalType::Hashmap(ref mapping, _) => expand_map(pattern, mapping, form, temps, out)?,
+        _ => bail!(
+            "binding pattern should be symbol, vector or map, got {}",
+            pr_str(pattern, true)
+        ),
+    }
+    Ok(())
+}
+
+/// Binds the items of a seq: each pattern before `&` to the next item, the
+/// one after it to the seq of those left, or nil, and the name after `:as`
+/// to the seq itself.
+fn expand_seq(
+    pattern: &MalType,
+    items: &[&MalType],
+    form: MalType,
+    temps: &mut Temps,
+    out: &mut Bindings,
+) -> Fallible<()> {
+    let (items, all) = match items.iter().position(|item| is_keyword(item, ":as")) {
+        Some(at) => {
+            ensure!(
+                at + 2 == items.len() && items[at + 1].is_symbol(),
+                ":as should be followed by a name, last in a vector pattern"
+            );
+            (&items[..at], Some(items[at + 1].to_symbol()))
+        }
+        None => (items, None),
+    };
+    let (items, rest) = match items
+        .iter()
+        .position(|item| item.is_symbol() && item.to_symbol() == "&")
+    {
+        Some(at) => {
+            ensure!(
+                at + 2 == items.len(),
+                "& should be followed by one pattern in a vector pattern"
+            );
+            (&items[..at], Some(items[at + 1]))
+        }
+        None => (items, None),
+    };
+
+    let value = temps.fresh("vec");
+    out.push((value, form));
+    if let Some(all) = all {
+        out.push((all, new_mal!(Symbol(value))));
+    }
+    let mut seq = value;
+    for (i, item) in items.iter().enumerate() {
+        expand(
+            item,
+            call(pattern, "first", vec![new_mal!(Symbol(seq))]),
+            temps,
+            out,
+        )?;
+        if i + 1 < items.len() || rest.is_some() {
+            let next = temps.fresh("seq");
+            out.push((next, call(pattern, "rest", vec![new_mal!(Symbol(seq))])));
+            seq = next;
+        }
+    }
+    if let Some(rest) = rest {
+        expand(
+            rest,
+            call(pattern, "seq", vec![new_mal!(Symbol(seq))]),
+            temps,
+            out,
+        )?;
+    }
+    Ok(())
+}
+
+/// Binds the values of a map: the names of `:keys` and `:strs` to those of
+/// the keywords and strings they name, each other name to that of the key
+/// it maps to, with the defaults of `:or` for keys it lacks, and the name
+/// of `:as` to the map itself. A list, like the rest args of a `fn*`, is
+/// made a map of its keys and values first.
+fn expand_map(
+    pattern: &MalType,
+    mapping: &HashMap<HashKey, MalType>,
+    form: MalType,
+    temps: &mut Temps,
+    out: &mut Bindings,
+) -> Fallible<()> {
+    let special = |name: &str| mapping.get(&HashKey::Keyword(Sym::new(name)));
+    let defaults = match special(":or") {
+        Some(or) => {
+            ensure!(
+                or.is_hashmap()
+                    && or
+                        .to_hashmap_ref()
+                        .keys()
+                        .all(|k| k.to_mal_type().is_symbol()),
+                ":or should be a map of names to defaults"
+            );
+            or.to_hashmap_ref().clone()
+        }
+        None => Default::default(),
+    };
+
+    // the names bound and the keys they are looked up by, sorted so that
+    // they are bound in the same order each time
+    let mut lookups = Vec::new();
+    for (key, value) in mapping {
+        match *key {
+            HashKey::Keyword(k) if k == ":keys" || k == ":strs" => {
+                ensure!(
+                    (value.is_vec() || value.is_list())
+                        && value.to_items_ref().iter().all(|name| name.is_symbol()),
+                    "{} should be a vector of names",
+                    k
+                );
+                for name in value.to_items_ref() {
+                    let name = name.to_symbol();
+                    let key = if k == ":keys" {
+                        new_mal!(Keyword(Sym::new(&format!(":{}", name))))
+                    } else {
+                        new_mal!(String(name.to_string()))
+                    };
+                    lookups.push((name, key));
+                }
+            }
+            HashKey::Keyword(k) if k == ":or" => {}
+            HashKey::Keyword(k) if k == ":as" => {
+                ensure!(value.is_symbol(), ":as should be followed by a name");
+            }
+            HashKey::Symbol(name) if name != "&" => lookups.push((name, value.clone())),
+            _ => bail!(
+                "map pattern keys should be names, :keys, :strs, :or or :as, got {}",
+                pr_str(&key.to_mal_type(), true)
+            ),
+        }
+    }
+    lookups.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+    if let Some(name) = defaults
+        .keys()
+        .map(|k| k.to_mal_type().to_symbol())
+        .find(|name| !lookups.iter().any(|(bound, _)| bound == name))
+    {
+        bail!(
+            ":or has a default for {}, which the map pattern does not bind",
+            name
+        );
+    }
+
+    let value = temps.fresh("map");
+    out.push((value, form));
+    let map = new_mal!(Symbol(value));
+    let as_map = new_mal!(List(
+        linked_list![
+            symbol("if"),
+            call(pattern, "list?", vec![map.clone()]),
+            call(
+                pattern,
+                "apply",
+                vec![symbol("mal.core/hash-map"), map.clone()]
+            ),
+            map.clone()
+        ],
+        new_mal!(Nil)
+    ));
+    trace::set_source(&as_map, pattern);
+    out.push((value, as_map));
+    if let Some(all) = special(":as") {
+        out.push((all.to_symbol(), map.clone()));
+    }
+    for (name, key) in lookups {
+        let mut args = vec![map.clone(), key];
+        if let Some(default) = defaults.get(&HashKey::Symbol(name)) {
+            args.push(default.clone());
+        }
+        out.push((name, call(pattern, "get", args)));
+    }
+    Ok(())
+}
+
+/// The plain params of a `fn*` of `params`, and the bindings destructuring
+/// the args bound to them in its body, unless all are plain.
+pub fn expand_params(params: &[MalType], temps: &mut Temps) -> Fallible<(Vec<MalType>, Bindings)> {
+    let mut plain = Vec::with_capacity(params.len());
+    let mut bindings = Vec::new();
+    for param in params {
+        if param.is_symbol() {
+            plain.push(param.clone());
+            continue;
+        }
+        let arg = temps.fresh("p");
+        plain.push(new_mal!(Symbol(arg)));
+        expand(param, new_mal!(Symbol(arg)), temps, &mut bindings)?;
+    }
+    Ok((plain, bindings))
+}
@@ -0,0 +1,259 @@
+use arity::parse_fn;
+use env::{env_find, env_get, env_get_meta, env_keys, Env};
+use error::NotFound;
+use failure::Fallible;
+use printer::pr_str;
+use reader::read_str;
+use regex::Regex;
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{HashKey, InnerMalType, MalType};
+
+/// The optional parts of `(def! name doc? attr-map? value)`.
+pub struct DefForm {
+    pub name: Sym,
+    pub doc: Option<MalType>,
+    pub attrs: Option<MalType>,
+    pub value: MalType,
+}
+
+pub fn parse_def(form: &str, mut list: LinkedList<MalType>) -> Fallible<DefForm> {
+    ensure_arity!(
+        list.len() >= 2 && list.len() <= 4,
+        "{} should have 2 to 4 params",
+        form
+    );
+    let (name, name_meta) = name_with_meta(list.pop_front().unwrap());
+    ensure_type!(name.is_symbol(), "{}'s first param should be symbol", form);
+    let value = list.pop_back().unwrap();
+    let doc = if list.front().map(|d| d.is_string()) == Some(true) {
+        list.pop_front()
+    } else {
+        None
+    };
+    let attrs = if list.front().map(|a| a.is_hashmap()) == Some(true) {
+        list.pop_front()
+    } else {
+        None
+    };
+    ensure!(
+        list.is_empty(),
+        "{} expects (name docstring? attr-map? value)",
+        form
+    );
+    let attrs = match (name_meta, attrs) {
+        (Some(meta), Some(attrs)) => {
+            let mut merged = meta.to_hashmap();
+            merged.extend(attrs.to_hashmap());
+            Some(new_mal!(Hashmap(merged, new_mal!(Nil))))
+        }
+        (meta, attrs) => attrs.or(meta),
+    };
+    Ok(DefForm {
+        name: name.to_symbol(),
+        doc,
+        attrs,
+        value,
+    })
+}
+
+/// Splits a name read as `^meta name` into the name and its attribute map;
+/// `^:flag` is short for `^{:flag true}`.
+fn name_with_meta(name: MalType) -> (MalType, Option<MalType>) {
+    let is_with_meta = name.is_list()
+        && name.len() == 3
+        && name.get_first_symbol().map(|s| s.to_symbol()) == Some(Sym::new("with-meta"));
+    if !is_with_meta {
+        return (name, None);
+    }
+    let mut items = name.to_items();
+    items.pop_front();
+    let name = items.pop_front().unwrap();
+    let meta = items.pop_front().unwrap();
+    let meta = match *meta {
+        InnerMalType::Keyword(k) => {
+            let mut map = HashMap::new();
+            map.insert(HashKey::Keyword(k), new_mal!(Bool(true)));
+            new_mal!(Hashmap(map, new_mal!(Nil)))
+        }
+        InnerMalType::Hashmap(..) => meta.clone(),
+        _ => return (new_mal!(Nil), None),
+    };
+    (name, Some(meta))
+}
+
+fn keyword(name: &str) -> HashKey {
+    HashKey::Keyword(Sym::new(&format!(":{}", name)))
+}
+
+/// Builds the binding metadata for a `def!`/`defmacro!`. `attrs` is the
+/// already evaluated attribute map.
+pub fn def_meta(def: &DefForm, attrs: Option<MalType>, source: String, env: &Env) -> MalType {
+    let mut meta = attrs.map(|a| a.to_hashmap()).unwrap_or_default();
+    meta.insert(keyword("name"), new_mal!(Symbol(def.name)));
+    if let Some(ref doc) = def.doc {
+        meta.insert(keyword("doc"), doc.clone());
+    }
+    if let Some(arglists) = arglists(&def.value) {
+        meta.insert(keyword("arglists"), arglists);
+    }
+    if let Some(file) = env_get(env.clone(), "*file*") {
+        meta.insert(keyword("file"), file);
+    }
+    meta.insert(keyword("source"), new_mal!(String(source)));
+    new_mal!(Hashmap(meta, new_mal!(Nil)))
+}
+
+/// `((a b))` for a value form like `(fn* (a b) ...)`, or the params of
+/// each clause of a `fn*` of several, as written, which an analyzed `fn*`
+/// keeps as its metadata.
+fn arglists(value: &MalType) -> Option<MalType> {
+    let value = match **value {
+        InnerMalType::List(_, ref written) if written.is_list() => written,
+        _ => value,
+    };
+    let items = match **value {
+        InnerMalType::List(ref l, ..) => l,
+        _ => return None,
+    };
+    match items.front() {
+        Some(f) if f.is_symbol() && f.to_symbol() == "fn*" => {}
+        _ => return None,
+    }
+    let clauses = parse_fn(items.iter().skip(1)).ok()?.clauses;
+    let arglists = clauses.into_iter().map(|clause| clause.params).collect();
+    Some(new_mal!(List(arglists, new_mal!(Nil))))
+}
+
+/// Metadata for a binding defined other than by `def!`.
+pub fn binding_meta(
+    name: &str,
+    doc: Option<MalType>,
+    arglists: Option<MalType>,
+    source: Option<String>,
+) -> MalType {
+    let mut meta = HashMap::new();
+    meta.insert(keyword("name"), new_mal!(Symbol(Sym::new(name))));
+    if let Some(doc) = doc {
+        meta.insert(keyword("doc"), doc);
+    }
+    if let Some(arglists) = arglists {
+        meta.insert(keyword("arglists"), arglists);
+    }
+    if let Some(source) = source {
+        meta.insert(keyword("source"), new_mal!(String(source)));
+    }
+    new_mal!(Hashmap(meta, new_mal!(Nil)))
+}
+
+/// Metadata for a built-in var which `binding` may rebind.
+pub fn dynamic_meta(name: &str, doc: &str) -> MalType {
+    let mut meta = binding_meta(name, Some(new_mal!(String(doc.to_string()))), None, None)
+        .to_hashmap();
+    meta.insert(keyword("dynamic"), new_mal!(Bool(true)));
+    new_mal!(Hashmap(meta, new_mal!(Nil)))
+}
+
+/// Metadata for a built-in registered in `Ns`.
+pub fn native_meta(name: &str, arglists: &str, doc: &str) -> MalType {
+    binding_meta(
+        name,
+        Some(new_mal!(String(doc.to_string()))),
+        Some(read_str(&format!("({})", arglists)).expect("parse arglists")),
+        None,
+    )
+}
+
+fn meta_get(meta: &MalType, key: &str) -> Option<MalType> {
+    if !meta.is_hashmap() {
+        return None;
+    }
+    meta.to_hashmap_ref().get(&keyword(key)).cloned()
+}
+
+/// Renders documentation the way `(doc sym)` prints it.
+pub fn format_doc(name: &str, meta: &MalType) -> String {
+    let mut s = String::from("-------------------------\n");
+    s.push_str(name);
+    s.push('\n');
+    if let Some(arglists) = meta_get(meta, "arglists") {
+        s.push_str(&pr_str(&arglists, true));
+        s.push('\n');
+    }
+    if meta_get(meta, "macro").map(|m| *m == InnerMalType::Bool(true)) == Some(true) {
+        s.push_str("Macro\n");
+    }
+    if let Some(doc) = meta_get(meta, "doc") {
+        s.push_str("  ");
+        s.push_str(&pr_str(&doc, false));
+        s.push('\n');
+    }
+    s
+}
+
+pub fn doc(env: &Env, name: &str) -> Fallible<String> {
+    if env_find(env.clone(), name).is_none() {
+        return Err(NotFound(name.to_string()).into());
+    }
+    let meta = env_get_meta(env.clone(), name).unwrap_or_else(|| new_mal!(Nil));
+    Ok(format_doc(name, &meta))
+}
+
+pub fn source(env: &Env, name: &str) -> Fallible<String> {
+    if env_find(env.clone(), name).is_none() {
+        return Err(NotFound(name.to_string()).into());
+    }
+    Ok(env_get_meta(env.clone(), name)
+        .and_then(|meta| meta_get(&meta, "source"))
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "Source not found".to_string()))
+}
+
+fn to_regex(pattern: &MalType, literal: bool) -> Fallible<Regex> {
+    if pattern.is_regex() {
+        return Ok(pattern.to_regex());
+    }
+    ensure_type!(pattern.is_string(), "{:?} should be a string or regex", pattern);
+    if literal {
+        return Ok(Regex::new(&regex::escape(&pattern.to_string()))?);
+    }
+    Ok(Regex::new(&pattern.to_string())?)
+}
+
+fn sorted_names(env: &Env) -> Vec<String> {
+    let mut names = env_keys(env.clone());
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Names visible from `env` containing `pattern` (a string or regex).
+pub fn apropos(env: &Env, pattern: &MalType) -> Fallible<MalType> {
+    let re = to_regex(pattern, true)?;
+    Ok(new_mal!(List(
+        sorted_names(env)
+            .into_iter()
+            .filter(|name| re.is_match(name))
+            .map(|name| new_mal!(Symbol(Sym::new(&name))))
+            .collect(),
+        new_mal!(Nil)
+    )))
+}
+
+/// Documentation of every binding whose name or docstring matches `pattern`.
+pub fn find_doc(env: &Env, pattern: &MalType) -> Fallible<String> {
+    let re = to_regex(pattern, false)?;
+    let mut s = String::new();
+    for name in sorted_names(env) {
+        let meta = env_get_meta(env.clone(), &name).unwrap_or_else(|| new_mal!(Nil));
+        let doc_matches = meta_get(&meta, "doc")
+            .map(|doc| doc.is_string() && re.is_match(&doc.to_string()))
+            == Some(true);
+        if doc_matches || re.is_match(&name) {
+            s.push_str(&format_doc(&name, &meta));
+        }
+    }
+    Ok(s)
+}
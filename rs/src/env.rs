@@ -1,41 +1,176 @@
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 use std::cell::RefCell;
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::rc::Rc;
+use symbol::Sym;
 use types::MalType;
 
-#[derive(Clone, Debug, PartialEq)]
+/// A binding made by name, by `def!` or for a built-in. References to
+/// globals cache it and still see later `def!`s, and namespaces which refer
+/// it share it.
+#[derive(Debug, PartialEq)]
+pub struct Binding {
+    // the root value, which `def!` sets
+    pub value: RefCell<MalType>,
+    pub meta: RefCell<Option<MalType>>,
+    // values given by the `binding`s being evaluated, innermost last,
+    // which hide the root value
+    pub dynamic: RefCell<Vec<MalType>>,
+}
+
+impl Binding {
+    fn new(value: MalType) -> Rc<Binding> {
+        Rc::new(Binding {
+            value: RefCell::new(value),
+            meta: RefCell::new(None),
+            dynamic: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn get(&self) -> MalType {
+        if let Some(value) = self.dynamic.borrow().last() {
+            return value.clone();
+        }
+        self.value.borrow().clone()
+    }
+
+    /// Rebinds it to `value` until `pop` is called, leaving its root alone.
+    pub fn push(&self, value: MalType) {
+        self.dynamic.borrow_mut().push(value);
+    }
+
+    pub fn pop(&self) -> Option<MalType> {
+        self.dynamic.borrow_mut().pop()
+    }
+
+    /// Sets the value of its innermost `binding`, or else its root.
+    pub fn set(&self, value: MalType) {
+        match self.dynamic.borrow_mut().last_mut() {
+            Some(dynamic) => *dynamic = value,
+            None => *self.value.borrow_mut() = value,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct EnvStruct {
-    data: RefCell<FnvHashMap<String, MalType>>,
+    // Parameters of fn*, bindings of let* and catch*, in the order the
+    // analyzer numbers them.
+    slots: RefCell<Vec<(Sym, MalType)>>,
+    data: RefCell<FnvHashMap<Sym, Rc<Binding>>>,
+    // names in `data` referred from another namespace
+    referred: RefCell<FnvHashSet<Sym>>,
     outer: Option<Rc<EnvStruct>>,
 }
 
 pub type Env = Rc<EnvStruct>;
 
-pub fn env_new(outer: Option<Env>, binds: Vec<String>, exprs: Vec<MalType>) -> Env {
-    let env = Rc::new(EnvStruct {
+/// Envs are only equal to themselves: comparing their bindings could
+/// recurse through the closures defined in them.
+impl PartialEq for EnvStruct {
+    fn eq(&self, other: &EnvStruct) -> bool {
+        self as *const EnvStruct == other as *const EnvStruct
+    }
+}
+
+pub fn env_new<B: IntoIterator<Item = Sym>>(
+    outer: Option<Env>,
+    binds: B,
+    exprs: Vec<MalType>,
+) -> Env {
+    Rc::new(EnvStruct {
+        slots: RefCell::new(binds.into_iter().zip(exprs).collect()),
         data: RefCell::new(FnvHashMap::default()),
+        referred: RefCell::new(FnvHashSet::default()),
         outer,
-    });
+    })
+}
+
+/// Adds a slot to `env`, shadowing earlier slots of the same name.
+pub fn env_bind(env: &Env, key: Sym, value: MalType) {
+    env.slots.borrow_mut().push((key, value));
+}
 
-    for (k, v) in binds.into_iter().zip(exprs) {
-        env_set(env.clone(), k, v);
+pub fn env_set<K: Into<Sym>>(env: Env, key: K, value: MalType) {
+    let key = key.into();
+    if let Some(slot) = env
+        .slots
+        .borrow_mut()
+        .iter_mut()
+        .rev()
+        .find(|(k, _)| *k == key)
+    {
+        slot.1 = value;
+        return;
+    }
+    // defining a referred name makes a binding of its own
+    if env.referred.borrow_mut().remove(&key) {
+        env.data.borrow_mut().insert(key, Binding::new(value));
+        return;
+    }
+    let mut data = env.data.borrow_mut();
+    if let Some(binding) = data.get(&key) {
+        *binding.value.borrow_mut() = value;
+        return;
     }
+    data.insert(key, Binding::new(value));
+}
 
-    env
+/// Metadata of a binding, e.g. the docstring given to `def!`. Only bindings
+/// made with `def!`/`defmacro!` or registered from `Ns` carry any.
+pub fn env_set_meta<K: Into<Sym>>(env: Env, key: K, meta: MalType) {
+    if let Some(binding) = env.data.borrow().get(&key.into()) {
+        *binding.meta.borrow_mut() = Some(meta);
+    }
 }
 
-pub fn env_set(env: Env, key: String, value: MalType) {
-    env.data.borrow_mut().insert(key, value);
+pub fn env_get_meta<K: Into<Sym>>(env: Env, key: K) -> Option<MalType> {
+    let key = key.into();
+    env_find(env, key)
+        .and_then(|env| env.data.borrow().get(&key).cloned())
+        .and_then(|binding| binding.meta.borrow().clone())
 }
 
-pub fn env_get(mut env: Env, key: &str) -> Option<MalType> {
-    loop {
-        if let Some(v) = env.data.borrow().get(key) {
+/// The binding `env` itself holds for `key`.
+pub fn env_binding(env: &Env, key: Sym) -> Option<Rc<Binding>> {
+    env.data.borrow().get(&key).cloned()
+}
+
+/// Shares `binding`, made in another namespace, as `key` in `env`.
+pub fn env_refer(env: &Env, key: Sym, binding: Rc<Binding>) {
+    env.data.borrow_mut().insert(key, binding);
+    env.referred.borrow_mut().insert(key);
+}
+
+/// The bindings made in `env` itself rather than referred into it.
+pub fn env_interns(env: &Env) -> Vec<(Sym, Rc<Binding>)> {
+    let referred = env.referred.borrow();
+    env.data
+        .borrow()
+        .iter()
+        .filter(|(k, _)| !referred.contains(k))
+        .map(|(k, b)| (*k, b.clone()))
+        .collect()
+}
+
+impl EnvStruct {
+    fn lookup(&self, key: Sym) -> Option<MalType> {
+        if let Some((_, v)) = self.slots.borrow().iter().rev().find(|(k, _)| *k == key) {
             return Some(v.clone());
         }
+        self.data.borrow().get(&key).map(|b| b.get())
+    }
+}
+
+/// Returns the innermost env which binds `key`.
+pub fn env_find<K: Into<Sym>>(mut env: Env, key: K) -> Option<Env> {
+    let key = key.into();
+    loop {
+        if env.lookup(key).is_some() {
+            return Some(env);
+        }
         if let Some(e) = env.outer.clone() {
             env = e;
         } else {
@@ -44,6 +179,135 @@ pub fn env_get(mut env: Env, key: &str) -> Option<MalType> {
     }
 }
 
+/// Names visible from `env`, innermost bindings first.
+pub fn env_keys(mut env: Env) -> Vec<String> {
+    let mut keys = Vec::new();
+    loop {
+        keys.extend(env.slots.borrow().iter().rev().map(|(k, _)| k.to_string()));
+        keys.extend(env.data.borrow().keys().map(|k| k.to_string()));
+        if let Some(e) = env.outer.clone() {
+            env = e;
+        } else {
+            return keys;
+        }
+    }
+}
+
+pub fn env_get<K: Into<Sym>>(mut env: Env, key: K) -> Option<MalType> {
+    let key = key.into();
+    loop {
+        if let Some(v) = env.lookup(key) {
+            return Some(v);
+        }
+        if let Some(e) = env.outer.clone() {
+            env = e;
+        } else {
+            return None;
+        }
+    }
+}
+
+/// The value of slot `slot` of the env `depth` levels out from `env`.
+pub fn env_get_slot(env: &Env, depth: usize, slot: usize) -> MalType {
+    let mut env = env;
+    for _ in 0..depth {
+        env = env.outer.as_ref().expect("analyzed env depth");
+    }
+    env.slots.borrow()[slot].1.clone()
+}
+
+/// Looks up a name the analyzer did not bind lexically. Envs between `env`
+/// and the root only hold such a name if it was `def!`ed in them, so only
+/// their non-empty maps are searched before the root binding, which is
+/// cached in `cache`.
+pub fn env_get_global(
+    env: &Env,
+    key: Sym,
+    cache: &RefCell<Option<Rc<Binding>>>,
+) -> Option<MalType> {
+    let mut e = env;
+    while let Some(ref outer) = e.outer {
+        {
+            let data = e.data.borrow();
+            if !data.is_empty() {
+                if let Some(b) = data.get(&key) {
+                    return Some(b.get());
+                }
+            }
+        }
+        e = outer;
+    }
+    if let Some(ref b) = *cache.borrow() {
+        return Some(b.get());
+    }
+    let found = e.data.borrow().get(&key).cloned();
+    match found {
+        Some(b) => {
+            let v = b.get();
+            *cache.borrow_mut() = Some(b);
+            Some(v)
+        }
+        // e.g. a let* binding referred to before it was bound
+        None => env_get(env.clone(), key),
+    }
+}
+
+/// Slot names of every env but the root, innermost first, with the names
+/// bound in it by name, like by `def!` or `env-set!`.
+pub fn env_frames(mut env: Env) -> Vec<(Vec<Sym>, Vec<Sym>)> {
+    let mut frames = Vec::new();
+    while let Some(e) = env.outer.clone() {
+        frames.push((
+            env.slots.borrow().iter().map(|(k, _)| *k).collect(),
+            env.data.borrow().keys().cloned().collect(),
+        ));
+        env = e;
+    }
+    frames
+}
+
+/// Names bound in `env` itself: its slots in order, then its other
+/// bindings by name.
+pub fn env_names(env: &Env) -> Vec<Sym> {
+    let mut names: Vec<Sym> = env.slots.borrow().iter().map(|(k, _)| *k).collect();
+    let mut data: Vec<Sym> = env.data.borrow().keys().cloned().collect();
+    data.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    names.extend(data);
+    names
+}
+
+pub fn env_outer(env: &Env) -> Option<Env> {
+    env.outer.clone()
+}
+
+/// The handles `env` owns: its outer env, slot values and bindings. Parts
+/// being mutated are left out.
+pub fn env_refs(env: &Env) -> (Option<Env>, Vec<MalType>, Vec<Rc<Binding>>) {
+    let slots = match env.slots.try_borrow() {
+        Ok(slots) => slots.iter().map(|(_, v)| v.clone()).collect(),
+        Err(_) => Vec::new(),
+    };
+    let data = match env.data.try_borrow() {
+        Ok(data) => data.values().cloned().collect(),
+        Err(_) => Vec::new(),
+    };
+    (env.outer.clone(), slots, data)
+}
+
+/// Unbinds everything in `env`, returning what it held so the caller
+/// decides when it is dropped.
+pub fn env_clear(env: &Env) -> (Vec<(Sym, MalType)>, FnvHashMap<Sym, Rc<Binding>>) {
+    let slots = match env.slots.try_borrow_mut() {
+        Ok(mut slots) => slots.drain(..).collect(),
+        Err(_) => Vec::new(),
+    };
+    let data = match env.data.try_borrow_mut() {
+        Ok(mut data) => data.drain().collect(),
+        Err(_) => FnvHashMap::default(),
+    };
+    (slots, data)
+}
+
 pub fn env_root(mut env: Env) -> Env {
     while let Some(e) = env.outer.clone() {
         env = e;
@@ -53,7 +317,7 @@ pub fn env_root(mut env: Env) -> Env {
 
 impl Display for EnvStruct {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{:#?}", self.data.borrow());
+        write!(f, "{:#?} {:#?}", self.slots.borrow(), self.data.borrow());
         Ok(())
     }
 }
@@ -1,7 +1,213 @@
+use failure::{Error, Fail};
+use fnv::FnvHashMap;
+use serde::{de, ser};
+use std::cell::RefCell;
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use types::MalType;
+
 #[derive(Fail, Debug)]
 #[fail(display = "Comment found error")]
 pub struct CommentFoundError;
 
-#[derive(Fail, Debug)]
-#[fail(display = "Mal Exception Error")]
-pub struct MalExceptionError(pub String);
+/// A mal value thrown by `throw`. The value stays in a slot of the thread
+/// it was thrown on, freed with the error, which only holds its id and how
+/// it printed.
+#[derive(Debug)]
+pub struct MalExceptionError {
+    id: usize,
+    printed: String,
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THROWN: RefCell<FnvHashMap<usize, MalType>> = RefCell::new(FnvHashMap::default());
+}
+
+impl MalExceptionError {
+    pub fn new(value: MalType, printed: String) -> MalExceptionError {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        THROWN.with(|thrown| thrown.borrow_mut().insert(id, value));
+        MalExceptionError { id, printed }
+    }
+
+    /// The value thrown, unless this is not the thread it was thrown on.
+    pub fn value(&self) -> Option<MalType> {
+        THROWN.with(|thrown| thrown.borrow().get(&self.id).cloned())
+    }
+
+    pub fn printed(&self) -> &str {
+        &self.printed
+    }
+}
+
+impl Drop for MalExceptionError {
+    fn drop(&mut self) {
+        let _ = THROWN.try_with(|thrown| thrown.borrow_mut().remove(&self.id));
+    }
+}
+
+impl fmt::Display for MalExceptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Exception: {}", self.printed)
+    }
+}
+
+impl Fail for MalExceptionError {}
+
+/// A fn or form given a number of params it does not take.
+#[derive(Debug)]
+pub struct ArityError(pub String);
+
+impl fmt::Display for ArityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Fail for ArityError {}
+
+/// A value of a type a fn or form does not take.
+#[derive(Debug)]
+pub struct TypeError(pub String);
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Fail for TypeError {}
+
+/// A name, which it holds, bound nowhere it is looked up.
+#[derive(Debug)]
+pub struct NotFound(pub String);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' not found", self.0)
+    }
+}
+
+impl Fail for NotFound {}
+
+/// Like `ensure!`, failing with an `ArityError`.
+macro_rules! ensure_arity {
+    ($cond:expr, $($arg:tt)+) => {
+        if !($cond) {
+            return Err($crate::error::ArityError(format!($($arg)+)).into());
+        }
+    };
+}
+
+/// Like `ensure!`, failing with a `TypeError`.
+macro_rules! ensure_type {
+    ($cond:expr, $($arg:tt)+) => {
+        if !($cond) {
+            return Err($crate::error::TypeError(format!($($arg)+)).into());
+        }
+    };
+}
+
+/// Like `bail!`, failing with a `TypeError`.
+macro_rules! bail_type {
+    ($($arg:tt)+) => {
+        return Err($crate::error::TypeError(format!($($arg)+)).into())
+    };
+}
+
+/// A mal value that could not be converted to a Rust value. `path` leads
+/// from the converted value to the offending one, like `.items[2].name`.
+#[derive(Debug)]
+pub struct ConversionError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{} at {}", self.message, self.path)
+        }
+    }
+}
+
+impl Fail for ConversionError {}
+
+/// Evaluation nested deeper than the max depth, which it holds.
+#[derive(Debug)]
+pub struct StackOverflow(pub usize);
+
+impl fmt::Display for StackOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StackOverflow: evaluation nested deeper than {} frames", self.0)
+    }
+}
+
+impl Fail for StackOverflow {}
+
+/// An error converting between mal values and serde's data model.
+#[derive(Debug)]
+pub struct SerdeError(pub String);
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl StdError for SerdeError {}
+
+impl ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> SerdeError {
+        SerdeError(msg.to_string())
+    }
+}
+
+impl de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> SerdeError {
+        SerdeError(msg.to_string())
+    }
+}
+
+/// Where a form was read from.
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A call an error was raised in: the name of the fn called, the form
+/// calling it and where that was read from, if known.
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    pub name: String,
+    pub form: String,
+    pub position: Option<Position>,
+}
+
+/// An error and the calls it was raised in, innermost first. Past the
+/// first few only their number is kept, in `elided`.
+#[derive(Debug)]
+pub struct Traced {
+    pub error: Error,
+    pub stack: Vec<TraceFrame>,
+    pub elided: usize,
+}
+
+impl fmt::Display for Traced {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Fail for Traced {
+    fn cause(&self) -> Option<&dyn Fail> {
+        Some(self.error.as_fail())
+    }
+}
@@ -0,0 +1,1095 @@
+use analyze::{analyze, is_special_form, macroexpand, quasiquote};
+use arity::{self, arity_error, parse_fn, Clause, FnForm};
+use compile::compile_fn;
+use console;
+use core::{apply_params, is_apply};
+use doc::{self, DefForm};
+use env::{
+    env_bind, env_get, env_get_global, env_get_slot, env_new, env_set, env_set_meta, Binding, Env,
+};
+use error::NotFound;
+use exception::{caught, parse_try, selects, Catch, TryForm};
+use failure::{Error, Fallible};
+use gc;
+use lazy::{seq_to_list, LazySeq};
+use namespace;
+use printer::try_pr_str;
+use protocol;
+use protocol::Protocol;
+use stack::{self, Frames};
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::rc::Rc;
+use symbol::Sym;
+use trace;
+use types::{Closure, ClosureEnv, HashKey, InnerMalType, Kind, MalType, Var};
+use vm;
+
+pub fn call_for_closure(params: LinkedList<MalType>, c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    ensure!(c_env.is_some(), "closure env should be available");
+    let c_env = c_env.unwrap();
+    if c_env.proto.is_some() {
+        return vm::call(&c_env, params);
+    }
+    let new_env = bind_params(&c_env, params)?;
+    eval(c_env.body.clone(), new_env)
+}
+
+/// The env of a call of the closure, binding its params to `params`.
+fn bind_params(c_env: &ClosureEnv, mut params: LinkedList<MalType>) -> Fallible<Env> {
+    let binds = &c_env.binds;
+    let exprs = if c_env.variadic {
+        let idx = binds.len() - 1;
+        if params.len() < idx {
+            return Err(arity_error(c_env, params.len()));
+        }
+        let varargs = params.split_off(idx);
+        let mut exprs: Vec<MalType> = params.into_iter().collect();
+        exprs.push(new_mal!(List(varargs, new_mal!(Nil))));
+        exprs
+    } else if params.len() != binds.len() {
+        return Err(arity_error(c_env, params.len()));
+    } else {
+        params.into_iter().collect()
+    };
+    Ok(env_new(Some(c_env.env.clone()), binds.iter().cloned(), exprs))
+}
+
+/// The closure of a clause of `params` and `body` of the `fn*` `name`,
+/// closing over `env`.
+fn new_closure(params: MalType, body: MalType, name: Option<Sym>, env: &Env) -> Fallible<Closure> {
+    ensure!(
+        (params.is_list() || params.is_vec())
+            && params.to_items_ref().iter().all(|p| p.is_symbol()),
+        "fn*'s params should be symbols"
+    );
+    let binds = params.to_symbol_list();
+    if let Some(idx) = binds.iter().position(|e| *e == "&") {
+        ensure!(binds.len() == idx + 2, "& must be followed by a param name");
+    }
+    let mut c_env = ClosureEnv::new(params, body, env.clone());
+    c_env.proto = compile_fn(&c_env.parameters, &c_env.body);
+    c_env.name = name;
+    Ok(Closure::new(call_for_closure, Some(c_env)))
+}
+
+/// `env`, or else the env of the current namespace, in which top-level
+/// forms run.
+fn top_env(env: Option<&Env>) -> Env {
+    match env {
+        Some(env) => env.clone(),
+        None => namespace::current().env.clone(),
+    }
+}
+
+/// Evaluates the leading forms of a top-level `do` and returns the last one,
+/// so that each form is analyzed only after the macros and namespace
+/// changes made before it.
+fn eval_leading(mut form: MalType, env: Option<&Env>) -> Fallible<MalType> {
+    loop {
+        form = macroexpand(form, &top_env(env))?;
+        if form.is_lazy_seq() {
+            form = new_mal!(List(seq_to_list(&form)?, new_mal!(Nil)));
+        }
+        if !form.is_list()
+            || form.get_first_symbol().map(|s| s.to_symbol()) != Some(Sym::new("do"))
+        {
+            return Ok(form);
+        }
+        let mut list = form.to_items();
+        list.pop_front();
+        form = list.pop_back().unwrap_or_else(|| new_mal!(Nil));
+        for item in list {
+            eval_top_in(item, env)?;
+        }
+    }
+}
+
+/// Evaluates `form` at top level in `env`, or in the current namespace.
+pub fn eval_top_in(form: MalType, env: Option<&Env>) -> Fallible<MalType> {
+    let form = eval_leading(form, env)?;
+    let env = top_env(env);
+    eval(analyze(form, &env)?, env)
+}
+
+/// Evaluates `form` at top level in the current namespace.
+pub fn eval_top(form: MalType) -> Fallible<MalType> {
+    eval_top_in(form, None)
+}
+
+/// What is left to do with the value of the form being evaluated. Conts
+/// are kept on the heap, so evaluating nested forms does not recurse.
+enum Cont {
+    // a `do` and the number of its items evaluated
+    Do {
+        form: MalType,
+        done: usize,
+        env: Env,
+    },
+    // the clauses of an `if` whose condition is being evaluated
+    If {
+        then_clause: MalType,
+        else_clause: Option<MalType>,
+        env: Env,
+    },
+    // the env of a `let*`, the name the value is bound to and what follows
+    Let {
+        name: Sym,
+        bindings: LinkedList<MalType>,
+        body: MalType,
+        env: Env,
+    },
+    // the body of a `loop*`, which a `recur` evaluates again in a new env
+    // binding the names of the loop in `env`
+    Loop {
+        names: Rc<Vec<Sym>>,
+        body: MalType,
+        env: Env,
+    },
+    // a `def!` of the value, then of its attrs once that is known
+    Def {
+        def: DefForm,
+        form: MalType,
+        value: Option<MalType>,
+        env: Env,
+    },
+    // an `eval` of the form, then in the target env once that is known
+    Eval {
+        form: Option<MalType>,
+        target: Option<MalType>,
+        env: Env,
+    },
+    // a call, list or vector and the values of its items evaluated so far
+    Items {
+        kind: Items,
+        form: MalType,
+        done: LinkedList<MalType>,
+        env: Env,
+    },
+    // the values of a map evaluated so far, the key of this one and those left
+    Map {
+        done: HashMap<HashKey, MalType>,
+        key: HashKey,
+        rest: Vec<(HashKey, MalType)>,
+        env: Env,
+    },
+    // the body of a `try*`, with the clauses it catches errors with and
+    // its `finally` clause
+    Try {
+        catches: Vec<Catch>,
+        finally: Option<MalType>,
+        env: Env,
+    },
+    // a `finally` clause, then what its `try*` came to
+    Finally {
+        outcome: Fallible<MalType>,
+    },
+    // the values of a `binding` evaluated so far and the forms left
+    BindingValues {
+        bindings: Vec<Rc<Binding>>,
+        values: Vec<MalType>,
+        rest: LinkedList<MalType>,
+        body: LinkedList<MalType>,
+        env: Env,
+    },
+    // the body of a `binding` left, which restores the values when left
+    Binding {
+        saved: Rebound,
+        body: LinkedList<MalType>,
+        env: Env,
+    },
+    // the body of a closure called by the form, for backtraces
+    Frame { form: MalType },
+}
+
+#[derive(Clone, Copy)]
+enum Items {
+    Call,
+    Recur,
+    List,
+    Vec,
+}
+
+/// The vars a `binding` pushed values on, which are popped when dropped.
+struct Rebound(Vec<Rc<Binding>>);
+
+impl Drop for Rebound {
+    fn drop(&mut self) {
+        for binding in self.0.drain(..).rev() {
+            binding.pop();
+        }
+    }
+}
+
+/// A form to evaluate, or the value of the one evaluated.
+enum Next {
+    Eval(MalType, Env),
+    Value(MalType),
+}
+
+pub fn eval(mal: MalType, env: Env) -> Fallible<MalType> {
+    stack::grow(|| run(mal, env))
+}
+
+fn run(mal: MalType, env: Env) -> Fallible<MalType> {
+    let mut conts = Frames::new();
+    let mut next = Next::Eval(mal, env);
+    loop {
+        let step = match next {
+            Next::Eval(mal, env) => step(mal, env, &mut conts),
+            Next::Value(value) => match conts.pop() {
+                Some(cont) => resume(cont, value, &mut conts),
+                None => return Ok(value),
+            },
+        };
+        next = match step {
+            Ok(next) => next,
+            Err(e) => unwind(e, &mut conts)?,
+        };
+    }
+}
+
+/// Leaves the conts up to the innermost `try*` with a clause selecting `e`
+/// and evaluates it, or else returns `e`. The calls left add their frames
+/// to it, and the `finally` clauses left are evaluated on the way.
+fn unwind(mut e: Error, conts: &mut Frames<Cont>) -> Fallible<Next> {
+    // the calls left since the last `try*`, innermost first
+    let mut calls = Vec::new();
+    while let Some(cont) = conts.pop() {
+        match cont {
+            Cont::Try {
+                catches,
+                finally,
+                env,
+            } => {
+                e = trace::frames(e, &calls);
+                calls.clear();
+                let exception = caught(&e);
+                let mut selected = None;
+                for catch in catches {
+                    let selects = match catch.selector {
+                        Some(ref selector) => eval(selector.clone(), env.clone())
+                            .and_then(|selector| selects(&selector, &e, &exception)),
+                        None => Ok(true),
+                    };
+                    match selects {
+                        Ok(true) => {
+                            selected = Some(catch);
+                            break;
+                        }
+                        Ok(false) => {}
+                        Err(err) => {
+                            e = err;
+                            break;
+                        }
+                    }
+                }
+                match (selected, finally) {
+                    (Some(catch), finally) => {
+                        let catch_env = env_new(
+                            Some(env.clone()),
+                            vec![catch.name.to_symbol()],
+                            vec![exception],
+                        );
+                        // the `finally` clause is evaluated after the catch
+                        // one too
+                        if finally.is_some() {
+                            conts.push(Cont::Try {
+                                catches: Vec::new(),
+                                finally,
+                                env,
+                            })?;
+                        }
+                        return Ok(Next::Eval(catch.body, catch_env));
+                    }
+                    (None, Some(finally)) => {
+                        conts.push(Cont::Finally { outcome: Err(e) })?;
+                        return next_form(finally, 1, env, conts);
+                    }
+                    (None, None) => {}
+                }
+            }
+            Cont::Frame { form } => calls.push(form),
+            _ => {}
+        }
+    }
+    Err(trace::frames(e, &calls))
+}
+
+/// Evaluates `mal` as far as it can without the value of another form.
+fn step(mut mal: MalType, env: Env, conts: &mut Frames<Cont>) -> Fallible<Next> {
+    if mal.is_lazy_seq() {
+        mal = new_mal!(List(seq_to_list(&mal)?, new_mal!(Nil)));
+    }
+    if !mal.is_list() || mal.is_empty_list() {
+        return eval_ast(mal, env, conts);
+    }
+
+    mal = macroexpand(mal, &env)?;
+    if !mal.is_list() || mal.is_empty_list() {
+        return Ok(Next::Eval(mal, env));
+    }
+
+    let form = mal.clone();
+    let items = form.to_items_ref();
+    let first_mal = items.front().unwrap();
+
+    if first_mal.is_symbol() && is_special_form(&first_mal.to_symbol()) {
+        // `if`, the most frequent form, reads its params in place
+        if first_mal.to_symbol() == "if" {
+            ensure_arity!(items.len() >= 3, "if should have at least 2 params");
+            ensure_arity!(items.len() <= 4, "if should have at most 3 params");
+            let mut params = items.iter().skip(1).cloned();
+            let condition = params.next().unwrap();
+            conts.push(Cont::If {
+                then_clause: params.next().unwrap(),
+                else_clause: params.next(),
+                env: env.clone(),
+            })?;
+            return Ok(Next::Eval(condition, env));
+        }
+
+        let mut list = items.clone();
+        list.pop_front();
+        match first_mal.to_symbol().as_str() {
+            "do" => return next_form(form.clone(), 1, env, conts),
+            "def!" => {
+                let def = doc::parse_def("def!", list)?;
+                let value = def.value.clone();
+                conts.push(Cont::Def {
+                    def,
+                    form: form.clone(),
+                    value: None,
+                    env: env.clone(),
+                })?;
+                return Ok(Next::Eval(value, env));
+            }
+            "let*" => {
+                ensure_arity!(list.len() == 2, "let* should have 2 params");
+                let new_env = env_new(Some(env.clone()), Vec::new(), Vec::new());
+                let binding_list = list.pop_front().unwrap().to_items();
+                ensure_arity!(
+                    binding_list.len().is_multiple_of(2),
+                    "def! binding list should have 2n params"
+                );
+                let body = list.pop_front().unwrap();
+                return next_binding(binding_list, body, new_env, conts);
+            }
+            "loop*" => {
+                ensure_arity!(list.len() == 2, "loop* should have 2 params");
+                let binding_list = list.pop_front().unwrap().to_items();
+                ensure_arity!(
+                    binding_list.len().is_multiple_of(2),
+                    "loop* binding list should have 2n params"
+                );
+                ensure!(
+                    binding_list.iter().step_by(2).all(|name| name.is_symbol()),
+                    "loop*'s names should be symbols"
+                );
+                let body = list.pop_front().unwrap();
+                conts.push(Cont::Loop {
+                    names: Rc::new(
+                        binding_list
+                            .iter()
+                            .step_by(2)
+                            .map(|name| name.to_symbol())
+                            .collect(),
+                    ),
+                    body: body.clone(),
+                    env: env.clone(),
+                })?;
+                let new_env = env_new(Some(env.clone()), Vec::new(), Vec::new());
+                return next_binding(binding_list, body, new_env, conts);
+            }
+            "recur" => {
+                // in place of the `recur` itself, which is no value
+                let done = linked_list![new_mal!(Nil)];
+                return next_item(Items::Recur, form.clone(), done, env, conts);
+            }
+            "fn*" => {
+                let FnForm { name, clauses } = parse_fn(list.iter())?;
+                // a named fn is bound to its name in an env around its clauses
+                let fn_env = match name {
+                    Some(_) => env_new(Some(env.clone()), vec![], vec![]),
+                    None => env.clone(),
+                };
+                let mut closures = Vec::with_capacity(clauses.len());
+                for Clause { params, body } in clauses {
+                    closures.push(new_closure(params, body, name, &fn_env)?);
+                }
+                let f = if closures.len() == 1 {
+                    new_mal!(Closure(closures.pop().unwrap(), new_mal!(Nil)))
+                } else {
+                    arity::new_fn(name, closures)
+                };
+                if let Some(name) = name {
+                    env_bind(&fn_env, name, f.clone());
+                }
+                return Ok(Next::Value(f));
+            }
+            "eval" => {
+                ensure_arity!(
+                    list.len() == 1 || list.len() == 2,
+                    "eval should have 1 or 2 params"
+                );
+                let form = list.pop_front().unwrap();
+                conts.push(Cont::Eval {
+                    form: None,
+                    target: list.pop_front(),
+                    env: env.clone(),
+                })?;
+                return Ok(Next::Eval(form, env));
+            }
+            "quote" => {
+                ensure_arity!(list.len() == 1, "quote should have 1 param");
+                return Ok(Next::Value(list.pop_front().unwrap()));
+            }
+            "quasiquote" => {
+                return Ok(Next::Eval(quasiquote(list.pop_front().unwrap()), env));
+            }
+            "defmacro!" => {
+                let def = doc::parse_def("defmacro!", list)?;
+                let mut value = eval(def.value.clone(), env.clone())?;
+                ensure!(
+                    value.is_closure(),
+                    "defmacro!'s last param should evaluate to func"
+                );
+                let new_value = Rc::make_mut(&mut value);
+                new_value.set_is_macro();
+                let mut attrs = match def.attrs {
+                    Some(ref attrs) => eval(attrs.clone(), env.clone())?.to_hashmap(),
+                    None => HashMap::new(),
+                };
+                attrs.insert(
+                    HashKey::Keyword(Sym::new(":macro")),
+                    new_mal!(Bool(true)),
+                );
+                let attrs = Some(new_mal!(Hashmap(attrs, new_mal!(Nil))));
+                let meta = doc::def_meta(&def, attrs, try_pr_str(&written(&form), true)?, &env);
+                env_set(env.clone(), def.name, value.clone());
+                env_set_meta(env.clone(), def.name, meta);
+                return Ok(Next::Value(value));
+            }
+            "var" => {
+                ensure_arity!(list.len() == 1, "var should have 1 param");
+                let name = list.pop_front().unwrap();
+                ensure_type!(name.is_symbol(), "var's param should be symbol");
+                let name = name.to_symbol();
+                return match namespace::resolve_var(&env, name)? {
+                    Some((found, name)) => Ok(Next::Value(new_mal!(Var(Var::new(found, name))))),
+                    None => Err(NotFound(name.to_string()).into()),
+                };
+            }
+            "doc" | "source" => {
+                ensure_arity!(list.len() == 1, "{} should have 1 param", first_mal.to_symbol());
+                let name = list.pop_front().unwrap();
+                ensure_type!(
+                    name.is_symbol(),
+                    "{}'s param should be symbol",
+                    first_mal.to_symbol()
+                );
+                let (env, name) = match namespace::resolve_var(&env, name.to_symbol())? {
+                    Some(found) => found,
+                    None => return Err(NotFound(name.to_symbol().to_string()).into()),
+                };
+                let text = if first_mal.to_symbol() == "doc" {
+                    doc::doc(&env, &name)?
+                } else {
+                    doc::source(&env, &name)?
+                };
+                console::println(text.trim_end())?;
+                return Ok(Next::Value(new_mal!(Nil)));
+            }
+            "apropos" => {
+                ensure_arity!(list.len() == 1, "apropos should have 1 param");
+                let pattern = eval(list.pop_front().unwrap(), env.clone())?;
+                return doc::apropos(&env, &pattern).map(Next::Value);
+            }
+            "find-doc" => {
+                ensure_arity!(list.len() == 1, "find-doc should have 1 param");
+                let pattern = eval(list.pop_front().unwrap(), env.clone())?;
+                console::print(&doc::find_doc(&env, &pattern)?)?;
+                return Ok(Next::Value(new_mal!(Nil)));
+            }
+            "defprotocol" => {
+                ensure_arity!(!list.is_empty(), "defprotocol should have at least 1 param");
+                let name = list.pop_front().unwrap();
+                ensure_type!(name.is_symbol(), "defprotocol's first param should be symbol");
+                let name = name.to_symbol();
+                let doc = if list.front().map(|d| d.is_string()) == Some(true) {
+                    list.pop_front()
+                } else {
+                    None
+                };
+                let sigs = list
+                    .iter()
+                    .map(protocol::parse_sig)
+                    .collect::<Fallible<Vec<_>>>()?;
+                let methods = sigs.iter().map(|sig| sig.name.clone()).collect();
+                let value = new_mal!(Protocol(Rc::new(Protocol::new(name.to_string(), methods))));
+                for sig in sigs {
+                    let f = analyze(protocol::dispatcher(&value, &sig.name), &env)?;
+                    let f = eval(f, env.clone())?;
+                    let meta = doc::binding_meta(&sig.name, sig.doc, Some(sig.arglists), None);
+                    env_set(env.clone(), sig.name.clone(), f);
+                    env_set_meta(env.clone(), sig.name, meta);
+                }
+                let meta = doc::binding_meta(&name, doc, None, Some(try_pr_str(&form, true)?));
+                env_set(env.clone(), name, value.clone());
+                env_set_meta(env.clone(), name, meta);
+                return Ok(Next::Value(value));
+            }
+            "extend-type" | "extend-protocol" => {
+                let form_name = first_mal.to_symbol();
+                ensure_arity!(!list.is_empty(), "{} should have at least 1 param", form_name);
+                let target = eval(list.pop_front().unwrap(), env.clone())?;
+                // every group is checked before any is registered
+                let mut extensions = Vec::new();
+                for (other, impls) in protocol::parse_impls(&form_name, list)? {
+                    let other = eval(other, env.clone())?;
+                    let (kind, value) = if form_name == "extend-type" {
+                        (Kind::from_mal(&target)?, other)
+                    } else {
+                        (Kind::from_mal(&other)?, target.clone())
+                    };
+                    ensure_type!(value.is_protocol(), "{:?} is not a protocol", value);
+                    let mut fns = HashMap::new();
+                    for (method, f) in impls {
+                        fns.insert(method, eval(analyze(f, &env)?, env.clone())?);
+                    }
+                    let protocol = value.to_protocol();
+                    protocol.check(&fns)?;
+                    extensions.push((protocol, kind, fns));
+                }
+                for (protocol, kind, fns) in extensions {
+                    protocol.extend(kind, fns)?;
+                }
+                return Ok(Next::Value(new_mal!(Nil)));
+            }
+            "lazy-seq" => {
+                list.push_front(new_mal!(Symbol(Sym::new("do"))));
+                let body = new_mal!(List(list, new_mal!(Nil)));
+                let env = env.clone();
+                return Ok(Next::Value(new_mal!(LazySeq(
+                    LazySeq::new(move || eval(body.clone(), env.clone())),
+                    new_mal!(Nil)
+                ))));
+            }
+            "macroexpand" => {
+                return macroexpand(list.pop_front().unwrap(), &env).map(Next::Value);
+            }
+            "try*" => {
+                let TryForm {
+                    body,
+                    catches,
+                    finally,
+                } = parse_try(&list)?;
+                conts.push(Cont::Try {
+                    catches,
+                    finally,
+                    env: env.clone(),
+                })?;
+                return Ok(Next::Eval(body, env));
+            }
+            "binding" => {
+                ensure!(!list.is_empty(), "binding should have a binding vector");
+                let bindings = list.pop_front().unwrap();
+                ensure!(
+                    (bindings.is_vec() || bindings.is_list()) && bindings.len().is_multiple_of(2),
+                    "binding's first param should be a vector of name/value pairs"
+                );
+                let pairs: Vec<_> = bindings.to_items_ref().iter().cloned().collect();
+                let mut cells = Vec::new();
+                let mut rest = LinkedList::new();
+                for pair in pairs.chunks(2) {
+                    ensure!(pair[0].is_symbol(), "binding's names should be symbols");
+                    cells.push(namespace::dynamic_binding(&env, pair[0].to_symbol())?);
+                    rest.push_back(pair[1].clone());
+                }
+                return next_binding_value(cells, Vec::new(), rest, list, env, conts);
+            }
+            "current-env" => {
+                ensure_arity!(list.is_empty(), "current-env should have 0 params");
+                gc::track_env(&env);
+                return Ok(Next::Value(new_mal!(Env(env.clone()))));
+            }
+            _ => {}
+        };
+    };
+
+    next_item(Items::Call, form.clone(), LinkedList::new(), env, conts)
+}
+
+/// Goes on with `cont` now that the form it waited for evaluated to `value`.
+fn resume(cont: Cont, value: MalType, conts: &mut Frames<Cont>) -> Fallible<Next> {
+    match cont {
+        Cont::Do { form, done, env } => next_form(form, done, env, conts),
+        Cont::If {
+            then_clause,
+            else_clause,
+            env,
+        } => Ok(match *value {
+            InnerMalType::Nil | InnerMalType::Bool(false) => match else_clause {
+                Some(else_clause) => Next::Eval(else_clause, env),
+                None => Next::Value(new_mal!(Nil)),
+            },
+            _ => Next::Eval(then_clause, env),
+        }),
+        Cont::Let {
+            name,
+            bindings,
+            body,
+            env,
+        } => {
+            env_bind(&env, name, value);
+            next_binding(bindings, body, env, conts)
+        }
+        Cont::Loop { .. } => Ok(Next::Value(value)),
+        Cont::Def {
+            def,
+            form,
+            value: None,
+            env,
+        } => {
+            if let Some(attrs) = def.attrs.clone() {
+                conts.push(Cont::Def {
+                    def,
+                    form,
+                    value: Some(value),
+                    env: env.clone(),
+                })?;
+                return Ok(Next::Eval(attrs, env));
+            }
+            define(&def, &form, value, None, &env)
+        }
+        Cont::Def {
+            def,
+            form,
+            value: Some(defined),
+            env,
+        } => define(&def, &form, defined, Some(value), &env),
+        Cont::Eval {
+            form: None,
+            target: Some(target),
+            env,
+        } => {
+            conts.push(Cont::Eval {
+                form: Some(value),
+                target: None,
+                env: env.clone(),
+            })?;
+            Ok(Next::Eval(target, env))
+        }
+        Cont::Eval { form, .. } => {
+            let (form, target) = match form {
+                Some(form) => {
+                    ensure_type!(value.is_env(), "eval's second param should be env");
+                    (form, Some(value.to_env()))
+                }
+                None => (value, None),
+            };
+            let form = eval_leading(form, target.as_ref())?;
+            let env = top_env(target.as_ref());
+            Ok(Next::Eval(analyze(form, &env)?, env))
+        }
+        Cont::Items {
+            kind,
+            form,
+            mut done,
+            env,
+        } => {
+            done.push_back(value);
+            next_item(kind, form, done, env, conts)
+        }
+        Cont::Map {
+            mut done,
+            key,
+            rest,
+            env,
+        } => {
+            done.insert(key, value);
+            next_value(done, rest, env, conts)
+        }
+        Cont::Try {
+            catches,
+            finally,
+            env,
+        } => {
+            // realise a lazy result here so that errors it raises are caught
+            if value.is_lazy_seq() {
+                if let Err(e) = seq_to_list(&value) {
+                    conts.push(Cont::Try {
+                        catches,
+                        finally,
+                        env,
+                    })?;
+                    return Err(e);
+                }
+            }
+            match finally {
+                Some(finally) => {
+                    conts.push(Cont::Finally { outcome: Ok(value) })?;
+                    next_form(finally, 1, env, conts)
+                }
+                None => Ok(Next::Value(value)),
+            }
+        }
+        Cont::Finally { outcome } => outcome.map(Next::Value),
+        Cont::BindingValues {
+            bindings,
+            mut values,
+            rest,
+            body,
+            env,
+        } => {
+            values.push(value);
+            next_binding_value(bindings, values, rest, body, env, conts)
+        }
+        Cont::Binding { saved, body, env } => {
+            if body.is_empty() {
+                drop(saved);
+                return Ok(Next::Value(value));
+            }
+            next_binding_form(saved, body, env, conts)
+        }
+        Cont::Frame { .. } => Ok(Next::Value(value)),
+    }
+}
+
+/// Evaluates the item of the `do` form after the `done` ones, the last
+/// one in tail position.
+fn next_form(form: MalType, done: usize, env: Env, conts: &mut Frames<Cont>) -> Fallible<Next> {
+    let (next, last) = {
+        let items = form.to_items_ref();
+        match items.iter().nth(done) {
+            Some(next) => (next.clone(), done + 1 == items.len()),
+            None => return Ok(Next::Value(new_mal!(Nil))),
+        }
+    };
+    if !last {
+        conts.push(Cont::Do {
+            form,
+            done: done + 1,
+            env: env.clone(),
+        })?;
+    }
+    Ok(Next::Eval(next, env))
+}
+
+/// Evaluates the value of the next name/value pair of a `let*` whose env
+/// is `env`, or else its body.
+fn next_binding(
+    mut bindings: LinkedList<MalType>,
+    body: MalType,
+    env: Env,
+    conts: &mut Frames<Cont>,
+) -> Fallible<Next> {
+    let name = match bindings.pop_front() {
+        Some(name) => name.to_symbol(),
+        None => return Ok(Next::Eval(body, env)),
+    };
+    let value = bindings.pop_front().unwrap();
+    conts.push(Cont::Let {
+        name,
+        bindings,
+        body,
+        env: env.clone(),
+    })?;
+    Ok(Next::Eval(value, env))
+}
+
+/// Whether `form` is a `fn*` form.
+fn is_fn_form(form: &MalType) -> bool {
+    form.is_list()
+        && form
+            .to_items_ref()
+            .front()
+            .map(|head| head.is_symbol() && head.to_symbol() == "fn*")
+            == Some(true)
+}
+
+/// Binds the name of `def!` to `value` with its metadata.
+fn define(
+    def: &DefForm,
+    form: &MalType,
+    value: MalType,
+    attrs: Option<MalType>,
+    env: &Env,
+) -> Fallible<Next> {
+    let meta = doc::def_meta(def, attrs, try_pr_str(&written(form), true)?, env);
+    // a fn made for the def is named after it
+    let value = if is_fn_form(&def.value) {
+        arity::name_fn(value, def.name)
+    } else {
+        value
+    };
+    env_set(env.clone(), def.name, value.clone());
+    env_set_meta(env.clone(), def.name, meta);
+    Ok(Next::Value(value))
+}
+
+/// Evaluates the value of the next pair of a `binding`, or else rebinds
+/// `bindings` to `values`, whose roots are left alone, and evaluates its
+/// body.
+fn next_binding_value(
+    bindings: Vec<Rc<Binding>>,
+    values: Vec<MalType>,
+    mut rest: LinkedList<MalType>,
+    body: LinkedList<MalType>,
+    env: Env,
+    conts: &mut Frames<Cont>,
+) -> Fallible<Next> {
+    if let Some(form) = rest.pop_front() {
+        conts.push(Cont::BindingValues {
+            bindings,
+            values,
+            rest,
+            body,
+            env: env.clone(),
+        })?;
+        return Ok(Next::Eval(form, env));
+    }
+    for (binding, value) in bindings.iter().zip(values) {
+        binding.push(value);
+    }
+    let saved = Rebound(bindings);
+    if body.is_empty() {
+        return Ok(Next::Value(new_mal!(Nil)));
+    }
+    next_binding_form(saved, body, env, conts)
+}
+
+/// Evaluates the next form of the body of a `binding`. The values it
+/// rebound are restored once the cont is left, also when the body throws.
+fn next_binding_form(
+    saved: Rebound,
+    mut body: LinkedList<MalType>,
+    env: Env,
+    conts: &mut Frames<Cont>,
+) -> Fallible<Next> {
+    let form = body.pop_front().unwrap();
+    conts.push(Cont::Binding {
+        saved,
+        body,
+        env: env.clone(),
+    })?;
+    Ok(Next::Eval(form, env))
+}
+
+/// Evaluates the items of a call, list or vector after the `done` ones,
+/// then makes it.
+fn next_item(
+    kind: Items,
+    form: MalType,
+    mut done: LinkedList<MalType>,
+    env: Env,
+    conts: &mut Frames<Cont>,
+) -> Fallible<Next> {
+    let next = {
+        let mut rest = form.to_items_ref().iter().skip(done.len());
+        loop {
+            match rest.next() {
+                Some(item) => match atom(item, &env)? {
+                    Some(value) => done.push_back(value),
+                    None => break Some(item.clone()),
+                },
+                None => break None,
+            }
+        }
+    };
+    if let Some(item) = next {
+        conts.push(Cont::Items {
+            kind,
+            form,
+            done,
+            env: env.clone(),
+        })?;
+        return Ok(Next::Eval(item, env));
+    }
+    match kind {
+        Items::Call => {
+            let f = done.pop_front().unwrap();
+            apply(f, done, &form, conts).map_err(|e| trace::frame(e, &form))
+        }
+        Items::Recur => {
+            done.pop_front();
+            recur(done, conts)
+        }
+        Items::List => Ok(Next::Value(new_mal!(List(done, new_mal!(Nil))))),
+        Items::Vec => Ok(Next::Value(new_mal!(Vec(done, new_mal!(Nil))))),
+    }
+}
+
+/// Evaluates the body of the `loop*` whose cont is on top again, with its
+/// names bound to `values`. `analyze` made sure nothing is left to do in
+/// the loop after the `recur`.
+fn recur(values: LinkedList<MalType>, conts: &mut Frames<Cont>) -> Fallible<Next> {
+    let (names, body, env) = match conts.last() {
+        Some(Cont::Loop { names, body, env }) => (names.clone(), body.clone(), env.clone()),
+        _ => bail!("recur is not in tail position of a loop* or fn*"),
+    };
+    ensure_arity!(
+        values.len() == names.len(),
+        "recur should have {} params, got {}",
+        names.len(),
+        values.len()
+    );
+    let env = env_new(
+        Some(env),
+        names.iter().cloned(),
+        values.into_iter().collect(),
+    );
+    Ok(Next::Eval(body, env))
+}
+
+/// Evaluates the values left of a map, then makes it.
+fn next_value(
+    mut done: HashMap<HashKey, MalType>,
+    mut rest: Vec<(HashKey, MalType)>,
+    env: Env,
+    conts: &mut Frames<Cont>,
+) -> Fallible<Next> {
+    while let Some((key, form)) = rest.pop() {
+        match atom(&form, &env)? {
+            Some(value) => {
+                done.insert(key, value);
+            }
+            None => {
+                conts.push(Cont::Map {
+                    done,
+                    key,
+                    rest,
+                    env: env.clone(),
+                })?;
+                return Ok(Next::Eval(form, env));
+            }
+        }
+    }
+    Ok(Next::Value(new_mal!(Hashmap(done, new_mal!(Nil)))))
+}
+
+/// Calls `f` with `params` for the call `form`. The body of a closure
+/// which is not compiled is evaluated in tail position, and so are the
+/// calls `apply` makes.
+fn apply(
+    mut f: MalType,
+    mut params: LinkedList<MalType>,
+    form: &MalType,
+    conts: &mut Frames<Cont>,
+) -> Fallible<Next> {
+    loop {
+        let closure = match *f {
+            InnerMalType::Closure(ref closure, ..) => closure.clone(),
+            InnerMalType::Keyword(..) => {
+                // (:k map) and (:k map default) look the keyword up in map
+                ensure_arity!(
+                    params.len() == 1 || params.len() == 2,
+                    "keyword lookup should have 1 or 2 params"
+                );
+                let map = params.pop_front().unwrap();
+                let default = params.pop_front().unwrap_or_else(|| new_mal!(Nil));
+                if !map.is_hashmap() {
+                    return Ok(Next::Value(default));
+                }
+                return Ok(Next::Value(
+                    map.to_hashmap_ref()
+                        .get(&f.to_hash_key())
+                        .cloned()
+                        .unwrap_or(default),
+                ));
+            }
+            _ => bail_type!("{:?} is not a function", f),
+        };
+
+        // the method chosen by a multimethod runs in tail position
+        let closure = match closure.multi {
+            Some(ref multi) => multi.method_for(&params)?,
+            None => closure,
+        };
+        // and so does the clause of a fn of several arities
+        let closure = match closure.arities {
+            Some(ref arities) => arities.closure_for(params.len())?,
+            None => closure,
+        };
+        if let Some(ref c_env) = closure.c_env {
+            if c_env.proto.is_some() {
+                return vm::call(c_env, params).map(Next::Value);
+            }
+            let env = bind_params(c_env, params)?;
+            // a call in tail position of a `loop*` leaves it, which no
+            // `recur` in the closure re-enters
+            while let Some(Cont::Loop { .. }) = conts.last() {
+                conts.pop();
+            }
+            // a call in tail position takes the frame of the caller
+            match conts.last_mut() {
+                Some(Cont::Frame { form: caller }) => *caller = form.clone(),
+                _ => conts.push(Cont::Frame { form: form.clone() })?,
+            }
+            return Ok(Next::Eval(c_env.body.clone(), env));
+        }
+        if closure.native.is_none() && is_apply(closure.func) {
+            let (func, args) = apply_params(params)?;
+            f = func;
+            params = args;
+            continue;
+        }
+        return closure.call(params).map(Next::Value);
+    }
+}
+
+/// The value of `mal` if it needs no other form evaluated first, like a
+/// symbol or a number.
+fn atom(mal: &MalType, env: &Env) -> Fallible<Option<MalType>> {
+    Ok(Some(match **mal {
+        InnerMalType::Symbol(s) => match env_get(env.clone(), s) {
+            Some(value) => value,
+            None => namespace::qualified_binding(env, &s)?
+                .map(|binding| binding.get())
+                .ok_or_else(|| NotFound(s.to_string()))?,
+        },
+        InnerMalType::Local(_, depth, slot) => env_get_slot(env, depth, slot),
+        InnerMalType::Global(s, ref cache) => match env_get_global(env, s, cache) {
+            Some(value) => value,
+            None => return Err(NotFound(s.to_string()).into()),
+        },
+        InnerMalType::List(..)
+        | InnerMalType::Vec(..)
+        | InnerMalType::Hashmap(..)
+        | InnerMalType::LazySeq(..) => return Ok(None),
+        _ => mal.clone(),
+    }))
+}
+
+/// The form `def!`/`defmacro!` was written as, which `analyze` keeps as the
+/// metadata of the analyzed form.
+fn written(form: &MalType) -> MalType {
+    match form.get_metadata() {
+        ref meta if meta.is_list() => meta.clone(),
+        _ => form.clone(),
+    }
+}
+
+/// The value of `ast`, which is not a call: collections are evaluated item
+/// by item.
+fn eval_ast(ast: MalType, env: Env, conts: &mut Frames<Cont>) -> Fallible<Next> {
+    if let Some(value) = atom(&ast, &env)? {
+        return Ok(Next::Value(value));
+    }
+    match *ast {
+        InnerMalType::List(..) => {
+            next_item(Items::List, ast.clone(), LinkedList::new(), env, conts)
+        }
+        InnerMalType::Vec(..) => {
+            next_item(Items::Vec, ast.clone(), LinkedList::new(), env, conts)
+        }
+        InnerMalType::Hashmap(ref mapping, ..) => {
+            let rest = mapping
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            next_value(HashMap::new(), rest, env, conts)
+        }
+        _ => Ok(Next::Value(ast.clone())),
+    }
+}
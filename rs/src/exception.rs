@@ -0,0 +1,175 @@
+//! What `try*` catches errors as. A value thrown by `throw` is caught as
+//! itself, any other error as its message. A `catch*` clause may select the
+//! errors it catches by their kind or by a predicate of the caught value;
+//! those it does not select go on to the clauses of enclosing `try*`s.
+
+use core::is_truthy;
+use error::{ArityError, ConversionError, MalExceptionError, NotFound, StackOverflow, TypeError};
+use failure::{Error, Fallible};
+use printer::pr_str;
+use reader::read_str;
+use record::{define_record_type, RecordType};
+use std::collections::{HashMap, LinkedList};
+use std::io;
+use std::rc::Rc;
+use symbol::Sym;
+use trace;
+use types::{HashKey, InnerMalType, MalType};
+
+thread_local! {
+    static EX_INFO: Rc<RecordType> = define_record_type(
+        "ExceptionInfo".to_string(),
+        ["message", "data", "cause"].iter().map(|f| field(f)).collect(),
+    );
+}
+
+fn field(name: &str) -> HashKey {
+    HashKey::Keyword(Sym::new(&format!(":{}", name)))
+}
+
+/// The record type of the exceptions `ex-info` makes.
+pub fn ex_info_type() -> Rc<RecordType> {
+    EX_INFO.with(|rtype| rtype.clone())
+}
+
+/// An exception with `message`, the map `data` and the exception `cause`
+/// it was thrown for, or nil.
+pub fn ex_info(message: MalType, data: MalType, cause: MalType) -> MalType {
+    let map: HashMap<_, _> = vec![
+        (field("message"), message),
+        (field("data"), data),
+        (field("cause"), cause),
+    ]
+    .into_iter()
+    .collect();
+    RecordType::instantiate(&ex_info_type(), map)
+}
+
+/// The field `name` of `exception`, if `ex-info` made it.
+pub fn ex_info_field(exception: &MalType, name: &str) -> Option<MalType> {
+    match **exception {
+        InnerMalType::Record(ref rtype, ref map, _) if **rtype == *ex_info_type() => {
+            map.get(&field(name)).cloned()
+        }
+        _ => None,
+    }
+}
+
+/// The value `catch*` binds for `e`, which keeps its frames for `ex-stack`.
+pub fn caught(e: &Error) -> MalType {
+    let exception = match trace::cause(e).downcast_ref::<MalExceptionError>() {
+        Some(thrown) => thrown.value().unwrap_or_else(|| {
+            read_str(thrown.printed())
+                .unwrap_or_else(|_| new_mal!(String(thrown.printed().to_string())))
+        }),
+        None => new_mal!(String(trace::cause(e).to_string())),
+    };
+    trace::set_stack(&exception, trace::stack_of(e));
+    exception
+}
+
+/// The kind of `e`, which a `catch*` selects by as a keyword: `thrown` by
+/// `throw`, or else `arity`, `type`, `io`, `not-found`, `stack-overflow`
+/// or `error`, by the type of the error raised.
+pub fn kind(e: &Error) -> &'static str {
+    let e = trace::cause(e);
+    if e.downcast_ref::<MalExceptionError>().is_some() {
+        "thrown"
+    } else if e.downcast_ref::<StackOverflow>().is_some() {
+        "stack-overflow"
+    } else if e.downcast_ref::<ArityError>().is_some() {
+        "arity"
+    } else if e.downcast_ref::<NotFound>().is_some() {
+        "not-found"
+    } else if e.downcast_ref::<io::Error>().is_some() {
+        "io"
+    } else if e.downcast_ref::<TypeError>().is_some()
+        || e.downcast_ref::<ConversionError>().is_some()
+    {
+        "type"
+    } else {
+        "error"
+    }
+}
+
+/// Whether the `selector` of a `catch*`, a keyword naming a kind or a
+/// predicate, selects `e`, caught as `exception`.
+pub fn selects(selector: &MalType, e: &Error, exception: &MalType) -> Fallible<bool> {
+    match **selector {
+        InnerMalType::Keyword(k) => Ok(k == format!(":{}", kind(e)).as_str()),
+        InnerMalType::Closure(ref closure, _) => {
+            Ok(is_truthy(&closure.call(linked_list![exception.clone()])?))
+        }
+        _ => bail_type!(
+            "catch* selector should be keyword or fn, got {}",
+            pr_str(selector, true)
+        ),
+    }
+}
+
+/// A `catch*` clause, `(catch* name body)` or `(catch* selector name body)`.
+pub struct Catch {
+    pub selector: Option<MalType>,
+    pub name: MalType,
+    pub body: MalType,
+}
+
+/// The parts of `(try* body catch* ... finally)`.
+pub struct TryForm {
+    pub body: MalType,
+    pub catches: Vec<Catch>,
+    /// The `(finally form ...)` clause, which comes last.
+    pub finally: Option<MalType>,
+}
+
+fn is_clause(form: &MalType, name: &str) -> bool {
+    form.is_list() && form.get_first_symbol().map(|s| s.to_symbol()) == Some(Sym::new(name))
+}
+
+/// The parts of a `try*` of `args`.
+pub fn parse_try<'a, I: IntoIterator<Item = &'a MalType>>(args: I) -> Fallible<TryForm> {
+    let mut args = args.into_iter();
+    let body = match args.next() {
+        Some(body) => body.clone(),
+        None => bail!("try* should have a body"),
+    };
+    let mut catches = Vec::new();
+    let mut finally = None;
+    for clause in args {
+        ensure!(
+            finally.is_none(),
+            "finally should be the last clause of try*"
+        );
+        if is_clause(clause, "finally") {
+            finally = Some(clause.clone());
+            continue;
+        }
+        ensure!(
+            is_clause(clause, "catch*"),
+            "try*'s clauses should be catch* or finally"
+        );
+        let mut items: LinkedList<MalType> =
+            clause.to_items_ref().iter().skip(1).cloned().collect();
+        ensure_arity!(
+            items.len() == 2 || items.len() == 3,
+            "catch* should have 2 or 3 params"
+        );
+        let selector = if items.len() == 3 {
+            items.pop_front()
+        } else {
+            None
+        };
+        let name = items.pop_front().unwrap();
+        ensure!(name.is_symbol(), "catch*'s name should be symbol");
+        catches.push(Catch {
+            selector,
+            name,
+            body: items.pop_front().unwrap(),
+        });
+    }
+    Ok(TryForm {
+        body,
+        catches,
+        finally,
+    })
+}
@@ -0,0 +1,370 @@
+//! Collects the reference cycles `Rc` cannot free, like a closure `def!`ed
+//! into the env it closes over, or an atom holding itself.
+//!
+//! Every env captured by a closure, an env value or a var, and every atom, is
+//! tracked as a candidate, since a cycle has to pass through one of them. A
+//! collection scans all that is reachable from the candidates and, by trial
+//! deletion, subtracts the references found inside the scanned graph from
+//! each object's strong count. What is left over is held from outside,
+//! e.g. by a namespace or the Rust stack; objects not reachable from such
+//! an object are garbage, and are freed by clearing the envs, atoms and
+//! bindings among them. Whatever the scan cannot look into, like the thunk
+//! of a lazy seq, counts as held from outside.
+
+use arity::Arities;
+use env::{env_clear, env_refs, Binding, Env, EnvStruct};
+use fnv::{FnvHashMap, FnvHashSet};
+use namespace::all_ns;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::rc::{Rc, Weak};
+use types::{Closure, InnerMalType, MalType};
+
+/// Candidates tracked before the first automatic collection.
+const MIN_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub collections: usize,
+    pub reclaimed_envs: usize,
+    pub reclaimed: usize,
+}
+
+/// How much a scan found reachable.
+#[derive(Debug, Clone, Default)]
+pub struct Room {
+    pub envs: usize,
+    pub bindings: usize,
+    pub values: usize,
+    pub tracked: usize,
+}
+
+thread_local! {
+    static ENVS: RefCell<Vec<Weak<EnvStruct>>> = const { RefCell::new(Vec::new()) };
+    static ATOMS: RefCell<Vec<Weak<InnerMalType>>> = const { RefCell::new(Vec::new()) };
+    static STATS: RefCell<Stats> = RefCell::new(Stats::default());
+    static THRESHOLD: Cell<usize> = const { Cell::new(MIN_THRESHOLD) };
+    static COLLECTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Tracks `env`, which a closure, env value or var now refers to.
+pub fn track_env(env: &Env) {
+    ENVS.with(|envs| envs.borrow_mut().push(Rc::downgrade(env)));
+    maybe_collect();
+}
+
+/// A new atom holding `value`, tracked by the collector.
+pub fn new_atom(value: MalType) -> MalType {
+    let atom = new_mal!(Atom(RefCell::new(value)));
+    ATOMS.with(|atoms| atoms.borrow_mut().push(Rc::downgrade(&atom)));
+    maybe_collect();
+    atom
+}
+
+/// Forgets candidates which were freed, and repeated ones. Returns how
+/// many are left.
+fn prune() -> usize {
+    let envs = ENVS.with(|envs| {
+        let mut seen = FnvHashSet::default();
+        let mut envs = envs.borrow_mut();
+        envs.retain(|e| e.upgrade().map(|e| seen.insert(key(&*e))) == Some(true));
+        envs.len()
+    });
+    let atoms = ATOMS.with(|atoms| {
+        let mut atoms = atoms.borrow_mut();
+        atoms.retain(|a| a.upgrade().is_some());
+        atoms.len()
+    });
+    envs + atoms
+}
+
+/// Collects once the candidates have doubled since the last collection.
+fn maybe_collect() {
+    let tracked = ENVS.with(|e| e.borrow().len()) + ATOMS.with(|a| a.borrow().len());
+    if tracked < THRESHOLD.with(|t| t.get()) || COLLECTING.with(|c| c.get()) {
+        return;
+    }
+    if prune() >= THRESHOLD.with(|t| t.get()) / 2 {
+        collect();
+    }
+    let live = prune();
+    THRESHOLD.with(|t| t.set((live * 2).max(MIN_THRESHOLD)));
+}
+
+fn key<T>(rc: &T) -> usize {
+    rc as *const T as usize
+}
+
+enum Obj {
+    Env(Env),
+    Binding(Rc<Binding>),
+    Value(MalType),
+    // shared by the copies of a compiled closure
+    Upvalues(Rc<Vec<MalType>>),
+    // shared by the copies of a fn of several arities
+    Arities(Rc<Arities>),
+}
+
+impl Obj {
+    fn key(&self) -> usize {
+        match self {
+            Obj::Env(env) => key(&**env),
+            Obj::Binding(binding) => key(&**binding),
+            Obj::Value(value) => key(&**value),
+            Obj::Upvalues(upvalues) => key(&**upvalues),
+            Obj::Arities(arities) => key(&**arities),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Obj::Env(env) => Rc::strong_count(env),
+            Obj::Binding(binding) => Rc::strong_count(binding),
+            Obj::Value(value) => Rc::strong_count(value),
+            Obj::Upvalues(upvalues) => Rc::strong_count(upvalues),
+            Obj::Arities(arities) => Rc::strong_count(arities),
+        }
+    }
+
+    /// The handles this object owns.
+    fn refs(&self) -> Vec<Obj> {
+        let mut refs = Vec::new();
+        match self {
+            Obj::Env(env) => {
+                let (outer, slots, data) = env_refs(env);
+                refs.extend(outer.map(Obj::Env));
+                refs.extend(slots.into_iter().map(Obj::Value));
+                refs.extend(data.into_iter().map(Obj::Binding));
+            }
+            Obj::Binding(binding) => {
+                if let Ok(value) = binding.value.try_borrow() {
+                    refs.push(Obj::Value(value.clone()));
+                }
+                if let Ok(dynamic) = binding.dynamic.try_borrow() {
+                    refs.extend(dynamic.iter().cloned().map(Obj::Value));
+                }
+                if let Ok(Some(ref meta)) = binding.meta.try_borrow().map(|m| m.clone()) {
+                    refs.push(Obj::Value(meta.clone()));
+                }
+            }
+            Obj::Upvalues(upvalues) => refs.extend(upvalues.iter().cloned().map(Obj::Value)),
+            Obj::Arities(arities) => {
+                for closure in arities.closures() {
+                    closure_refs(closure, &mut refs);
+                }
+            }
+            Obj::Value(value) => match **value {
+                InnerMalType::List(ref items, ref meta)
+                | InnerMalType::Vec(ref items, ref meta) => {
+                    refs.extend(items.iter().cloned().map(Obj::Value));
+                    refs.push(Obj::Value(meta.clone()));
+                }
+                InnerMalType::LazySeq(_, ref meta) => refs.push(Obj::Value(meta.clone())),
+                InnerMalType::Hashmap(ref map, ref meta)
+                | InnerMalType::Record(_, ref map, ref meta) => {
+                    refs.extend(map.values().cloned().map(Obj::Value));
+                    refs.push(Obj::Value(meta.clone()));
+                }
+                InnerMalType::Atom(ref cell) => {
+                    if let Ok(inner) = cell.try_borrow() {
+                        refs.push(Obj::Value(inner.clone()));
+                    }
+                }
+                InnerMalType::Closure(ref closure, ref meta) => {
+                    closure_refs(closure, &mut refs);
+                    refs.push(Obj::Value(meta.clone()));
+                }
+                InnerMalType::Var(ref var) => refs.push(Obj::Env(var.env.clone())),
+                InnerMalType::Env(ref env) => refs.push(Obj::Env(env.clone())),
+                InnerMalType::Global(_, ref cache) => {
+                    if let Ok(Some(binding)) = cache.try_borrow().map(|c| c.clone()) {
+                        refs.push(Obj::Binding(binding));
+                    }
+                }
+                _ => {}
+            },
+        }
+        refs
+    }
+}
+
+/// Adds the handles `closure` owns to `refs`.
+fn closure_refs(closure: &Closure, refs: &mut Vec<Obj>) {
+    if let Some(ref c_env) = closure.c_env {
+        refs.push(Obj::Value(c_env.parameters.clone()));
+        refs.push(Obj::Value(c_env.body.clone()));
+        refs.push(Obj::Env(c_env.env.clone()));
+        if !c_env.upvalues.is_empty() {
+            refs.push(Obj::Upvalues(c_env.upvalues.clone()));
+        }
+    }
+    if let Some(ref arities) = closure.arities {
+        refs.push(Obj::Arities(arities.clone()));
+    }
+}
+
+struct Node {
+    obj: Obj,
+    // references to it from scanned objects
+    internal: usize,
+    refs: Vec<usize>,
+}
+
+/// Everything reachable from `roots`, with the references between them.
+fn scan(roots: Vec<Obj>) -> FnvHashMap<usize, Node> {
+    let mut graph: FnvHashMap<usize, Node> = FnvHashMap::default();
+    let mut pending = Vec::new();
+    for root in roots {
+        let k = root.key();
+        if let Entry::Vacant(entry) = graph.entry(k) {
+            entry.insert(Node {
+                obj: root,
+                internal: 0,
+                refs: Vec::new(),
+            });
+            pending.push(k);
+        }
+    }
+    while let Some(k) = pending.pop() {
+        let refs = graph[&k].obj.refs();
+        let mut keys = Vec::with_capacity(refs.len());
+        for obj in refs {
+            let rk = obj.key();
+            keys.push(rk);
+            match graph.entry(rk) {
+                Entry::Occupied(mut entry) => entry.get_mut().internal += 1,
+                Entry::Vacant(entry) => {
+                    entry.insert(Node {
+                        obj,
+                        internal: 1,
+                        refs: Vec::new(),
+                    });
+                    pending.push(rk);
+                }
+            }
+        }
+        graph.get_mut(&k).unwrap().refs = keys;
+    }
+    graph
+}
+
+/// The objects of `graph` not reachable from outside of it.
+fn garbage(graph: &FnvHashMap<usize, Node>) -> Vec<usize> {
+    // the graph itself holds one handle to each object
+    let mut live: FnvHashSet<usize> = graph
+        .iter()
+        .filter(|(_, node)| node.obj.strong_count() > node.internal + 1)
+        .map(|(k, _)| *k)
+        .collect();
+    let mut pending: Vec<usize> = live.iter().cloned().collect();
+    while let Some(k) = pending.pop() {
+        for r in &graph[&k].refs {
+            if live.insert(*r) {
+                pending.push(*r);
+            }
+        }
+    }
+    graph
+        .keys()
+        .filter(|k| !live.contains(k))
+        .cloned()
+        .collect()
+}
+
+fn candidates() -> Vec<Obj> {
+    let mut roots: Vec<Obj> = ENVS.with(|envs| {
+        envs.borrow()
+            .iter()
+            .filter_map(|e| e.upgrade())
+            .map(Obj::Env)
+            .collect()
+    });
+    roots.extend(ATOMS.with(|atoms| {
+        atoms
+            .borrow()
+            .iter()
+            .filter_map(|a| a.upgrade())
+            .map(Obj::Value)
+            .collect::<Vec<_>>()
+    }));
+    roots
+}
+
+/// Frees the unreachable cycles among the candidates. Returns how many
+/// objects were reclaimed.
+pub fn collect() -> usize {
+    if COLLECTING.with(|c| c.replace(true)) {
+        return 0;
+    }
+    let graph = scan(candidates());
+    let garbage = garbage(&graph);
+
+    // Break the cycles by emptying the mutable cells in them. What they held
+    // is dropped only once every cell is empty.
+    let mut dropped_envs = Vec::new();
+    let mut dropped_values = Vec::new();
+    let mut envs = 0;
+    for k in &garbage {
+        match graph[k].obj {
+            Obj::Env(ref env) => {
+                envs += 1;
+                dropped_envs.push(env_clear(env));
+            }
+            Obj::Binding(ref binding) => {
+                if let Ok(mut value) = binding.value.try_borrow_mut() {
+                    dropped_values.push(::std::mem::replace(&mut *value, new_mal!(Nil)));
+                }
+                if let Ok(mut dynamic) = binding.dynamic.try_borrow_mut() {
+                    dropped_values.append(&mut dynamic);
+                }
+                if let Ok(mut meta) = binding.meta.try_borrow_mut() {
+                    dropped_values.extend(meta.take());
+                }
+            }
+            Obj::Value(ref value) => {
+                if let InnerMalType::Atom(ref cell) = **value {
+                    if let Ok(mut inner) = cell.try_borrow_mut() {
+                        dropped_values.push(::std::mem::replace(&mut *inner, new_mal!(Nil)));
+                    }
+                }
+            }
+            Obj::Upvalues(_) | Obj::Arities(_) => {}
+        }
+    }
+    drop(graph);
+    drop(dropped_envs);
+    drop(dropped_values);
+
+    STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        stats.collections += 1;
+        stats.reclaimed_envs += envs;
+        stats.reclaimed += garbage.len();
+    });
+    COLLECTING.with(|c| c.set(false));
+    prune();
+    garbage.len()
+}
+
+pub fn stats() -> Stats {
+    STATS.with(|stats| stats.borrow().clone())
+}
+
+/// Counts what is reachable from the namespaces and the candidates.
+pub fn room() -> Room {
+    let mut roots = candidates();
+    let tracked = roots.len();
+    roots.extend(all_ns().into_iter().map(|ns| Obj::Env(ns.env.clone())));
+    let mut room = Room {
+        tracked,
+        ..Room::default()
+    };
+    for node in scan(roots).values() {
+        match node.obj {
+            Obj::Env(_) => room.envs += 1,
+            Obj::Binding(_) => room.bindings += 1,
+            Obj::Value(_) | Obj::Upvalues(_) | Obj::Arities(_) => room.values += 1,
+        }
+    }
+    room
+}
@@ -0,0 +1,232 @@
+use console;
+use core::Ns;
+use doc;
+use env::{env_get, env_set, env_set_meta, Env};
+use eval::eval_top;
+use exception;
+use failure::{Error, Fallible};
+use module;
+use namespace::{self, Namespace, CORE};
+use printer::pr_str;
+use reader::{read_str, Forms};
+use runtime::{self, Runtime};
+use stack;
+use std::collections::LinkedList;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{Closure, InnerMalType, Kind, MalType};
+
+/// Definitions made in mal when an interpreter starts, in order.
+const PRELUDE: &[&str] = &[
+    r#"(def! not "Returns true if a is logical false, false otherwise." (fn* (a) (if a false true)))"#,
+    r#"(defmacro! cond "Takes test/expr pairs and evaluates the expr of the first test that is logical true." (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw "odd number of forms to cond")) (cons 'cond (rest (rest xs)))))))"#,
+    r#"(defmacro! or (fn* (& xs) (if (empty? xs) nil (if (= 1 (count xs)) (first xs) `(let* (or_FIXME ~(first xs)) (if or_FIXME or_FIXME (or ~@(rest xs))))))))"#,
+    r#"(do (def! *gensym-counter* (atom 0))
+    (def! gensym "Returns a new symbol with a unique name." (fn* [] (symbol (str "G__" (swap! *gensym-counter* (fn* [x] (+ 1 x)))))))
+    (defmacro! or "Evaluates xs one at a time and returns the first logical true value, or the last value." (fn* (& xs) (if (empty? xs) nil (if (= 1 (count xs)) (first xs) (let* (condvar (gensym)) `(let* (~condvar ~(first xs)) (if ~condvar ~condvar (or ~@(rest xs))))))))))
+    "#,
+    r#"(defmacro! defrecord "Defines the record type name with the given fields, a positional constructor ->name and a constructor map->name taking a map." (fn* [name fields]
+    `(do (def! ~name (record-type ~(str name) (vector ~@(map (fn* [f] (keyword (str f))) fields))))
+         (def! ~(symbol (str "->" name)) (fn* ~fields (make-record ~name (vector ~@fields))))
+         (def! ~(symbol (str "map->" name)) (fn* [m] (make-record ~name m)))
+         ~name)))"#,
+    r#"(defmacro! defmulti "Defines name as a multimethod calling the method for the value of (dispatch-fn & args). Options: :default value, the dispatch value of the fallback method (:default by default)." (fn* [name & args]
+    (if (string? (first args))
+      `(def! ~name ~(first args) (make-multi ~(str name) ~@(rest args)))
+      `(def! ~name (make-multi ~(str name) ~@args)))))"#,
+    r#"(defmacro! defmethod "Installs the method of multimethod name for dispatch-val." (fn* [name dispatch-val params & body]
+    `(add-method ~name ~dispatch-val (fn* ~params (do ~@body)))))"#,
+    r#"(defmacro! ns "Creates the namespace name if needed and makes it current. Each clause (:require spec*) requires the specs as require does." (fn* [name & clauses]
+    `(do (in-ns (quote ~name))
+         ~@(map (fn* [clause]
+                  (if (= :require (first clause))
+                    `(require ~@(map (fn* [spec] `(quote ~spec)) (rest clause)))
+                    (throw (str "unsupported ns clause " (first clause)))))
+                (filter list? clauses))
+         nil)))"#,
+];
+
+/// A mal interpreter to embed in a Rust program. Each has a runtime of its
+/// own, with its namespaces, output, input and max depth, which its
+/// methods run mal in.
+pub struct Interpreter {
+    runtime: Rc<Runtime>,
+    core: Rc<Namespace>,
+}
+
+impl Interpreter {
+    /// Sets up `mal.core` with the built-ins and the prelude, and makes a
+    /// `user` namespace current.
+    pub fn new() -> Interpreter {
+        let runtime = Rc::new(Runtime::new());
+        let core = runtime::enter(&runtime, init);
+        Interpreter { runtime, core }
+    }
+
+    /// Evaluates the forms of `s` one at a time in the current namespace,
+    /// returning the value of the last one. Text after the last whole form
+    /// is an error.
+    pub fn eval_str(&self, s: &str) -> Fallible<MalType> {
+        self.enter(|| {
+            let mut value = new_mal!(Nil);
+            for form in Forms::new(s) {
+                value = eval_top(form?)?;
+            }
+            Ok(value)
+        })
+    }
+
+    /// Evaluates a form that was read or built in Rust.
+    pub fn eval_form(&self, form: MalType) -> Fallible<MalType> {
+        self.enter(|| eval_top(form))
+    }
+
+    /// Binds `name` to `value` in the current namespace.
+    pub fn define(&self, name: &str, value: MalType) {
+        self.enter(|| env_set(current_env(), name, value))
+    }
+
+    /// Binds `name` to a mal function calling `f` with the evaluated args.
+    pub fn register_fn<F>(&self, name: &str, f: F)
+    where
+        F: Fn(LinkedList<MalType>) -> Fallible<MalType> + 'static,
+    {
+        self.define(
+            name,
+            new_mal!(Closure(Closure::new_native(f), new_mal!(Nil))),
+        );
+    }
+
+    /// The value `name` has in the current namespace.
+    pub fn get(&self, name: &str) -> Option<MalType> {
+        self.enter(|| env_get(current_env(), name))
+    }
+
+    /// Evaluates the forms of a file, as `load-file` does.
+    pub fn load_file<P: AsRef<Path>>(&self, path: P) -> Fallible<MalType> {
+        self.enter(|| module::load_file(path.as_ref()))
+    }
+
+    /// Calls the mal function `f` with `args`.
+    pub fn call<I>(&self, f: &MalType, args: I) -> Fallible<MalType>
+    where
+        I: IntoIterator<Item = MalType>,
+    {
+        match **f {
+            InnerMalType::Closure(ref closure, _) => {
+                self.enter(|| closure.call(args.into_iter().collect()))
+            }
+            _ => bail_type!("{} is not a function", pr_str(f, true)),
+        }
+    }
+
+    /// Binds `*e` to the exception `e` raises, as the REPL does with the
+    /// errors it prints, and returns it.
+    pub fn set_last_error(&self, e: Error) -> MalType {
+        let exception = exception::caught(&e);
+        env_set(self.core.env.clone(), "*e", exception.clone());
+        exception
+    }
+
+    /// Sets `*ARGV*`, the command line arguments of a mal script.
+    pub fn set_argv(&self, args: Vec<String>) {
+        let argv = args.into_iter().map(|s| new_mal!(String(s))).collect();
+        env_set(
+            self.core.env.clone(),
+            "*ARGV*",
+            new_mal!(List(argv, new_mal!(Nil))),
+        );
+    }
+
+    /// Sends what mal prints to `out`.
+    pub fn set_stdout(&self, out: Box<dyn Write>) {
+        console::set_stdout(&self.runtime, out);
+    }
+
+    /// Makes `readline` read from `input`.
+    pub fn set_stdin(&self, input: Box<dyn BufRead>) {
+        console::set_stdin(&self.runtime, input);
+    }
+
+    /// Limits evaluation to `depth` frames nested at once, past which it
+    /// fails with a `StackOverflow`; `None` leaves memory as the only limit.
+    pub fn set_max_depth(&self, depth: Option<usize>) {
+        stack::set_max_depth(&self.runtime, depth);
+    }
+
+    /// Runs `f` in the runtime of this interpreter.
+    fn enter<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        runtime::enter(&self.runtime, f)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+fn current_env() -> Env {
+    namespace::current().env.clone()
+}
+
+/// Sets up `mal.core` and `user` in the runtime entered, and returns
+/// `mal.core`.
+fn init() -> Rc<Namespace> {
+    let core = namespace::create_ns(Sym::new(CORE));
+    let env = core.env.clone();
+    let ns = Ns::new();
+    for (k, v) in ns.map {
+        env_set(env.clone(), k, new_mal!(Closure(v, new_mal!(Nil))));
+    }
+    for (k, meta) in ns.meta {
+        env_set_meta(env.clone(), k, meta);
+    }
+    for kind in Kind::builtins() {
+        env_set(env.clone(), kind.name(), new_mal!(Type(kind)));
+    }
+    env_set(
+        env.clone(),
+        "ExceptionInfo",
+        new_mal!(Type(Kind::Record(exception::ex_info_type()))),
+    );
+    env_set(
+        env.clone(),
+        "*host-language*",
+        new_mal!(String("mal".to_string())),
+    );
+    env_set(env.clone(), "*ns*", new_mal!(Namespace(core.clone())));
+    env_set(
+        env.clone(),
+        "*ARGV*",
+        new_mal!(List(LinkedList::new(), new_mal!(Nil))),
+    );
+    env_set(env.clone(), "*file*", new_mal!(Nil));
+    env_set(env.clone(), "*e", new_mal!(Nil));
+    env_set(env.clone(), "*load-path*", module::default_load_path());
+    for (name, text) in &[
+        (
+            "*ns*",
+            "The current namespace, in which top-level forms are evaluated.",
+        ),
+        ("*file*", "The path of the file being loaded, or nil."),
+        (
+            "*e",
+            "The last exception the REPL printed, whose calls ex-stack returns.",
+        ),
+        (
+            "*load-path*",
+            "An atom of the directories require searches for modules.",
+        ),
+    ] {
+        env_set_meta(env.clone(), *name, doc::dynamic_meta(name, text));
+    }
+    for form in PRELUDE {
+        eval_top(read_str(form).expect("prelude form")).expect("prelude");
+    }
+
+    namespace::set_current(namespace::create_ns(Sym::new("user")));
+    core
+}
@@ -0,0 +1,772 @@
+//! Compiles hot numeric fns to native code with Cranelift.
+//!
+//! A compiled fn which has been called `HOT_CALLS` times is translated from
+//! its bytecode if all it does is arithmetic and comparisons of numbers,
+//! with `if`, `let*` and calls to itself, which are loops in tail position.
+//! The code keeps numbers unboxed and is specialized for them: it runs
+//! only when every arg is a `Num`, and the globals it calls still are the
+//! builtins and fn it was compiled for. Otherwise, or when it recurses too
+//! deep, it deoptimises: the call runs in the interpreter instead, which is
+//! the same since the code has no side effects.
+
+use compile::{Op, Proto};
+use core::numeric_builtin;
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::condcodes::FloatCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, InstBuilder, MemFlags, Type, UserFuncName, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Module};
+use env::{env_get_global, Env};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, LinkedList};
+use std::mem;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{ClosureEnv, HashKey, InnerMalType, MalType};
+
+/// Calls of a fn before it is compiled.
+const HOT_CALLS: u32 = 50;
+/// Deoptimisations after which a fn is left to the interpreter.
+const MAX_DEOPTS: u32 = 100;
+/// Nesting of the calls compiled code makes to itself before it deopts.
+const MAX_DEPTH: i64 = 10_000;
+
+/// The JIT state of a `Proto`.
+#[derive(Debug, Default)]
+pub struct Slot {
+    calls: Cell<u32>,
+    state: RefCell<State>,
+}
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Cold,
+    Rejected,
+    Compiled(Rc<Native>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Num,
+    Bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prim {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// What a global called by compiled code is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Callee {
+    Prim(Prim),
+    // the fn being compiled
+    Own,
+}
+
+type Entry = extern "C" fn(*mut u8, *const f64) -> f64;
+
+#[derive(Debug)]
+struct Native {
+    entry: Entry,
+    nparams: usize,
+    ret: Kind,
+    // the globals called, as indexes of their consts
+    guards: Vec<(usize, Callee)>,
+    deopts: Cell<u32>,
+}
+
+#[derive(Default)]
+struct Stats {
+    compiled: Vec<MalType>,
+    rejected: usize,
+    native_calls: usize,
+    deopts: usize,
+}
+
+thread_local! {
+    static MODULE: RefCell<Option<JITModule>> = const { RefCell::new(None) };
+    static STATS: RefCell<Stats> = RefCell::new(Stats::default());
+}
+
+/// What `(jit-stats)` shows: the fns compiled, how many were not, and how
+/// often compiled code ran or deoptimised.
+pub fn stats() -> MalType {
+    STATS.with(|stats| {
+        let stats = stats.borrow();
+        let map = vec![
+            ("enabled", new_mal!(Bool(true))),
+            (
+                "compiled",
+                new_mal!(Vec(stats.compiled.iter().cloned().collect(), new_mal!(Nil))),
+            ),
+            ("rejected", new_mal!(Num(stats.rejected as f64))),
+            ("native-calls", new_mal!(Num(stats.native_calls as f64))),
+            ("deopts", new_mal!(Num(stats.deopts as f64))),
+        ]
+        .into_iter()
+        .map(|(k, v)| (HashKey::Keyword(Sym::new(&format!(":{}", k))), v))
+        .collect();
+        new_mal!(Hashmap(map, new_mal!(Nil)))
+    })
+}
+
+fn prim(name: &str) -> Option<Prim> {
+    Some(match name {
+        "+" => Prim::Add,
+        "-" => Prim::Sub,
+        "*" => Prim::Mul,
+        "/" => Prim::Div,
+        "=" => Prim::Eq,
+        "<" => Prim::Lt,
+        "<=" => Prim::Le,
+        ">" => Prim::Gt,
+        ">=" => Prim::Ge,
+        _ => return None,
+    })
+}
+
+/// What the global of `proto` at const `idx` is, looked up from `env`.
+fn classify(proto: &Rc<Proto>, env: &Env, idx: usize) -> Option<Callee> {
+    let value = match *proto.consts[idx] {
+        InnerMalType::Global(name, ref cache) => env_get_global(env, name, cache)?,
+        _ => return None,
+    };
+    let closure = match *value {
+        InnerMalType::Closure(ref closure, _) if !closure.is_macro && closure.multi.is_none() => {
+            closure
+        }
+        _ => return None,
+    };
+    match closure.c_env {
+        Some(ref c_env) => {
+            let own = c_env.proto.as_ref().map(|p| Rc::ptr_eq(p, proto)) == Some(true)
+                && Rc::ptr_eq(&c_env.env, env)
+                && c_env.upvalues.is_empty();
+            if own {
+                Some(Callee::Own)
+            } else {
+                None
+            }
+        }
+        None if closure.native.is_none() => numeric_builtin(closure.func)
+            .and_then(prim)
+            .map(Callee::Prim),
+        None => None,
+    }
+}
+
+/// Calls the closure of `c_env` natively if it is compiled, or hot enough
+/// to be, and `args` fit. `None` leaves the call to the interpreter.
+pub fn call(c_env: &ClosureEnv, args: &[MalType]) -> Option<MalType> {
+    let proto = c_env.proto.as_ref()?;
+    let native = {
+        let slot = &proto.jit;
+        let mut state = slot.state.borrow_mut();
+        match *state {
+            State::Compiled(ref native) => native.clone(),
+            State::Rejected => return None,
+            State::Cold => {
+                slot.calls.set(slot.calls.get() + 1);
+                if slot.calls.get() < HOT_CALLS {
+                    return None;
+                }
+                match compile(proto, &c_env.env) {
+                    Some(native) => {
+                        let native = Rc::new(native);
+                        let form = new_mal!(List(
+                            linked_list![
+                                new_mal!(Symbol(Sym::new("fn*"))),
+                                proto.params.clone(),
+                                proto.body.clone()
+                            ],
+                            new_mal!(Nil)
+                        ));
+                        STATS.with(|stats| stats.borrow_mut().compiled.push(form));
+                        *state = State::Compiled(native.clone());
+                        native
+                    }
+                    None => {
+                        STATS.with(|stats| stats.borrow_mut().rejected += 1);
+                        *state = State::Rejected;
+                        return None;
+                    }
+                }
+            }
+        }
+    };
+    if args.len() != native.nparams {
+        return None;
+    }
+    let result = run(&native, proto, &c_env.env, args);
+    STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        match result {
+            Some(_) => stats.native_calls += 1,
+            None => stats.deopts += 1,
+        }
+    });
+    if result.is_none() {
+        native.deopts.set(native.deopts.get() + 1);
+        if native.deopts.get() >= MAX_DEOPTS {
+            *proto.jit.state.borrow_mut() = State::Rejected;
+        }
+    }
+    result
+}
+
+/// Runs `native` unless a guard fails.
+fn run(native: &Native, proto: &Rc<Proto>, env: &Env, args: &[MalType]) -> Option<MalType> {
+    let mut nums = Vec::with_capacity(args.len());
+    for arg in args {
+        match **arg {
+            InnerMalType::Num(n) => nums.push(n),
+            _ => return None,
+        }
+    }
+    for &(idx, callee) in &native.guards {
+        if classify(proto, env, idx) != Some(callee) {
+            return None;
+        }
+    }
+    let mut deopt = 0u8;
+    let result = (native.entry)(&mut deopt, nums.as_ptr());
+    if deopt != 0 {
+        return None;
+    }
+    Some(match native.ret {
+        Kind::Num => new_mal!(Num(result)),
+        Kind::Bool => new_mal!(Bool(result != 0.0)),
+    })
+}
+
+fn new_module() -> JITModule {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").unwrap();
+    flags.set("use_colocated_libcalls", "false").unwrap();
+    flags.set("is_pic", "false").unwrap();
+    let isa = cranelift_native::builder()
+        .expect("the host is supported")
+        .finish(settings::Flags::new(flags))
+        .expect("the host ISA");
+    JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()))
+}
+
+/// Compiles `proto`, whose closure was made in `env`, if it is numeric.
+fn compile(proto: &Rc<Proto>, env: &Env) -> Option<Native> {
+    if proto.variadic || proto.in_env || !proto.captures.is_empty() {
+        return None;
+    }
+    MODULE.with(|module| {
+        let mut module = module.borrow_mut();
+        let module = module.get_or_insert_with(new_module);
+        // a fn returning booleans is found out by trying numbers first
+        let (ret, defined) = match define(module, proto, env, Kind::Num) {
+            Err(Retry) => (Kind::Bool, define(module, proto, env, Kind::Bool)),
+            defined => (Kind::Num, defined),
+        };
+        let (main, guards) = defined.ok()?;
+        let entry = define_entry(module, main, proto.binds.len(), ret).ok()?;
+        module.finalize_definitions().ok()?;
+        let code = module.get_finalized_function(entry);
+        Some(Native {
+            entry: unsafe { mem::transmute::<*const u8, Entry>(code) },
+            nparams: proto.binds.len(),
+            ret,
+            guards,
+            deopts: Cell::new(0),
+        })
+    })
+}
+
+/// Why a fn is not compiled.
+#[derive(Debug)]
+enum Reject {
+    Unsupported,
+    // it returns booleans, not numbers
+    Retry,
+}
+
+use self::Reject::{Retry, Unsupported};
+
+fn ir_type(kind: Kind) -> Type {
+    match kind {
+        Kind::Num => types::F64,
+        Kind::Bool => types::I8,
+    }
+}
+
+/// Defines the code of `proto` returning `ret`: it takes the deopt flag,
+/// the depth of its calls to itself and the args.
+fn define(
+    module: &mut JITModule,
+    proto: &Rc<Proto>,
+    env: &Env,
+    ret: Kind,
+) -> Result<(FuncId, Vec<(usize, Callee)>), Reject> {
+    let nparams = proto.binds.len();
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.params.push(AbiParam::new(types::I64));
+    for _ in 0..nparams {
+        sig.params.push(AbiParam::new(types::F64));
+    }
+    sig.returns.push(AbiParam::new(ir_type(ret)));
+    let id = module
+        .declare_anonymous_function(&sig)
+        .map_err(|_| Unsupported)?;
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig;
+    ctx.func.name = UserFuncName::user(0, id.as_u32());
+    let mut fn_ctx = FunctionBuilderContext::new();
+    let guards = {
+        let builder = FunctionBuilder::new(&mut ctx.func, &mut fn_ctx);
+        let own = module.declare_func_in_func(id, builder.func);
+        let mut translator = Translator {
+            proto,
+            env,
+            ret,
+            builder,
+            own,
+            guards: Vec::new(),
+            blocks: HashMap::new(),
+            entered: HashMap::new(),
+            stack: Vec::new(),
+            regs: vec![None; proto.nregs],
+            reachable: true,
+            flag: None,
+            depth: None,
+            header: None,
+        };
+        let translated = translator.translate();
+        translator.builder.seal_all_blocks();
+        translator.builder.finalize();
+        translated?;
+        translator.guards
+    };
+    define_context(module, id, &mut ctx)?;
+    Ok((id, guards))
+}
+
+/// Defines the entry called from Rust, which reads the args from memory
+/// and returns a boolean as 0 or 1.
+fn define_entry(
+    module: &mut JITModule,
+    main: FuncId,
+    nparams: usize,
+    ret: Kind,
+) -> Result<FuncId, Reject> {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::F64));
+    let id = module
+        .declare_anonymous_function(&sig)
+        .map_err(|_| Unsupported)?;
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig;
+    ctx.func.name = UserFuncName::user(0, id.as_u32());
+    let mut fn_ctx = FunctionBuilderContext::new();
+    {
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut fn_ctx);
+        let callee = module.declare_func_in_func(main, b.func);
+        let block = b.create_block();
+        b.append_block_params_for_function_params(block);
+        b.switch_to_block(block);
+        let flag = b.block_params(block)[0];
+        let args_ptr = b.block_params(block)[1];
+        let mut args = vec![flag, b.ins().iconst(types::I64, 0)];
+        for i in 0..nparams {
+            let arg = b
+                .ins()
+                .load(types::F64, MemFlags::trusted(), args_ptr, (i * 8) as i32);
+            args.push(arg);
+        }
+        let call = b.ins().call(callee, &args);
+        let mut result = b.inst_results(call)[0];
+        if ret == Kind::Bool {
+            let one = b.ins().f64const(1.0);
+            let zero = b.ins().f64const(0.0);
+            result = b.ins().select(result, one, zero);
+        }
+        b.ins().return_(&[result]);
+        b.seal_all_blocks();
+        b.finalize();
+    }
+    define_context(module, id, &mut ctx)?;
+    Ok(id)
+}
+
+fn define_context(module: &mut JITModule, id: FuncId, ctx: &mut Context) -> Result<(), Reject> {
+    module.define_function(id, ctx).map_err(|_| Unsupported)?;
+    module.clear_context(ctx);
+    Ok(())
+}
+
+/// A value on the stack of the bytecode, as it is translated.
+#[derive(Clone, Copy)]
+enum Val {
+    Num(Value),
+    Bool(Value),
+    Callee(Callee),
+}
+
+/// What is on the stack at a jump target: values passed as block params,
+/// or callees, which are known.
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    Param(Kind),
+    Callee(Callee),
+}
+
+struct Translator<'a> {
+    proto: &'a Rc<Proto>,
+    env: &'a Env,
+    ret: Kind,
+    builder: FunctionBuilder<'a>,
+    own: cranelift_codegen::ir::FuncRef,
+    guards: Vec<(usize, Callee)>,
+    // jump targets, with the shape of the stack there once known
+    blocks: HashMap<usize, (Block, Option<Vec<Shape>>)>,
+    // the kinds of the registers at the jump targets translated, which a
+    // jump back to one, like a `recur`, should keep
+    entered: HashMap<usize, Vec<Option<Kind>>>,
+    stack: Vec<Val>,
+    // the kind of the value of each register
+    regs: Vec<Option<Kind>>,
+    reachable: bool,
+    flag: Option<Value>,
+    depth: Option<Value>,
+    // where a call to itself in tail position loops to
+    header: Option<Block>,
+}
+
+fn var(reg: usize, kind: Kind) -> Variable {
+    Variable::new(reg * 2 + if kind == Kind::Num { 0 } else { 1 })
+}
+
+impl<'a> Translator<'a> {
+    fn translate(&mut self) -> Result<(), Reject> {
+        let nparams = self.proto.binds.len();
+        for (reg, _) in self.regs.iter().enumerate() {
+            self.builder.declare_var(var(reg, Kind::Num), types::F64);
+            self.builder.declare_var(var(reg, Kind::Bool), types::I8);
+        }
+        for op in &self.proto.code {
+            match *op {
+                Op::Jump(ip) | Op::JumpIfFalse(ip) => {
+                    let block = self.builder.create_block();
+                    self.blocks.insert(ip as usize, (block, None));
+                }
+                _ => {}
+            }
+        }
+
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let params = self.builder.block_params(entry).to_vec();
+        self.flag = Some(params[0]);
+        self.depth = Some(params[1]);
+        let too_deep = self.builder.ins().icmp_imm(
+            cranelift_codegen::ir::condcodes::IntCC::SignedGreaterThan,
+            params[1],
+            MAX_DEPTH,
+        );
+        let deopt = self.builder.create_block();
+        let header = self.builder.create_block();
+        for _ in 0..nparams {
+            self.builder.append_block_param(header, types::F64);
+        }
+        self.builder
+            .ins()
+            .brif(too_deep, deopt, &[], header, &params[2..]);
+        self.switch_to_deopt(deopt);
+
+        self.builder.switch_to_block(header);
+        let args = self.builder.block_params(header).to_vec();
+        for (reg, arg) in args.into_iter().enumerate() {
+            self.builder.def_var(var(reg, Kind::Num), arg);
+            self.regs[reg] = Some(Kind::Num);
+        }
+        self.header = Some(header);
+
+        for ip in 0..self.proto.code.len() {
+            self.enter(ip)?;
+            if self.reachable {
+                self.op(self.proto.code[ip])?;
+            }
+        }
+        if self.reachable {
+            return Err(Unsupported);
+        }
+        Ok(())
+    }
+
+    /// Fills `block` with setting the deopt flag and returning.
+    fn switch_to_deopt(&mut self, block: Block) {
+        self.builder.switch_to_block(block);
+        let one = self.builder.ins().iconst(types::I8, 1);
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), one, self.flag.unwrap(), 0);
+        let zero = match self.ret {
+            Kind::Num => self.builder.ins().f64const(0.0),
+            Kind::Bool => self.builder.ins().iconst(types::I8, 0),
+        };
+        self.builder.ins().return_(&[zero]);
+    }
+
+    /// Continues at the jump target `ip`, if it is one.
+    fn enter(&mut self, ip: usize) -> Result<(), Reject> {
+        let (block, shape) = match self.blocks.get(&ip) {
+            Some(&(block, ref shape)) => (block, shape.clone()),
+            None => return Ok(()),
+        };
+        if self.reachable {
+            self.jump(ip)?;
+        }
+        let shape = match shape.or_else(|| self.blocks[&ip].1.clone()) {
+            Some(shape) => shape,
+            // no jump leads here
+            None => return Ok(()),
+        };
+        self.builder.switch_to_block(block);
+        self.entered.insert(ip, self.regs.clone());
+        let mut params = self.builder.block_params(block).to_vec().into_iter();
+        self.stack = shape
+            .iter()
+            .map(|s| match *s {
+                Shape::Param(Kind::Num) => Val::Num(params.next().unwrap()),
+                Shape::Param(Kind::Bool) => Val::Bool(params.next().unwrap()),
+                Shape::Callee(callee) => Val::Callee(callee),
+            })
+            .collect();
+        self.reachable = true;
+        Ok(())
+    }
+
+    /// The block of the jump target `ip`, and the args passing the stack
+    /// to it.
+    fn target(&mut self, ip: usize) -> Result<(Block, Vec<Value>), Reject> {
+        if let Some(kinds) = self.entered.get(&ip) {
+            let kept = kinds
+                .iter()
+                .zip(&self.regs)
+                .all(|(at, now)| at.is_none() || at == now);
+            if !kept {
+                return Err(Unsupported);
+            }
+        }
+        let shape: Vec<Shape> = self
+            .stack
+            .iter()
+            .map(|v| match *v {
+                Val::Num(_) => Shape::Param(Kind::Num),
+                Val::Bool(_) => Shape::Param(Kind::Bool),
+                Val::Callee(callee) => Shape::Callee(callee),
+            })
+            .collect();
+        let args = self
+            .stack
+            .iter()
+            .filter_map(|v| match *v {
+                Val::Num(v) | Val::Bool(v) => Some(v),
+                Val::Callee(_) => None,
+            })
+            .collect();
+        let (block, known) = self.blocks[&ip].clone();
+        match known {
+            Some(ref known) if *known != shape => return Err(Unsupported),
+            Some(_) => {}
+            None => {
+                for s in &shape {
+                    if let Shape::Param(kind) = *s {
+                        self.builder.append_block_param(block, ir_type(kind));
+                    }
+                }
+                self.blocks.insert(ip, (block, Some(shape)));
+            }
+        }
+        Ok((block, args))
+    }
+
+    fn jump(&mut self, ip: usize) -> Result<(), Reject> {
+        let (block, args) = self.target(ip)?;
+        self.builder.ins().jump(block, &args);
+        self.reachable = false;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Val {
+        self.stack.pop().unwrap()
+    }
+
+    fn ret(&mut self, value: Val) -> Result<(), Reject> {
+        let v = match (value, self.ret) {
+            (Val::Num(v), Kind::Num) | (Val::Bool(v), Kind::Bool) => v,
+            (Val::Bool(_), Kind::Num) => return Err(Retry),
+            _ => return Err(Unsupported),
+        };
+        self.builder.ins().return_(&[v]);
+        self.reachable = false;
+        Ok(())
+    }
+
+    fn op(&mut self, op: Op) -> Result<(), Reject> {
+        match op {
+            Op::Const(idx) => {
+                let value = match *self.proto.consts[idx as usize] {
+                    InnerMalType::Num(n) => Val::Num(self.builder.ins().f64const(n)),
+                    InnerMalType::Bool(b) => {
+                        Val::Bool(self.builder.ins().iconst(types::I8, b as i64))
+                    }
+                    _ => return Err(Unsupported),
+                };
+                self.stack.push(value);
+            }
+            Op::Reg(reg) => {
+                let reg = reg as usize;
+                let value = match self.regs[reg] {
+                    Some(Kind::Num) => Val::Num(self.builder.use_var(var(reg, Kind::Num))),
+                    Some(Kind::Bool) => Val::Bool(self.builder.use_var(var(reg, Kind::Bool))),
+                    None => return Err(Unsupported),
+                };
+                self.stack.push(value);
+            }
+            Op::SetReg(reg) => {
+                let reg = reg as usize;
+                let (kind, value) = match self.pop() {
+                    Val::Num(v) => (Kind::Num, v),
+                    Val::Bool(v) => (Kind::Bool, v),
+                    Val::Callee(_) => return Err(Unsupported),
+                };
+                self.builder.def_var(var(reg, kind), value);
+                self.regs[reg] = Some(kind);
+            }
+            Op::Global(idx) => {
+                let idx = idx as usize;
+                let callee = classify(self.proto, self.env, idx).ok_or(Unsupported)?;
+                self.guards.push((idx, callee));
+                self.stack.push(Val::Callee(callee));
+            }
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Jump(ip) => self.jump(ip as usize)?,
+            Op::JumpIfFalse(ip) => match self.pop() {
+                Val::Bool(cond) => {
+                    let (target, args) = self.target(ip as usize)?;
+                    let next = self.builder.create_block();
+                    self.builder.ins().brif(cond, next, &[], target, &args);
+                    self.builder.switch_to_block(next);
+                }
+                // numbers are true
+                Val::Num(_) => {}
+                Val::Callee(_) => return Err(Unsupported),
+            },
+            Op::Call(argc) => {
+                let value = self.call(argc as usize, false)?;
+                if let Some(value) = value {
+                    self.stack.push(value);
+                }
+            }
+            Op::TailCall(argc) => {
+                if let Some(value) = self.call(argc as usize, true)? {
+                    self.ret(value)?;
+                }
+            }
+            Op::Return => {
+                let value = self.pop();
+                self.ret(value)?;
+            }
+            _ => return Err(Unsupported),
+        }
+        Ok(())
+    }
+
+    /// Translates a call of `argc` args. A call to itself in tail position
+    /// loops, and has no value.
+    fn call(&mut self, argc: usize, tail: bool) -> Result<Option<Val>, Reject> {
+        let at = self.stack.len() - argc;
+        let mut args = Vec::with_capacity(argc);
+        for v in self.stack.split_off(at) {
+            match v {
+                Val::Num(v) => args.push(v),
+                _ => return Err(Unsupported),
+            }
+        }
+        let callee = match self.pop() {
+            Val::Callee(callee) => callee,
+            _ => return Err(Unsupported),
+        };
+        let prim = match callee {
+            Callee::Prim(prim) => prim,
+            Callee::Own => {
+                if args.len() != self.proto.binds.len() {
+                    return Err(Unsupported);
+                }
+                return self.call_own(args, tail);
+            }
+        };
+        if args.len() != 2 {
+            return Err(Unsupported);
+        }
+        let (a, b) = (args[0], args[1]);
+        let ins = self.builder.ins();
+        Ok(Some(match prim {
+            Prim::Add => Val::Num(ins.fadd(a, b)),
+            Prim::Sub => Val::Num(ins.fsub(a, b)),
+            Prim::Mul => Val::Num(ins.fmul(a, b)),
+            Prim::Div => Val::Num(ins.fdiv(a, b)),
+            Prim::Eq => Val::Bool(ins.fcmp(FloatCC::Equal, a, b)),
+            Prim::Lt => Val::Bool(ins.fcmp(FloatCC::LessThan, a, b)),
+            Prim::Le => Val::Bool(ins.fcmp(FloatCC::LessThanOrEqual, a, b)),
+            Prim::Gt => Val::Bool(ins.fcmp(FloatCC::GreaterThan, a, b)),
+            Prim::Ge => Val::Bool(ins.fcmp(FloatCC::GreaterThanOrEqual, a, b)),
+        }))
+    }
+
+    fn call_own(&mut self, args: Vec<Value>, tail: bool) -> Result<Option<Val>, Reject> {
+        if tail {
+            let header = self.header.unwrap();
+            self.builder.ins().jump(header, &args);
+            self.reachable = false;
+            return Ok(None);
+        }
+        let flag = self.flag.unwrap();
+        let depth = self.builder.ins().iadd_imm(self.depth.unwrap(), 1);
+        let mut call_args = vec![flag, depth];
+        call_args.extend(args);
+        let call = self.builder.ins().call(self.own, &call_args);
+        let result = self.builder.inst_results(call)[0];
+        // a deopt below unwinds to the entry
+        let deopted = self
+            .builder
+            .ins()
+            .load(types::I8, MemFlags::trusted(), flag, 0);
+        let deopt = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(deopted, deopt, &[], next, &[]);
+        self.switch_to_deopt(deopt);
+        self.builder.switch_to_block(next);
+        Ok(Some(match self.ret {
+            Kind::Num => Val::Num(result),
+            Kind::Bool => Val::Bool(result),
+        }))
+    }
+}
@@ -0,0 +1,352 @@
+use failure::Fallible;
+use stack;
+use std::cell::RefCell;
+use std::collections::linked_list;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use types::{drop_all, InnerMalType, MalType};
+
+/// Result of realising one cell of a sequence: `None` when the sequence is
+/// empty, otherwise its first element and the rest of the sequence.
+pub type Step = Option<(MalType, MalType)>;
+
+/// A producer for the next cell of a lazy sequence.
+///
+/// `Fn` thunks are opaque and may return anything seqable. `Step` thunks
+/// declare the sequences they depend on so that `LazySeq::realize` can force
+/// those first from an explicit stack, which keeps long chains of nested
+/// lazy operations (`map` over `map` over ...) from recursing on the Rust
+/// stack.
+#[derive(Clone)]
+pub enum Thunk {
+    Fn(Rc<dyn Fn() -> Fallible<MalType>>),
+    Step(Vec<MalType>, Rc<dyn Fn(Vec<Step>) -> Fallible<MalType>>),
+}
+
+enum LazyState {
+    Pending(Thunk),
+    Realizing,
+    Cons(MalType, MalType),
+    Empty,
+}
+
+/// A memoised, possibly infinite sequence. Once realised a cell is either
+/// `Empty` or `Cons(first, rest)` where `rest` is always `Nil` or another
+/// `LazySeq`.
+#[derive(Clone)]
+pub struct LazySeq(Rc<RefCell<LazyState>>);
+
+impl LazySeq {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn() -> Fallible<MalType> + 'static,
+    {
+        LazySeq::from_thunk(Thunk::Fn(Rc::new(f)))
+    }
+
+    pub fn step_on<F>(sources: Vec<MalType>, f: F) -> Self
+    where
+        F: Fn(Vec<Step>) -> Fallible<MalType> + 'static,
+    {
+        LazySeq::from_thunk(Thunk::Step(sources, Rc::new(f)))
+    }
+
+    pub fn from_thunk(thunk: Thunk) -> Self {
+        LazySeq(Rc::new(RefCell::new(LazyState::Pending(thunk))))
+    }
+
+    pub fn cons(first: MalType, rest: MalType) -> Self {
+        LazySeq(Rc::new(RefCell::new(LazyState::Cons(first, rest))))
+    }
+
+    pub fn is_realized(&self) -> bool {
+        matches!(*self.0.borrow(), LazyState::Cons(..) | LazyState::Empty)
+    }
+
+    /// Realises the first cell and returns it.
+    pub fn step(&self) -> Fallible<Step> {
+        self.realize()?;
+        Ok(self.realized_step())
+    }
+
+    fn realized_step(&self) -> Step {
+        match *self.0.borrow() {
+            LazyState::Cons(ref first, ref rest) => Some((first.clone(), rest.clone())),
+            LazyState::Empty => None,
+            _ => unreachable!(),
+        }
+    }
+
+    fn take_thunk(&self) -> Fallible<Option<Thunk>> {
+        let mut state = self.0.borrow_mut();
+        match mem::replace(&mut *state, LazyState::Realizing) {
+            LazyState::Pending(thunk) => Ok(Some(thunk)),
+            LazyState::Realizing => bail!("lazy seq depends on its own realisation"),
+            realized => {
+                *state = realized;
+                Ok(None)
+            }
+        }
+    }
+
+    fn set_state(&self, state: LazyState) {
+        *self.0.borrow_mut() = state;
+    }
+
+    fn realize(&self) -> Fallible<()> {
+        let thunk = match self.take_thunk()? {
+            Some(thunk) => thunk,
+            None => return Ok(()),
+        };
+        let mut stack: Vec<(LazySeq, Thunk)> = vec![(self.clone(), thunk)];
+
+        while !stack.is_empty() {
+            let result = match stack.last().unwrap().1.clone() {
+                // an opaque thunk may realise other seqs from Rust
+                Thunk::Fn(f) => stack::nest(|| f()),
+                Thunk::Step(sources, f) => {
+                    let mut blocked = None;
+                    for source in &sources {
+                        if let InnerMalType::LazySeq(ref seq, ..) = **source {
+                            match seq.take_thunk() {
+                                Ok(Some(thunk)) => {
+                                    blocked = Some((seq.clone(), thunk));
+                                    break;
+                                }
+                                Ok(None) => {}
+                                Err(e) => {
+                                    restore(stack);
+                                    return Err(e);
+                                }
+                            }
+                        }
+                    }
+                    if let Some(frame) = blocked {
+                        stack.push(frame);
+                        continue;
+                    }
+                    sources
+                        .iter()
+                        .map(source_step)
+                        .collect::<Fallible<Vec<Step>>>()
+                        .and_then(|steps| f(steps))
+                }
+            };
+
+            let value = match result {
+                Ok(value) => value,
+                Err(e) => {
+                    restore(stack);
+                    return Err(e);
+                }
+            };
+            let (seq, thunk) = stack.pop().unwrap();
+            match *value {
+                InnerMalType::LazySeq(ref inner, ..) if !inner.is_realized() => {
+                    // The value is whatever `inner` realises to.
+                    stack.push((
+                        seq,
+                        Thunk::Step(
+                            vec![value.clone()],
+                            Rc::new(|mut steps: Vec<Step>| Ok(cell(steps.remove(0)))),
+                        ),
+                    ));
+                }
+                InnerMalType::LazySeq(ref inner, ..) => {
+                    seq.set_state(match inner.realized_step() {
+                        Some((first, rest)) => LazyState::Cons(first, rest),
+                        None => LazyState::Empty,
+                    });
+                }
+                InnerMalType::Nil => seq.set_state(LazyState::Empty),
+                InnerMalType::List(ref l, ..) | InnerMalType::Vec(ref l, ..) => {
+                    seq.set_state(state_from_iter(Rc::new(RefCell::new(l.clone().into_iter()))));
+                }
+                _ => {
+                    stack.push((seq, thunk));
+                    restore(stack);
+                    bail!("lazy seq should produce a sequence, got {:?}", value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves out everything this cell references so that dropping a long
+    /// chain does not recurse once per cell.
+    /// Moves what this seq holds to `out`, unless the seq is shared.
+    pub(crate) fn take_unshared(&self, out: &mut Vec<MalType>) {
+        if Rc::strong_count(&self.0) == 1 {
+            self.take_children(out);
+        }
+    }
+
+    fn take_children(&self, out: &mut Vec<MalType>) {
+        if let Ok(mut state) = self.0.try_borrow_mut() {
+            match mem::replace(&mut *state, LazyState::Empty) {
+                LazyState::Cons(first, rest) => {
+                    out.push(first);
+                    out.push(rest);
+                }
+                LazyState::Pending(Thunk::Step(sources, _)) => out.extend(sources),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn restore(stack: Vec<(LazySeq, Thunk)>) {
+    for (seq, thunk) in stack {
+        seq.set_state(LazyState::Pending(thunk));
+    }
+}
+
+fn source_step(source: &MalType) -> Fallible<Step> {
+    match **source {
+        InnerMalType::LazySeq(ref seq, ..) => Ok(seq.realized_step()),
+        _ => seq_step(source),
+    }
+}
+
+fn state_from_iter(iter: Rc<RefCell<linked_list::IntoIter<MalType>>>) -> LazyState {
+    let first = iter.borrow_mut().next();
+    match first {
+        Some(first) => {
+            let rest = LazySeq::new(move || {
+                Ok(new_mal!(LazySeq(
+                    LazySeq(Rc::new(RefCell::new(state_from_iter(iter.clone())))),
+                    new_mal!(Nil)
+                )))
+            });
+            LazyState::Cons(first, new_mal!(LazySeq(rest, new_mal!(Nil))))
+        }
+        None => LazyState::Empty,
+    }
+}
+
+impl Drop for LazySeq {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.take_unshared(&mut pending);
+        drop_all(pending);
+    }
+}
+
+impl fmt::Debug for LazySeq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_realized() {
+            write!(f, "LazySeq({:?})", self.realized_step())
+        } else {
+            write!(f, "LazySeq(..)")
+        }
+    }
+}
+
+impl PartialEq for LazySeq {
+    fn eq(&self, other: &LazySeq) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Builds a lazy sequence value from an optional first cell.
+pub fn cell(step: Step) -> MalType {
+    match step {
+        Some((first, rest)) => new_mal!(LazySeq(LazySeq::cons(first, rest), new_mal!(Nil))),
+        None => new_mal!(Nil),
+    }
+}
+
+/// Wraps any seqable value as `Nil` or a `LazySeq`, the representation
+/// expected for the sources of a `Thunk::Step`.
+pub fn lazy_source(coll: &MalType) -> Fallible<MalType> {
+    Ok(match **coll {
+        InnerMalType::LazySeq(..) | InnerMalType::Nil => coll.clone(),
+        InnerMalType::List(ref l, ..) | InnerMalType::Vec(ref l, ..) => new_mal!(LazySeq(
+            LazySeq(Rc::new(RefCell::new(state_from_iter(Rc::new(RefCell::new(
+                l.clone().into_iter()
+            )))))),
+            new_mal!(Nil)
+        )),
+        InnerMalType::String(ref s) => new_mal!(LazySeq(
+            LazySeq(Rc::new(RefCell::new(state_from_iter(Rc::new(RefCell::new(
+                s.chars()
+                    .map(|c| new_mal!(String(c.to_string())))
+                    .collect::<linked_list::LinkedList<MalType>>()
+                    .into_iter()
+            )))))),
+            new_mal!(Nil)
+        )),
+        _ => bail_type!("{:?} is not a sequence", coll),
+    })
+}
+
+/// Realises the first cell of any seqable value.
+pub fn seq_step(coll: &MalType) -> Fallible<Step> {
+    match **coll {
+        InnerMalType::LazySeq(ref seq, ..) => seq.step(),
+        InnerMalType::Nil => Ok(None),
+        InnerMalType::List(ref l, ..) | InnerMalType::Vec(ref l, ..) => {
+            let mut rest = l.clone();
+            Ok(rest
+                .pop_front()
+                .map(|first| (first, new_mal!(List(rest, new_mal!(Nil))))))
+        }
+        _ => lazy_source(coll).and_then(|s| seq_step(&s)),
+    }
+}
+
+enum Cursor {
+    Items(linked_list::IntoIter<MalType>),
+    Lazy(MalType),
+    Done,
+}
+
+/// Iterates over the elements of any seqable value, realising lazy cells as
+/// it goes.
+pub struct SeqIter {
+    cursor: Cursor,
+}
+
+impl Iterator for SeqIter {
+    type Item = Fallible<MalType>;
+
+    fn next(&mut self) -> Option<Fallible<MalType>> {
+        match mem::replace(&mut self.cursor, Cursor::Done) {
+            Cursor::Items(mut iter) => {
+                let next = iter.next();
+                self.cursor = Cursor::Items(iter);
+                next.map(Ok)
+            }
+            Cursor::Lazy(seq) => match seq_step(&seq) {
+                Ok(Some((first, rest))) => {
+                    if !rest.is_nil() {
+                        self.cursor = Cursor::Lazy(rest);
+                    }
+                    Some(Ok(first))
+                }
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            },
+            Cursor::Done => None,
+        }
+    }
+}
+
+pub fn seq_iter(coll: &MalType) -> Fallible<SeqIter> {
+    let cursor = match **coll {
+        InnerMalType::List(ref l, ..) | InnerMalType::Vec(ref l, ..) => {
+            Cursor::Items(l.clone().into_iter())
+        }
+        InnerMalType::LazySeq(..) => Cursor::Lazy(coll.clone()),
+        InnerMalType::Nil => Cursor::Done,
+        _ => Cursor::Lazy(lazy_source(coll)?),
+    };
+    Ok(SeqIter { cursor })
+}
+
+/// Fully realises a seqable value into a list of its elements.
+pub fn seq_to_list(coll: &MalType) -> Fallible<linked_list::LinkedList<MalType>> {
+    seq_iter(coll)?.collect()
+}
@@ -10,11 +10,57 @@ extern crate lazy_static;
 extern crate debug_stub_derive;
 extern crate fnv;
 extern crate time;
+extern crate rs_derive;
+#[macro_use]
+extern crate serde;
+extern crate stacker;
+#[cfg(jit)]
+extern crate cranelift_codegen;
+#[cfg(jit)]
+extern crate cranelift_frontend;
+#[cfg(jit)]
+extern crate cranelift_jit;
+#[cfg(jit)]
+extern crate cranelift_module;
+#[cfg(jit)]
+extern crate cranelift_native;
 
+#[macro_use]
+pub mod error;
 #[macro_use]
 pub mod types;
+pub mod analyze;
+pub mod arity;
+pub mod console;
+pub mod convert;
+pub mod capi;
+pub mod compile;
 pub mod core;
+pub mod de;
+pub mod destructure;
+pub mod doc;
 pub mod env;
-pub mod error;
+pub mod eval;
+pub mod exception;
+pub mod gc;
+pub mod interpreter;
+#[cfg(jit)]
+pub mod jit;
+pub mod lazy;
+pub mod module;
+pub mod multi;
+pub mod namespace;
 pub mod printer;
+pub mod protocol;
 pub mod reader;
+pub mod record;
+pub mod runtime;
+pub mod ser;
+pub mod stack;
+pub mod symbol;
+pub mod trace;
+pub mod vm;
+
+pub use convert::{FromMal, IntoMal};
+pub use interpreter::Interpreter;
+pub use rs_derive::{FromMal, IntoMal};
@@ -0,0 +1,174 @@
+use env::{env_get, env_set};
+use eval::eval_top;
+use failure::Fallible;
+use gc;
+use namespace::{current, find_ns, remove_ns, set_current, CORE};
+use reader::read_file;
+use runtime;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use symbol::Sym;
+use types::{InnerMalType, MalType};
+
+/// The initial `*load-path*`, an atom of the directories of `MAL_PATH` and
+/// then the working directory.
+pub fn default_load_path() -> MalType {
+    let mut dirs: Vec<MalType> = match env::var_os("MAL_PATH") {
+        Some(paths) => env::split_paths(&paths)
+            .map(|p| new_mal!(String(p.to_string_lossy().into_owned())))
+            .collect(),
+        None => Vec::new(),
+    };
+    dirs.push(new_mal!(String(".".to_string())));
+    gc::new_atom(new_mal!(Vec(dirs.into_iter().collect(), new_mal!(Nil))))
+}
+
+fn core_var(name: &str) -> MalType {
+    let core = find_ns(Sym::new(CORE)).expect("mal.core namespace");
+    env_get(core.env.clone(), name).unwrap_or_else(|| new_mal!(Nil))
+}
+
+fn set_core_var(name: &str, value: MalType) {
+    let core = find_ns(Sym::new(CORE)).expect("mal.core namespace");
+    env_set(core.env.clone(), name, value);
+}
+
+/// The directory of the file being loaded, if any.
+fn loading_dir() -> Option<PathBuf> {
+    match *core_var("*file*") {
+        InnerMalType::String(ref file) => Path::new(file).parent().map(|p| p.to_path_buf()),
+        _ => None,
+    }
+}
+
+/// `path` resolved against the directory of the file being loaded when it
+/// is relative and exists there, else against the working directory.
+pub fn resolve_path(path: &str) -> PathBuf {
+    let path = PathBuf::from(path);
+    if path.is_relative() {
+        if let Some(dir) = loading_dir() {
+            let candidate = dir.join(&path);
+            if candidate.is_file() {
+                return candidate;
+            }
+        }
+    }
+    path
+}
+
+/// `foo.bar-baz` lives in `foo/bar_baz.mal`.
+fn module_file(name: &str) -> PathBuf {
+    let mut path: PathBuf = name.replace('-', "_").split('.').collect();
+    path.set_extension("mal");
+    path
+}
+
+fn find_module(name: Sym) -> Fallible<PathBuf> {
+    let file = module_file(&name);
+    let load_path = core_var("*load-path*");
+    ensure!(
+        load_path.is_atom() && {
+            let dirs = load_path.to_atom();
+            dirs.is_vec() || dirs.is_list()
+        },
+        "*load-path* should be an atom of a vector of directories"
+    );
+    let mut dirs: Vec<PathBuf> = loading_dir().into_iter().collect();
+    for dir in load_path.to_atom().to_items_ref() {
+        ensure!(
+            dir.is_string(),
+            "*load-path* should be an atom of a vector of directories"
+        );
+        dirs.push(PathBuf::from(dir.to_string()));
+    }
+    match dirs.iter().map(|dir| dir.join(&file)).find(|p| p.is_file()) {
+        Some(path) => Ok(path),
+        None => bail!(
+            "could not find module {} as {} in *load-path*",
+            name,
+            file.display()
+        ),
+    }
+}
+
+/// Evaluates the forms of the file at `path` with `*file*` bound to it. The
+/// current namespace and `*file*` are restored afterwards, even on error.
+pub fn load_file(path: &Path) -> Fallible<MalType> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            let message = format!("could not read {}: {}", path.display(), e);
+            return Err(io::Error::new(e.kind(), message).into());
+        }
+    };
+    let form = read_file(&content, &path.display().to_string())?;
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let saved_ns = current();
+    let saved_file = core_var("*file*");
+    set_core_var(
+        "*file*",
+        new_mal!(String(path.to_string_lossy().into_owned())),
+    );
+    let result = eval_top(form);
+    set_current(saved_ns);
+    set_core_var("*file*", saved_file);
+    result
+}
+
+/// Loads the module defining namespace `name` unless it is loaded, or
+/// defined otherwise, already. `reload` loads it again regardless. A load
+/// which fails leaves no namespace it created behind.
+pub fn require(name: Sym, reload: bool) -> Fallible<()> {
+    let runtime = runtime::current();
+    let chain = runtime.loading.borrow().clone();
+    if chain.contains(&name) {
+        let names: Vec<String> = chain
+            .iter()
+            .skip_while(|n| **n != name)
+            .chain(Some(&name))
+            .map(|n| n.to_string())
+            .collect();
+        bail!("circular require: {}", names.join(" -> "));
+    }
+    let loaded = runtime.loaded.borrow().contains(&name);
+    if loaded && !reload {
+        return Ok(());
+    }
+
+    let path = find_module(name)?;
+    let existed = find_ns(name).is_some();
+    runtime.loading.borrow_mut().push(name);
+    let result = load_file(&path).and_then(|_| {
+        ensure!(
+            find_ns(name).is_some(),
+            "{} did not define namespace {}",
+            path.display(),
+            name
+        );
+        Ok(())
+    });
+    runtime.loading.borrow_mut().pop();
+    match result {
+        Ok(()) => defined(name),
+        Err(_) if !existed => remove_ns(name),
+        Err(_) => {}
+    }
+    result
+}
+
+/// Records namespace `name` as loaded, unless it is being defined by the
+/// load of its module, which may yet fail.
+pub fn defined(name: Sym) {
+    let runtime = runtime::current();
+    if runtime.loading.borrow().last() == Some(&name) {
+        return;
+    }
+    let mut loaded = runtime.loaded.borrow_mut();
+    if !loaded.contains(&name) {
+        loaded.push(name);
+    }
+}
@@ -0,0 +1,304 @@
+use core::eq;
+use failure::Fallible;
+use printer::pr_str;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::fmt;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{Closure, MalType, InnerMalType, Kind};
+
+thread_local! {
+    // The global ad-hoc hierarchy as (child, parent) edges.
+    static HIERARCHY: RefCell<Vec<(MalType, MalType)>> = const { RefCell::new(Vec::new()) };
+    // Bumped by every `derive` so that dispatch caches notice.
+    static HIERARCHY_VERSION: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn derive(child: MalType, parent: MalType) -> Fallible<()> {
+    ensure_type!(
+        child.is_keyword() || child.is_symbol() || child.is_type(),
+        "derive's child should be keyword, symbol or type"
+    );
+    ensure_type!(
+        parent.is_keyword() || parent.is_symbol(),
+        "derive's parent should be keyword or symbol"
+    );
+    ensure!(
+        !isa(&parent, &child)?,
+        "cyclic derivation: {} is already a {}",
+        pr_str(&parent, true),
+        pr_str(&child, true)
+    );
+    if parents(&child)?.contains(&parent) {
+        return Ok(());
+    }
+    HIERARCHY.with(|h| h.borrow_mut().push((child, parent)));
+    HIERARCHY_VERSION.with(|v| v.set(v.get() + 1));
+    Ok(())
+}
+
+/// The direct parents of `x`. Types also have the built-in parents given by
+/// `Kind::supers`.
+pub fn parents(x: &MalType) -> Fallible<Vec<MalType>> {
+    let mut found = Vec::new();
+    HIERARCHY.with(|h| -> Fallible<()> {
+        for (child, parent) in h.borrow().iter() {
+            if eq(child.clone(), x.clone())? {
+                found.push(parent.clone());
+            }
+        }
+        Ok(())
+    })?;
+    if let InnerMalType::Type(ref kind) = **x {
+        for sup in kind.supers().into_iter().skip(1) {
+            found.push(new_mal!(Type(sup)));
+        }
+    }
+    Ok(found)
+}
+
+pub fn ancestors(x: &MalType) -> Fallible<Vec<MalType>> {
+    let mut found: Vec<MalType> = Vec::new();
+    let mut pending = parents(x)?;
+    while let Some(p) = pending.pop() {
+        if found.contains(&p) {
+            continue;
+        }
+        pending.extend(parents(&p)?);
+        found.push(p);
+    }
+    Ok(found)
+}
+
+/// True if `child` equals `parent`, derives from it, or both are vectors of
+/// the same length whose items are pairwise `isa`.
+pub fn isa(child: &MalType, parent: &MalType) -> Fallible<bool> {
+    if eq(child.clone(), parent.clone())? {
+        return Ok(true);
+    }
+    if child.is_vec() && parent.is_vec() {
+        if child.len() != parent.len() {
+            return Ok(false);
+        }
+        for (c, p) in child.to_items_ref().iter().zip(parent.to_items_ref()) {
+            if !isa(c, p)? {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+    for a in ancestors(child)? {
+        if eq(a, parent.clone())? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// A function created by `defmulti`, choosing a method by the value its
+/// dispatch function returns for the arguments.
+pub struct MultiFn {
+    pub name: String,
+    dispatch: MalType,
+    default: MalType,
+    methods: RefCell<Vec<(MalType, MalType)>>,
+    // (x, y) when x is preferred over y
+    prefers: RefCell<Vec<(MalType, MalType)>>,
+    cache: RefCell<HashMap<CacheKey, MalType>>,
+    cache_version: Cell<usize>,
+}
+
+impl MultiFn {
+    pub fn new(name: String, dispatch: MalType, default: MalType) -> Self {
+        MultiFn {
+            name,
+            dispatch,
+            default,
+            methods: RefCell::new(Vec::new()),
+            prefers: RefCell::new(Vec::new()),
+            cache: RefCell::new(HashMap::new()),
+            cache_version: Cell::new(0),
+        }
+    }
+
+    pub fn add_method(&self, value: MalType, f: MalType) -> Fallible<()> {
+        ensure_type!(f.is_closure(), "method of {} should be func", self.name);
+        self.remove_method(&value)?;
+        self.methods.borrow_mut().push((value, f));
+        Ok(())
+    }
+
+    pub fn remove_method(&self, value: &MalType) -> Fallible<()> {
+        let mut methods = self.methods.borrow_mut();
+        let mut kept = Vec::new();
+        for (v, f) in methods.drain(..) {
+            if !eq(v.clone(), value.clone())? {
+                kept.push((v, f));
+            }
+        }
+        *methods = kept;
+        self.cache.borrow_mut().clear();
+        Ok(())
+    }
+
+    pub fn methods(&self) -> Vec<(MalType, MalType)> {
+        self.methods.borrow().clone()
+    }
+
+    pub fn prefer_method(&self, x: MalType, y: MalType) -> Fallible<()> {
+        ensure!(
+            !self.prefers(&y, &x)?,
+            "preference conflict in multimethod {}: {} is already preferred to {}",
+            self.name,
+            pr_str(&y, true),
+            pr_str(&x, true)
+        );
+        self.prefers.borrow_mut().push((x, y));
+        self.cache.borrow_mut().clear();
+        Ok(())
+    }
+
+    fn prefers(&self, x: &MalType, y: &MalType) -> Fallible<bool> {
+        for (px, py) in self.prefers.borrow().iter() {
+            if eq(px.clone(), x.clone())? && eq(py.clone(), y.clone())? {
+                return Ok(true);
+            }
+        }
+        for p in parents(y)? {
+            if self.prefers(x, &p)? {
+                return Ok(true);
+            }
+        }
+        for p in parents(x)? {
+            if self.prefers(&p, y)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn dominates(&self, x: &MalType, y: &MalType) -> Fallible<bool> {
+        Ok(self.prefers(x, y)? || isa(x, y)?)
+    }
+
+    fn find_method(&self, value: &MalType) -> Fallible<MalType> {
+        let mut best: Option<(MalType, MalType)> = None;
+        for (v, f) in self.methods() {
+            if !isa(value, &v)? {
+                continue;
+            }
+            best = match best {
+                Some((bv, bf)) => {
+                    if self.dominates(&v, &bv)? {
+                        Some((v, f))
+                    } else if self.dominates(&bv, &v)? {
+                        Some((bv, bf))
+                    } else {
+                        bail!(
+                            "multiple methods in multimethod {} match dispatch value {}: {} and {}, and neither is preferred",
+                            self.name,
+                            pr_str(value, true),
+                            pr_str(&v, true),
+                            pr_str(&bv, true)
+                        )
+                    }
+                }
+                None => Some((v, f)),
+            };
+        }
+        if let Some((_, f)) = best {
+            return Ok(f);
+        }
+        for (v, f) in self.methods() {
+            if eq(v, self.default.clone())? {
+                return Ok(f);
+            }
+        }
+        bail!(
+            "no method in multimethod {} for dispatch value {}",
+            self.name,
+            pr_str(value, true)
+        )
+    }
+
+    /// The method to apply to `args`.
+    pub fn method_for(&self, args: &LinkedList<MalType>) -> Fallible<Closure> {
+        let value = if self.dispatch.is_keyword() {
+            // (defmulti name :key) dispatches on a key of the first argument
+            match args.front() {
+                Some(m) if m.is_hashmap() => m
+                    .to_hashmap_ref()
+                    .get(&self.dispatch.to_hash_key())
+                    .cloned()
+                    .unwrap_or_else(|| new_mal!(Nil)),
+                _ => new_mal!(Nil),
+            }
+        } else {
+            self.dispatch.to_closure().call(args.clone())?
+        };
+
+        let version = HIERARCHY_VERSION.with(|v| v.get());
+        if self.cache_version.get() != version {
+            self.cache.borrow_mut().clear();
+            self.cache_version.set(version);
+        }
+        let key = cache_key(&value);
+        if let Some(ref key) = key {
+            if let Some(f) = self.cache.borrow().get(key) {
+                return Ok(f.to_closure());
+            }
+        }
+        let f = self.find_method(&value)?;
+        if let Some(key) = key {
+            self.cache.borrow_mut().insert(key, f.clone());
+        }
+        Ok(f.to_closure())
+    }
+}
+
+/// A dispatch value as the cache of a multimethod keys it, tagged with its
+/// type so that, say, the symbol `nil` and nil are told apart.
+#[derive(PartialEq, Eq, Hash)]
+enum CacheKey {
+    Keyword(Sym),
+    Symbol(Sym),
+    String(String),
+    // the bits of the num, with -0 as 0
+    Num(u64),
+    Bool(bool),
+    Nil,
+    Type(Kind),
+    Vec(Vec<CacheKey>),
+}
+
+/// The key of a dispatch value which can be cached: one of scalars, types
+/// other than record types, and vectors of them.
+fn cache_key(value: &MalType) -> Option<CacheKey> {
+    Some(match **value {
+        InnerMalType::Keyword(k) => CacheKey::Keyword(k),
+        InnerMalType::Symbol(s) => CacheKey::Symbol(s),
+        InnerMalType::String(ref s) => CacheKey::String(s.clone()),
+        InnerMalType::Num(n) => CacheKey::Num(if n == 0f64 { 0 } else { n.to_bits() }),
+        InnerMalType::Bool(b) => CacheKey::Bool(b),
+        InnerMalType::Nil => CacheKey::Nil,
+        InnerMalType::Type(ref kind) if !kind.is_record() => CacheKey::Type(kind.clone()),
+        InnerMalType::Vec(ref l, ..) => {
+            CacheKey::Vec(l.iter().map(cache_key).collect::<Option<Vec<_>>>()?)
+        }
+        _ => return None,
+    })
+}
+
+impl fmt::Debug for MultiFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MultiFn({})", self.name)
+    }
+}
+
+impl PartialEq for MultiFn {
+    fn eq(&self, other: &MultiFn) -> bool {
+        ::std::ptr::eq(self, other)
+    }
+}
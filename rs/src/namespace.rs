@@ -0,0 +1,239 @@
+use env::{
+    env_binding, env_find, env_get, env_interns, env_new, env_refer, env_root, env_set, Binding,
+    Env,
+};
+use failure::Fallible;
+use fnv::FnvHashMap;
+use module;
+use runtime;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{HashKey, InnerMalType, MalType};
+
+/// The namespace of the built-ins, referred into every other namespace.
+pub const CORE: &str = "mal.core";
+
+/// A named root env for definitions. Other namespaces reach its public
+/// bindings as `name/sym`, through an alias, or by referring them.
+pub struct Namespace {
+    pub name: Sym,
+    pub env: Env,
+    aliases: RefCell<FnvHashMap<Sym, Rc<Namespace>>>,
+}
+
+pub fn find_ns(name: Sym) -> Option<Rc<Namespace>> {
+    let runtime = runtime::current();
+    let all = runtime.namespaces.borrow();
+    all.iter().find(|ns| ns.name == name).cloned()
+}
+
+/// The namespaces of the current runtime.
+pub fn all_ns() -> Vec<Rc<Namespace>> {
+    runtime::current().namespaces.borrow().clone()
+}
+
+/// The namespace named `name`, created with the public bindings of
+/// `mal.core` referred if it does not exist yet.
+pub fn create_ns(name: Sym) -> Rc<Namespace> {
+    if let Some(ns) = find_ns(name) {
+        return ns;
+    }
+    let ns = Rc::new(Namespace {
+        name,
+        env: env_new(None, Vec::new(), Vec::new()),
+        aliases: RefCell::new(FnvHashMap::default()),
+    });
+    if let Some(core) = find_ns(Sym::new(CORE)) {
+        for (key, binding) in core.publics() {
+            env_refer(&ns.env, key, binding);
+        }
+    }
+    runtime::current().namespaces.borrow_mut().push(ns.clone());
+    module::defined(name);
+    ns
+}
+
+/// Forgets the namespace named `name`, like one a failed load created.
+pub fn remove_ns(name: Sym) {
+    let runtime = runtime::current();
+    runtime.namespaces.borrow_mut().retain(|ns| ns.name != name);
+}
+
+/// The namespace whose definitions `env` sees.
+pub fn ns_of_env(env: &Env) -> Option<Rc<Namespace>> {
+    let root = env_root(env.clone());
+    let runtime = runtime::current();
+    let all = runtime.namespaces.borrow();
+    all.iter().find(|ns| Rc::ptr_eq(&ns.env, &root)).cloned()
+}
+
+/// The namespace `*ns*` names, in which top-level forms are evaluated.
+pub fn current() -> Rc<Namespace> {
+    let core = find_ns(Sym::new(CORE)).expect("mal.core namespace");
+    env_get(core.env.clone(), "*ns*")
+        .expect("*ns* binding")
+        .to_namespace()
+}
+
+/// Makes `ns` current. `*ns*` is referred from `mal.core`, so every
+/// namespace sees the change. Inside a `binding` of `*ns*`, only that
+/// binding changes.
+pub fn set_current(ns: Rc<Namespace>) {
+    let core = find_ns(Sym::new(CORE)).expect("mal.core namespace");
+    match env_binding(&core.env, Sym::new("*ns*")) {
+        Some(binding) => binding.set(new_mal!(Namespace(ns))),
+        None => env_set(core.env.clone(), "*ns*", new_mal!(Namespace(ns))),
+    }
+}
+
+/// `name` split at its first slash into a namespace and a name, if it is
+/// qualified like `foo.bar/baz`.
+pub fn split_qualified(name: &str) -> Option<(&str, &str)> {
+    let idx = name.find('/')?;
+    if idx == 0 || idx == name.len() - 1 {
+        return None;
+    }
+    Some((&name[..idx], &name[idx + 1..]))
+}
+
+/// Whether the metadata of `binding` sets the keyword `flag`, like
+/// `:private`, to a logical true value.
+fn has_flag(binding: &Binding, flag: &str) -> bool {
+    match *binding.meta.borrow() {
+        Some(ref meta) if meta.is_hashmap() => {
+            meta.to_hashmap_ref()
+                .get(&HashKey::Keyword(Sym::new(flag)))
+                .map(|p| !p.is_nil() && **p != InnerMalType::Bool(false))
+                == Some(true)
+        }
+        _ => false,
+    }
+}
+
+fn is_private(binding: &Binding) -> bool {
+    has_flag(binding, ":private")
+}
+
+impl Namespace {
+    /// The non-private bindings defined in this namespace.
+    pub fn publics(&self) -> Vec<(Sym, Rc<Binding>)> {
+        env_interns(&self.env)
+            .into_iter()
+            .filter(|(_, binding)| !is_private(binding))
+            .collect()
+    }
+
+    /// Refers the given public bindings of `from`, or all of them.
+    pub fn refer(&self, from: &Namespace, names: Option<Vec<Sym>>) -> Fallible<()> {
+        let publics = from.publics();
+        let names = names.unwrap_or_else(|| publics.iter().map(|(k, _)| *k).collect());
+        for name in names {
+            match publics.iter().find(|(k, _)| *k == name) {
+                Some((_, binding)) => env_refer(&self.env, name, binding.clone()),
+                None => match env_binding(&from.env, name) {
+                    Some(_) => bail!("{}/{} is not public", from.name, name),
+                    None => bail!("{}/{} does not exist", from.name, name),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_alias(&self, alias: Sym, ns: Rc<Namespace>) {
+        self.aliases.borrow_mut().insert(alias, ns);
+    }
+
+    /// The namespace called `name` here: an alias, or a namespace's name.
+    fn lookup_ns(&self, name: &str) -> Fallible<Rc<Namespace>> {
+        let name = Sym::new(name);
+        if let Some(ns) = self.aliases.borrow().get(&name) {
+            return Ok(ns.clone());
+        }
+        find_ns(name).ok_or_else(|| format_err!("no namespace named {}", name))
+    }
+
+    /// The namespace and binding which the qualified `sym` names from this
+    /// namespace. Private bindings are only reachable from their own one.
+    pub fn resolve_qualified(&self, sym: &str) -> Fallible<Option<(Rc<Namespace>, Sym)>> {
+        let (ns_name, name) = match split_qualified(sym) {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+        let ns = self.lookup_ns(ns_name)?;
+        let name = Sym::new(name);
+        let binding = match env_interns(&ns.env).into_iter().find(|(k, _)| *k == name) {
+            Some((_, binding)) => binding,
+            None => bail!("{}/{} does not exist", ns.name, name),
+        };
+        ensure!(
+            ns.name == self.name || !is_private(&binding),
+            "{}/{} is not public",
+            ns.name,
+            name
+        );
+        Ok(Some((ns, name)))
+    }
+}
+
+/// The binding of a qualified `sym` seen from `env`.
+pub fn qualified_binding(env: &Env, sym: &str) -> Fallible<Option<Rc<Binding>>> {
+    let ns = match ns_of_env(env) {
+        Some(ns) => ns,
+        None => return Ok(None),
+    };
+    Ok(ns
+        .resolve_qualified(sym)?
+        .and_then(|(ns, name)| env_binding(&ns.env, name)))
+}
+
+/// The env binding `sym` seen from `env` and the name it has there.
+pub fn resolve_var(env: &Env, sym: Sym) -> Fallible<Option<(Env, Sym)>> {
+    if let Some(found) = env_find(env.clone(), sym) {
+        return Ok(Some((found, sym)));
+    }
+    let ns = match ns_of_env(env) {
+        Some(ns) => ns,
+        None => return Ok(None),
+    };
+    Ok(ns
+        .resolve_qualified(&sym)?
+        .map(|(ns, name)| (ns.env.clone(), name)))
+}
+
+/// The binding of the var `sym` names from `env`, which `binding` may
+/// rebind only if it was defined `^:dynamic`.
+pub fn dynamic_binding(env: &Env, sym: Sym) -> Fallible<Rc<Binding>> {
+    let binding = match resolve_var(env, sym)? {
+        Some((found, name)) => env_binding(&found, name),
+        None => bail!("unable to resolve {}", sym),
+    };
+    match binding {
+        Some(ref binding) if has_flag(binding, ":dynamic") => Ok(binding.clone()),
+        _ => bail!("can't dynamically bind non-dynamic var {}", sym),
+    }
+}
+
+impl fmt::Debug for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Namespace({})", self.name)
+    }
+}
+
+impl PartialEq for Namespace {
+    fn eq(&self, other: &Namespace) -> bool {
+        ::std::ptr::eq(self, other)
+    }
+}
+
+/// Reads a namespace argument: a namespace, or the symbol naming one.
+pub fn to_ns(mal: &MalType) -> Fallible<Rc<Namespace>> {
+    match **mal {
+        InnerMalType::Namespace(ref ns) => Ok(ns.clone()),
+        InnerMalType::Symbol(name) => {
+            find_ns(name).ok_or_else(|| format_err!("no namespace named {}", name))
+        }
+        _ => bail_type!("{:?} is not a namespace", mal),
+    }
+}
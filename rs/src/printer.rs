@@ -1,17 +1,61 @@
+use failure::Fallible;
+use lazy::seq_iter;
+use std::cell::Cell;
 use types::MalType;
 use types::InnerMalType;
 
+thread_local! {
+    static PRINT_LENGTH: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Limits how many items of a sequence get printed before `...`. Infinite
+/// lazy sequences can only be printed with a limit set.
+pub fn set_print_length(length: Option<usize>) {
+    PRINT_LENGTH.with(|l| l.set(length));
+}
+
+pub fn print_length() -> Option<usize> {
+    PRINT_LENGTH.with(|l| l.get())
+}
+
+/// Like `try_pr_str`, but renders a failure to realise a lazy sequence
+/// inline instead of returning it.
 pub fn pr_str(mal: &MalType, print_readably: bool) -> String {
+    try_pr_str(mal, print_readably).unwrap_or_else(|e| format!("#<error {}>", e))
+}
+
+fn pr_seq(mal: &MalType, open: &str, close: &str, print_readably: bool) -> Fallible<String> {
     let mut s = String::new();
+    s.push_str(open);
+    let mut items = seq_iter(mal)?;
+    let mut printed = 0;
+    while let Some(t) = items.next() {
+        if print_length() == Some(printed) {
+            s.push_str("...");
+            break;
+        }
+        s.push_str(&try_pr_str(&t?, print_readably)?);
+        s.push_str(" ");
+        printed += 1;
+    }
+    let mut s = s.trim().to_string();
+    s.push_str(close);
+    Ok(s)
+}
 
-    match (**mal).clone() {
-        InnerMalType::Symbol(sym) => s.push_str(sym.as_str()),
+pub fn try_pr_str(mal: &MalType, print_readably: bool) -> Fallible<String> {
+    let mut s = String::new();
+
+    match **mal {
+        InnerMalType::Symbol(sym)
+        | InnerMalType::Local(sym, ..)
+        | InnerMalType::Global(sym, _) => s.push_str(sym.as_str()),
         InnerMalType::Nil => s.push_str("nil"),
         InnerMalType::Bool(b) => s.push_str(&format!("{}", b)),
         InnerMalType::Keyword(k) => s.push_str(k.as_str()),
-        InnerMalType::String(k) => {
+        InnerMalType::String(ref k) => {
             if !print_readably {
-                s.push_str(&k)
+                s.push_str(k)
             } else {
                 s.push('"');
                 let mut chars = k.chars();
@@ -38,44 +82,70 @@ pub fn pr_str(mal: &MalType, print_readably: bool) -> String {
             }
         }
         InnerMalType::Num(num) => s.push_str(&format!("{}", num)),
-        InnerMalType::List(list, _) => {
-            s.push_str("(");
-            for t in list {
-                s.push_str(&pr_str(&t, print_readably));
-                s.push_str(" ");
+        InnerMalType::Regex(ref re) => {
+            if print_readably {
+                s.push_str("#\"");
+                s.push_str(&re.regex.as_str().replace("\"", "\\\""));
+                s.push('"');
+            } else {
+                s.push_str(re.regex.as_str());
             }
-            s = s.trim().to_string();
-            s.push_str(")");
         }
-        InnerMalType::Vec(list, _) => {
-            s.push_str("[");
-            for t in list {
-                s.push_str(&pr_str(&t, print_readably));
-                s.push_str(" ");
-            }
-            s = s.trim().to_string();
-            s.push_str("]");
+        InnerMalType::List(..) | InnerMalType::LazySeq(..) => {
+            s.push_str(&pr_seq(mal, "(", ")", print_readably)?);
+        }
+        InnerMalType::Vec(..) => {
+            s.push_str(&pr_seq(mal, "[", "]", print_readably)?);
         }
-        InnerMalType::Hashmap(hashmap, _) => {
+        InnerMalType::Hashmap(ref hashmap, _) => {
             s.push_str("{");
-            for (k, v) in hashmap.into_iter() {
-                s.push_str(&pr_str(&k.to_mal_type(), print_readably));
+            for (k, v) in hashmap.iter() {
+                s.push_str(&try_pr_str(&k.to_mal_type(), print_readably)?);
                 s.push_str(" ");
-                s.push_str(&pr_str(&v, print_readably));
+                s.push_str(&try_pr_str(v, print_readably)?);
                 s.push_str(" ");
             }
             s = s.trim().to_string();
             s.push_str("}");
         }
-        InnerMalType::Atom(atom) => {
+        InnerMalType::Record(ref rtype, ref fields, _) => {
+            let entries = rtype
+                .entries(fields)
+                .into_iter()
+                .map(|(k, v)| {
+                    Ok(format!(
+                        "{} {}",
+                        try_pr_str(&k.to_mal_type(), print_readably)?,
+                        try_pr_str(v, print_readably)?
+                    ))
+                }).collect::<Fallible<Vec<String>>>()?;
+            s.push_str(&format!("#{}{{{}}}", rtype.name, entries.join(" ")));
+        }
+        InnerMalType::Type(ref kind) => {
+            s.push_str(&kind.name());
+        }
+        InnerMalType::Protocol(ref protocol) => {
+            s.push_str(&format!("#<protocol {}>", protocol.name));
+        }
+        InnerMalType::Namespace(ref ns) => {
+            s.push_str(&format!("#namespace[{}]", ns.name));
+        }
+        InnerMalType::Env(_) => {
+            s.push_str("#<env>");
+        }
+        InnerMalType::Atom(ref atom) => {
             s.push_str("(atom ");
-            s.push_str(&pr_str(&atom.borrow(), print_readably));
+            s.push_str(&try_pr_str(&atom.borrow(), print_readably)?);
             s.push_str(")")
         }
         InnerMalType::Closure(..) => {
             s.push_str("#<function>");
         }
+        InnerMalType::Var(ref var) => {
+            s.push_str("#'");
+            s.push_str(&var.name);
+        }
     }
 
-    s
+    Ok(s)
 }
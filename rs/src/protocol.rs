@@ -0,0 +1,194 @@
+use failure::Fallible;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::fmt;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{Kind, MalType, InnerMalType};
+
+/// A set of methods created by `defprotocol` and implemented per type with
+/// `extend-type`/`extend-protocol`.
+pub struct Protocol {
+    pub name: String,
+    pub methods: Vec<String>,
+    impls: RefCell<HashMap<Kind, HashMap<String, MalType>>>,
+    // Which extended kind serves each kind dispatched on so far.
+    cache: RefCell<HashMap<Kind, Option<Kind>>>,
+}
+
+impl Protocol {
+    pub fn new(name: String, methods: Vec<String>) -> Self {
+        Protocol {
+            name,
+            methods,
+            impls: RefCell::new(HashMap::new()),
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn extend(&self, kind: Kind, fns: HashMap<String, MalType>) -> Fallible<()> {
+        self.check(&fns)?;
+        self.impls
+            .borrow_mut()
+            .entry(kind)
+            .or_default()
+            .extend(fns);
+        self.cache.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Fails unless `fns` are fns implementing methods of this protocol.
+    pub fn check(&self, fns: &HashMap<String, MalType>) -> Fallible<()> {
+        for (method, f) in fns {
+            ensure_type!(
+                self.methods.contains(method),
+                "{} is not a method of protocol {}",
+                method,
+                self.name
+            );
+            ensure_type!(f.is_closure(), "implementation of {} should be func", method);
+        }
+        Ok(())
+    }
+
+    fn implementor(&self, kind: &Kind) -> Option<Kind> {
+        if let Some(found) = self.cache.borrow().get(kind) {
+            return found.clone();
+        }
+        let impls = self.impls.borrow();
+        let found = kind.supers().into_iter().find(|k| impls.contains_key(k));
+        self.cache.borrow_mut().insert(kind.clone(), found.clone());
+        found
+    }
+
+    pub fn is_satisfied_by(&self, kind: &Kind) -> bool {
+        self.implementor(kind).is_some()
+    }
+
+    /// The implementation of `method` for a first argument of type `kind`.
+    pub fn find_method(&self, method: &str, kind: &Kind) -> Fallible<MalType> {
+        let f = self
+            .implementor(kind)
+            .and_then(|k| self.impls.borrow()[&k].get(method).cloned());
+        match f {
+            Some(f) => Ok(f),
+            None => bail!(
+                "no implementation of method {} of protocol {} found for type {}",
+                method,
+                self.name,
+                kind.name()
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Protocol({})", self.name)
+    }
+}
+
+impl PartialEq for Protocol {
+    fn eq(&self, other: &Protocol) -> bool {
+        ::std::ptr::eq(self, other)
+    }
+}
+
+/// A method signature of `defprotocol`: `(name [params]+ docstring?)`.
+pub struct MethodSig {
+    pub name: String,
+    pub arglists: MalType,
+    pub doc: Option<MalType>,
+}
+
+pub fn parse_sig(sig: &MalType) -> Fallible<MethodSig> {
+    ensure!(
+        sig.is_list() && sig.did_collection_have_leading_symbol(),
+        "protocol method should be (name [params]+ docstring?)"
+    );
+    let mut items = sig.to_items();
+    let name = items.pop_front().unwrap().to_symbol().to_string();
+    let doc = if items.back().map(|d| d.is_string()) == Some(true) {
+        items.pop_back()
+    } else {
+        None
+    };
+    ensure_arity!(
+        !items.is_empty() && items.iter().all(|params| params.is_vec()),
+        "protocol method {} should have vectors of params",
+        name
+    );
+    ensure!(
+        items.iter().all(|params| !params.is_empty_vec()),
+        "protocol method {} should take at least one param",
+        name
+    );
+    Ok(MethodSig {
+        name,
+        arglists: new_mal!(List(items, new_mal!(Nil))),
+        doc,
+    })
+}
+
+fn symbol(name: &str) -> MalType {
+    new_mal!(Symbol(Sym::new(name)))
+}
+
+fn list(items: LinkedList<MalType>) -> MalType {
+    new_mal!(List(items, new_mal!(Nil)))
+}
+
+/// `(fn* (& args) (apply (protocol-method protocol method args) args))`, the
+/// function bound to each method name.
+pub fn dispatcher(protocol: &MalType, method: &str) -> MalType {
+    let find = list(linked_list![
+        symbol("protocol-method"),
+        protocol.clone(),
+        new_mal!(String(method.to_string())),
+        symbol("args")
+    ]);
+    list(linked_list![
+        symbol("fn*"),
+        list(linked_list![symbol("&"), symbol("args")]),
+        list(linked_list![symbol("apply"), find, symbol("args")])
+    ])
+}
+
+/// A protocol (resp. type) form of `extend-type`/`extend-protocol` and the
+/// names of its methods with their `fn*` forms.
+pub type ImplGroup = (MalType, Vec<(String, MalType)>);
+
+/// The body of `extend-type`/`extend-protocol`: each protocol (resp. type)
+/// form followed by its method implementations `(name [params] body*)`,
+/// which are returned as `fn*` forms.
+pub fn parse_impls(
+    form: &str,
+    body: LinkedList<MalType>,
+) -> Fallible<Vec<ImplGroup>> {
+    let mut groups: Vec<ImplGroup> = Vec::new();
+    for item in body {
+        if !item.is_list() {
+            groups.push((item, Vec::new()));
+            continue;
+        }
+        let fns = match groups.last_mut() {
+            Some(group) => &mut group.1,
+            None => bail!("{} expects a type or protocol before {:?}", form, item),
+        };
+        let mut items = item.to_items();
+        ensure!(
+            items.len() >= 2 && items.front().unwrap().is_symbol() && items.iter().nth(1).unwrap().is_vec(),
+            "{}'s method should be (name [params] body*)",
+            form
+        );
+        let name = items.pop_front().unwrap().to_symbol().to_string();
+        let params = items.pop_front().unwrap();
+        items.push_front(symbol("do"));
+        fns.push((
+            name,
+            list(linked_list![symbol("fn*"), params, list(items)]),
+        ));
+    }
+    Ok(groups)
+}
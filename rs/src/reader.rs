@@ -1,24 +1,58 @@
 use error::CommentFoundError;
 use failure::Fallible;
+use record::{find_record_type, RecordType};
 use regex::Regex;
 use std::collections::HashMap;
 use std::collections::LinkedList;
-use types::{MalType, InnerMalType};
+use types::{MalType, InnerMalType, Pattern};
 use std::rc::Rc;
+use symbol::Sym;
+use trace;
 
 struct Reader {
     tokens: Vec<String>,
+    // where each token starts in the text
+    offsets: Vec<usize>,
     current_pos: usize,
+    source: Option<Source>,
+}
+
+/// The file the text read comes from, after `skip` bytes of its own.
+struct Source {
+    file: Rc<str>,
+    text: String,
+    line_starts: Vec<usize>,
+    skip: usize,
 }
 
 impl Reader {
-    fn new(tokens: Vec<String>) -> Self {
+    fn new(s: &str) -> Self {
+        let (tokens, offsets) = tokenizer(s);
         Reader {
             tokens,
+            offsets,
             current_pos: 0,
+            source: None,
         }
     }
 
+    /// Records where the list `form`, starting at the token at `pos`, was
+    /// read from.
+    fn record(&self, form: &MalType, pos: usize) {
+        let source = match self.source {
+            Some(ref source) => source,
+            None => return,
+        };
+        let offset = match self.offsets.get(pos) {
+            Some(&offset) if offset >= source.skip => offset - source.skip,
+            _ => return,
+        };
+        let line = source.line_starts.partition_point(|&start| start <= offset);
+        let start = source.line_starts[line - 1];
+        let column = source.text[start..offset].chars().count() + 1;
+        trace::set_position(form, source.file.clone(), line, column);
+    }
+
     fn next(&mut self) -> Option<&String> {
         let current = self.tokens.get(self.current_pos);
         self.current_pos += 1;
@@ -28,27 +62,99 @@ impl Reader {
     fn peek(&self) -> Option<&String> {
         self.tokens.get(self.current_pos)
     }
+
+    fn peek_next(&self) -> Option<&str> {
+        self.tokens.get(self.current_pos + 1).map(|t| t.as_str())
+    }
 }
 
 pub fn read_str(s: &str) -> Fallible<MalType> {
-    let tokens = tokenizer(s);
-    let mut reader = Reader::new(tokens);
+    let mut reader = Reader::new(s);
+    read_form(&mut reader)
+}
+
+/// The forms of a text, read one at a time. What is left that is not a
+/// whole form, like a `)` closing nothing, is an error.
+pub struct Forms {
+    reader: Reader,
+}
+
+impl Forms {
+    pub fn new(s: &str) -> Forms {
+        Forms {
+            reader: Reader::new(s),
+        }
+    }
+}
+
+impl Iterator for Forms {
+    type Item = Fallible<MalType>;
+
+    fn next(&mut self) -> Option<Fallible<MalType>> {
+        loop {
+            let result = match self.reader.peek()?.chars().next() {
+                // the end of the text
+                None => {
+                    self.reader.next();
+                    continue;
+                }
+                Some(c) if c == ')' || c == ']' || c == '}' => {
+                    Err(format_err!("unexpected '{}'", c))
+                }
+                Some(_) => read_form(&mut self.reader),
+            };
+            match result {
+                Ok(form) => {
+                    self.reader.next();
+                    return Some(Ok(form));
+                }
+                Err(e) => {
+                    if e.downcast_ref::<CommentFoundError>().is_some() {
+                        self.reader.next();
+                        continue;
+                    }
+                    self.reader.current_pos = self.reader.tokens.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the forms of the file `file`, whose text is `s`, as a `do`. The
+/// lists read keep where they start, for backtraces.
+pub fn read_file(s: &str, file: &str) -> Fallible<MalType> {
+    let prefix = "(do ";
+    let mut reader = Reader::new(&format!("{}{}\n)", prefix, s));
+    let line_starts = Some(0)
+        .into_iter()
+        .chain(s.match_indices('\n').map(|(at, _)| at + 1))
+        .collect();
+    reader.source = Some(Source {
+        file: file.into(),
+        text: s.to_string(),
+        line_starts,
+        skip: prefix.len(),
+    });
     read_form(&mut reader)
 }
 
-fn tokenizer(s: &str) -> Vec<String> {
+/// The tokens of `s` and where each starts.
+fn tokenizer(s: &str) -> (Vec<String>, Vec<usize>) {
     lazy_static! {
         static ref RE: Regex =
-            Regex::new(r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"|;.*|[^\s\[\]{}('"`,;)]*)"#)
+            Regex::new(r#"[\s,]*(~@|#'|[\[\]{}()'`~^@]|#?"(?:\\.|[^\\"])*"|;.*|[^\s\[\]{}('"`,;)]*)"#)
                 .expect("make regexp");
     }
 
     let mut caps = Vec::new();
+    let mut offsets = Vec::new();
     for cap in RE.captures_iter(s) {
         caps.push(cap[1].to_string());
+        offsets.push(cap.get(1).unwrap().start());
     }
     //        println!("{:?}", caps);
-    caps
+    (caps, offsets)
 }
 
 fn read_form(reader: &mut Reader) -> Fallible<MalType> {
@@ -73,6 +179,12 @@ fn read_form(reader: &mut Reader) -> Fallible<MalType> {
             Some('"') => return read_string(reader),
             Some('^') => return read_with_meta(reader),
             Some('@') => return read_deref(reader),
+            Some('#') => match chars.next() {
+                Some('"') => return read_regex(reader),
+                Some('\'') => return read_var(reader),
+                Some(_) if reader.peek_next() == Some("{") => return read_record(reader),
+                _ => return read_symbol(reader),
+            },
             Some(';') => return Err(CommentFoundError.into()),
             Some(_) => return read_symbol(reader),
         }
@@ -82,6 +194,7 @@ fn read_form(reader: &mut Reader) -> Fallible<MalType> {
 }
 
 fn read_list(reader: &mut Reader) -> Fallible<MalType> {
+    let start = reader.current_pos;
     let mut ret = LinkedList::new();
     loop {
         reader.next();
@@ -91,7 +204,9 @@ fn read_list(reader: &mut Reader) -> Fallible<MalType> {
             Some(t) => t,
         };
         if c == ")" {
-            return Ok(new_mal!(List(ret, new_mal!(Nil))));
+            let list = new_mal!(List(ret, new_mal!(Nil)));
+            reader.record(&list, start);
+            return Ok(list);
         }
         let type_ = match read_form(reader) {
             Ok(t) => t,
@@ -159,7 +274,7 @@ fn read_hashmap(reader: &mut Reader) -> Fallible<MalType> {
 fn read_quote(reader: &mut Reader) -> Fallible<MalType> {
     reader.next();
     return Ok(new_mal!(List(
-        linked_list![new_mal!(Symbol("quote".to_string())), read_form(reader)?],
+        linked_list![new_mal!(Symbol(Sym::new("quote"))), read_form(reader)?],
         new_mal!(Nil)
     )));
 }
@@ -168,7 +283,7 @@ fn read_quasiquote(reader: &mut Reader) -> Fallible<MalType> {
     reader.next();
     return Ok(new_mal!(List(
         linked_list![
-            new_mal!(Symbol("quasiquote".to_string())),
+            new_mal!(Symbol(Sym::new("quasiquote"))),
             read_form(reader)?,
         ],
         new_mal!(Nil)
@@ -178,7 +293,7 @@ fn read_quasiquote(reader: &mut Reader) -> Fallible<MalType> {
 fn read_unquote(reader: &mut Reader) -> Fallible<MalType> {
     reader.next();
     return Ok(new_mal!(List(
-        linked_list![new_mal!(Symbol("unquote".to_string())), read_form(reader)?],
+        linked_list![new_mal!(Symbol(Sym::new("unquote"))), read_form(reader)?],
         new_mal!(Nil)
     )));
 }
@@ -187,7 +302,7 @@ fn read_splice_unquote(reader: &mut Reader) -> Fallible<MalType> {
     reader.next();
     return Ok(new_mal!(List(
         linked_list![
-            new_mal!(Symbol("splice-unquote".to_string())),
+            new_mal!(Symbol(Sym::new("splice-unquote"))),
             read_form(reader)?,
         ],
         new_mal!(Nil)
@@ -206,7 +321,7 @@ fn read_symbol(reader: &mut Reader) -> Fallible<MalType> {
                 "nil" => new_mal!(Nil),
                 "true" => new_mal!(Bool(true)),
                 "false" => new_mal!(Bool(false)),
-                _ => new_mal!(Symbol(token.to_owned())),
+                _ => new_mal!(Symbol(Sym::new(token))),
             })
         }
     }
@@ -219,11 +334,22 @@ fn read_string(reader: &mut Reader) -> Fallible<MalType> {
     }
 }
 
+fn read_regex(reader: &mut Reader) -> Fallible<MalType> {
+    match reader.peek() {
+        None => unreachable!(),
+        Some(token) => {
+            // only \" is unescaped, everything else is left for the regex
+            let source = token[2..token.len() - 1].replace("\\\"", "\"");
+            Ok(new_mal!(Regex(Pattern::new(&source)?)))
+        }
+    }
+}
+
 fn read_keyword(reader: &mut Reader) -> Fallible<MalType> {
     match reader.peek() {
         None => unreachable!(),
         Some(token) => {
-            return Ok(new_mal!(Keyword(token.to_owned())));
+            return Ok(new_mal!(Keyword(Sym::new(token))));
         }
     }
 }
@@ -234,23 +360,33 @@ fn read_with_meta(reader: &mut Reader) -> Fallible<MalType> {
     reader.next();
     let func = read_form(reader)?;
     return Ok(new_mal!(List(
-        linked_list![new_mal!(Symbol("with-meta".to_string())), func, meta],
+        linked_list![new_mal!(Symbol(Sym::new("with-meta"))), func, meta],
         new_mal!(Nil)
     )));
 }
 
+fn read_var(reader: &mut Reader) -> Fallible<MalType> {
+    reader.next();
+    return Ok(new_mal!(List(
+        linked_list![new_mal!(Symbol(Sym::new("var"))), read_form(reader)?],
+        new_mal!(Nil)
+    )));
+}
+
+fn read_record(reader: &mut Reader) -> Fallible<MalType> {
+    let name = reader.next().expect("record tag")[1..].to_string();
+    let rtype = match find_record_type(&name) {
+        Some(rtype) => rtype,
+        None => bail!("no record type named {}", name),
+    };
+    let fields = read_hashmap(reader)?.to_hashmap();
+    Ok(RecordType::instantiate(&rtype, fields))
+}
+
 fn read_deref(reader: &mut Reader) -> Fallible<MalType> {
     reader.next();
-    match reader.peek() {
-        None => unreachable!(),
-        Some(token) => {
-            return Ok(new_mal!(List(
-                linked_list![
-                    new_mal!(Symbol("deref".to_string())),
-                    new_mal!(Symbol(token.to_string())),
-                ],
-                new_mal!(Nil)
-            )));
-        }
-    }
+    return Ok(new_mal!(List(
+        linked_list![new_mal!(Symbol(Sym::new("deref"))), read_form(reader)?],
+        new_mal!(Nil)
+    )));
 }
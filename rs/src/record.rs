@@ -0,0 +1,93 @@
+use failure::Fallible;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use types::{HashKey, InnerMalType, MalType};
+
+/// A type created by `defrecord`. Every type is distinct, even when it is
+/// redefined with the same name and fields.
+#[derive(Debug)]
+pub struct RecordType {
+    id: usize,
+    pub name: String,
+    pub fields: Vec<HashKey>,
+}
+
+impl PartialEq for RecordType {
+    fn eq(&self, other: &RecordType) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for RecordType {}
+
+impl Hash for RecordType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Latest type defined under each name, used to read `#Name{...}`.
+    static RECORD_TYPES: RefCell<HashMap<String, Rc<RecordType>>> = RefCell::new(HashMap::new());
+}
+
+pub fn define_record_type(name: String, fields: Vec<HashKey>) -> Rc<RecordType> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let rtype = Rc::new(RecordType { id, name, fields });
+    RECORD_TYPES.with(|types| {
+        types
+            .borrow_mut()
+            .insert(rtype.name.clone(), rtype.clone())
+    });
+    rtype
+}
+
+pub fn find_record_type(name: &str) -> Option<Rc<RecordType>> {
+    RECORD_TYPES.with(|types| types.borrow().get(name).cloned())
+}
+
+impl RecordType {
+    pub fn has_field(&self, key: &HashKey) -> bool {
+        self.fields.contains(key)
+    }
+
+    /// Builds an instance from `map`; missing fields are `nil`, extra keys
+    /// are kept.
+    pub fn instantiate(rtype: &Rc<RecordType>, mut map: HashMap<HashKey, MalType>) -> MalType {
+        for field in &rtype.fields {
+            map.entry(field.clone()).or_insert_with(|| new_mal!(Nil));
+        }
+        new_mal!(Record(rtype.clone(), map, new_mal!(Nil)))
+    }
+
+    /// Builds an instance from the values of the fields, in declared order.
+    pub fn from_values(rtype: &Rc<RecordType>, values: Vec<MalType>) -> Fallible<MalType> {
+        ensure_arity!(
+            values.len() == rtype.fields.len(),
+            "->{} should have {} params",
+            rtype.name,
+            rtype.fields.len()
+        );
+        let map = rtype.fields.iter().cloned().zip(values).collect();
+        Ok(new_mal!(Record(rtype.clone(), map, new_mal!(Nil))))
+    }
+
+    /// Entries in printing order: declared fields first, then the extra keys.
+    pub fn entries<'a>(
+        &self,
+        map: &'a HashMap<HashKey, MalType>,
+    ) -> Vec<(&'a HashKey, &'a MalType)> {
+        let mut entries: Vec<_> = self
+            .fields
+            .iter()
+            .filter_map(|field| map.get_key_value(field))
+            .collect();
+        entries.extend(map.iter().filter(|(k, _)| !self.has_field(k)));
+        entries
+    }
+}
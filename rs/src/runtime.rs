@@ -0,0 +1,106 @@
+//! The state of one interpreter: its namespaces, the modules it loaded,
+//! where mal prints to and reads from, and how deeply evaluation may nest.
+//!
+//! An `Interpreter` owns its runtime and enters it for each call into mal,
+//! which makes it the runtime the evaluator and the built-ins use until
+//! the call returns. Outside of any call, like when a lazy seq it returned
+//! is realised, the runtime entered last is used while it lives.
+
+use env::env_clear;
+use namespace::Namespace;
+use stack::{self, DEFAULT_MAX_DEPTH};
+use std::cell::{Cell, RefCell};
+use std::io::{self, BufRead, BufReader, Write};
+use std::ptr;
+use std::rc::{Rc, Weak};
+use symbol::Sym;
+
+pub struct Runtime {
+    pub(crate) namespaces: RefCell<Vec<Rc<Namespace>>>,
+    // modules loaded by require, and namespaces defined other than by
+    // loading their module
+    pub(crate) loaded: RefCell<Vec<Sym>>,
+    // modules being loaded, outermost first
+    pub(crate) loading: RefCell<Vec<Sym>>,
+    pub(crate) stdout: RefCell<Box<dyn Write>>,
+    pub(crate) stdin: RefCell<Box<dyn BufRead>>,
+    pub(crate) max_depth: Cell<usize>,
+}
+
+impl Runtime {
+    /// A runtime of no namespaces, on the process's stdout and stdin.
+    pub fn new() -> Runtime {
+        Runtime {
+            namespaces: RefCell::new(Vec::new()),
+            loaded: RefCell::new(Vec::new()),
+            loading: RefCell::new(Vec::new()),
+            stdout: RefCell::new(Box::new(io::stdout())),
+            stdin: RefCell::new(Box::new(BufReader::new(io::stdin()))),
+            max_depth: Cell::new(DEFAULT_MAX_DEPTH),
+        }
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Runtime {
+        Runtime::new()
+    }
+}
+
+/// Clears the envs of its namespaces, which the closures defined in them
+/// refer back to, so that what was defined is freed with the runtime.
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        for ns in self.namespaces.get_mut().drain(..) {
+            env_clear(&ns.env);
+        }
+    }
+}
+
+thread_local! {
+    // the runtimes of the calls into mal being run, innermost last
+    static ENTERED: RefCell<Vec<Rc<Runtime>>> = const { RefCell::new(Vec::new()) };
+    static LAST: RefCell<Weak<Runtime>> = const { RefCell::new(Weak::new()) };
+    // the runtime used before any other is entered
+    static DEFAULT: Rc<Runtime> = Rc::new(Runtime::new());
+}
+
+/// Leaves the runtime entered last when dropped, even by a panic, and
+/// restores the max depth from before it.
+struct Leave(usize);
+
+impl Drop for Leave {
+    fn drop(&mut self) {
+        stack::set_limit(self.0);
+        ENTERED.with(|entered| entered.borrow_mut().pop());
+    }
+}
+
+/// Runs `f` in `runtime`.
+pub fn enter<R, F: FnOnce() -> R>(runtime: &Rc<Runtime>, f: F) -> R {
+    ENTERED.with(|entered| entered.borrow_mut().push(runtime.clone()));
+    LAST.with(|last| *last.borrow_mut() = Rc::downgrade(runtime));
+    let _leave = Leave(stack::set_limit(runtime.max_depth.get()));
+    f()
+}
+
+/// The runtime of the innermost call into mal being run, else the one
+/// entered last.
+pub fn current() -> Rc<Runtime> {
+    if let Some(runtime) = ENTERED.with(|entered| entered.borrow().last().cloned()) {
+        return runtime;
+    }
+    LAST.with(|last| last.borrow().upgrade())
+        .unwrap_or_else(|| DEFAULT.with(|runtime| runtime.clone()))
+}
+
+/// Whether `runtime` is that of the innermost call into mal being run.
+pub fn is_entered(runtime: &Runtime) -> bool {
+    ENTERED.with(|entered| {
+        entered
+            .borrow()
+            .last()
+            .map(|last| ptr::eq(&**last, runtime))
+            == Some(true)
+    })
+}
@@ -0,0 +1,420 @@
+//! Serializes mal values with serde, and any serde type into a mal value.
+//!
+//! In serde's data model nil is a unit, a number is an `i64` when it is a
+//! whole number a double represents exactly and an `f64` otherwise, lists,
+//! vectors and lazy seqs are sequences, and maps and records are maps.
+//! Strings, keywords and symbols, as values and as map keys, are all
+//! strings, told apart by their first character:
+//!
+//! - a keyword is its name with the colon, `:a` is `":a"`;
+//! - a symbol is its name after a quote, `a` is `"'a"`;
+//! - a string is itself, with a backslash before it when it starts with
+//!   `:`, `'` or `\`, so `":a"` is `"\\:a"`.
+//!
+//! A record loses its type, lists read back as vectors, and other values,
+//! like functions and atoms, can not be serialized.
+//!
+//! Serializing a Rust value with `to_mal` makes a struct a map with a
+//! keyword for each field, a unit variant of an enum a keyword, and other
+//! variants a vector tagged with that keyword, `[:Variant field...]`. Map
+//! keys which are numbers become strings.
+
+use de::decode_text;
+use error::SerdeError;
+use failure::Fallible;
+use lazy::seq_to_list;
+use printer::pr_str;
+use serde::ser::{self, Error, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+use symbol::Sym;
+use types::{HashKey, InnerMalType, Kind, MalType};
+
+/// The newtype strings, keywords and symbols are serialized as, so that
+/// `to_mal` can read them back without the encoding.
+pub const TEXT: &str = "$mal::Text";
+
+/// Converts `value` to a mal value.
+pub fn to_mal<T: Serialize + ?Sized>(value: &T) -> Fallible<MalType> {
+    Ok(value.serialize(Serializer)?)
+}
+
+/// The string which is `text`, a string, keyword or symbol, in serde's
+/// data model.
+pub fn encode_text(text: &InnerMalType) -> String {
+    match *text {
+        InnerMalType::Keyword(k) => k.to_string(),
+        InnerMalType::Symbol(s) => format!("'{}", s),
+        InnerMalType::String(ref s) if s.starts_with(&[':', '\'', '\\'][..]) => {
+            format!("\\{}", s)
+        }
+        InnerMalType::String(ref s) => s.clone(),
+        _ => unreachable!(),
+    }
+}
+
+impl Serialize for HashKey {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_mal_type().serialize(serializer)
+    }
+}
+
+impl Serialize for InnerMalType {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            InnerMalType::Nil => serializer.serialize_unit(),
+            InnerMalType::Bool(b) => serializer.serialize_bool(b),
+            InnerMalType::Num(n) if n.fract() == 0.0 && n.abs() <= (1u64 << 53) as f64 => {
+                serializer.serialize_i64(n as i64)
+            }
+            InnerMalType::Num(n) => serializer.serialize_f64(n),
+            InnerMalType::String(_) | InnerMalType::Keyword(_) | InnerMalType::Symbol(_) => {
+                serializer.serialize_newtype_struct(TEXT, &encode_text(self))
+            }
+            InnerMalType::List(ref l, _) | InnerMalType::Vec(ref l, _) => serializer.collect_seq(l),
+            InnerMalType::LazySeq(ref seq, _) => {
+                let items = match seq.step().map_err(S::Error::custom)? {
+                    Some((first, rest)) => {
+                        let mut items = seq_to_list(&rest).map_err(S::Error::custom)?;
+                        items.push_front(first);
+                        items
+                    }
+                    None => Default::default(),
+                };
+                serializer.collect_seq(&items)
+            }
+            InnerMalType::Hashmap(ref map, _) | InnerMalType::Record(_, ref map, _) => {
+                serializer.collect_map(map)
+            }
+            _ => Err(S::Error::custom(format!(
+                "can't serialize {}",
+                Kind::of(self).name()
+            ))),
+        }
+    }
+}
+
+fn vector(items: Vec<MalType>) -> MalType {
+    new_mal!(Vec(items.into_iter().collect(), new_mal!(Nil)))
+}
+
+fn keyword(name: &str) -> MalType {
+    new_mal!(Keyword(Sym::new(&format!(":{}", name))))
+}
+
+fn hash_key(key: MalType) -> Result<HashKey, SerdeError> {
+    match *key {
+        InnerMalType::String(ref s) => Ok(HashKey::String(s.clone())),
+        InnerMalType::Keyword(k) => Ok(HashKey::Keyword(k)),
+        InnerMalType::Symbol(s) => Ok(HashKey::Symbol(s)),
+        InnerMalType::Num(_) => Ok(HashKey::String(pr_str(&key, false))),
+        _ => Err(SerdeError::custom(format!(
+            "{} can't be a map key",
+            pr_str(&key, true)
+        ))),
+    }
+}
+
+/// Serializes into mal values.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = MalType;
+    type Error = SerdeError;
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVec;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<MalType, SerdeError> {
+        Ok(new_mal!(Bool(v)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<MalType, SerdeError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<MalType, SerdeError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<MalType, SerdeError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<MalType, SerdeError> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<MalType, SerdeError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<MalType, SerdeError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<MalType, SerdeError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<MalType, SerdeError> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<MalType, SerdeError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<MalType, SerdeError> {
+        Ok(new_mal!(Num(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<MalType, SerdeError> {
+        Ok(new_mal!(String(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<MalType, SerdeError> {
+        Ok(new_mal!(String(v.to_string())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<MalType, SerdeError> {
+        Ok(vector(v.iter().map(|b| new_mal!(Num(f64::from(*b)))).collect()))
+    }
+
+    fn serialize_none(self) -> Result<MalType, SerdeError> {
+        Ok(new_mal!(Nil))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<MalType, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<MalType, SerdeError> {
+        Ok(new_mal!(Nil))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<MalType, SerdeError> {
+        Ok(new_mal!(Nil))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<MalType, SerdeError> {
+        Ok(keyword(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<MalType, SerdeError> {
+        let value = value.serialize(self)?;
+        if name == TEXT {
+            if let InnerMalType::String(ref s) = *value {
+                return Ok(decode_text(s));
+            }
+        }
+        Ok(value)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<MalType, SerdeError> {
+        Ok(vector(vec![keyword(variant), value.serialize(self)?]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec, SerdeError> {
+        Ok(SerializeVec {
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, SerdeError> {
+        let mut items = Vec::with_capacity(len + 1);
+        items.push(keyword(variant));
+        Ok(SerializeVec { items })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, SerdeError> {
+        Ok(SerializeMap {
+            map: HashMap::new(),
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap, SerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeMap, SerdeError> {
+        let mut map = self.serialize_map(Some(len))?;
+        map.variant = Some(variant);
+        Ok(map)
+    }
+}
+
+pub struct SerializeVec {
+    items: Vec<MalType>,
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = MalType;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.items.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<MalType, SerdeError> {
+        Ok(vector(self.items))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = MalType;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<MalType, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = MalType;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<MalType, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVec {
+    type Ok = MalType;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<MalType, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub struct SerializeMap {
+    map: HashMap<HashKey, MalType>,
+    // the key of the value serialized next
+    key: Option<HashKey>,
+    // the variant of a struct variant
+    variant: Option<&'static str>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = MalType;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        self.key = Some(hash_key(key.serialize(Serializer)?)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| SerdeError::custom("map value serialized before its key"))?;
+        self.map.insert(key, value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<MalType, SerdeError> {
+        let map = new_mal!(Hashmap(self.map, new_mal!(Nil)));
+        Ok(match self.variant {
+            Some(variant) => vector(vec![keyword(variant), map]),
+            None => map,
+        })
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = MalType;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        self.map.insert(
+            HashKey::Keyword(Sym::new(&format!(":{}", key))),
+            value.serialize(Serializer)?,
+        );
+        Ok(())
+    }
+
+    fn end(self) -> Result<MalType, SerdeError> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = MalType;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<MalType, SerdeError> {
+        ser::SerializeMap::end(self)
+    }
+}
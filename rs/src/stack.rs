@@ -0,0 +1,160 @@
+//! The depth of evaluation.
+//!
+//! `eval` and the VM keep what is left to do in frames on the heap, so
+//! recursion in mal does not recurse in Rust. Every frame counts towards a
+//! max depth, past which a catchable `StackOverflow` is raised instead of
+//! running out of memory. Where evaluation still re-enters from Rust, like
+//! a builtin calling a closure, `grow` moves on to a new stack segment
+//! before the current one runs out, and `nest` also counts the re-entry,
+//! like the realising of a lazy seq, as a frame.
+
+use error::StackOverflow;
+use failure::Fallible;
+use runtime::{self, Runtime};
+use stacker;
+use std::cell::Cell;
+
+/// The max depth a new runtime starts with.
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 1_000_000;
+
+// room left on the Rust stack below which a new segment is used, and its size
+const RED_ZONE: usize = 256 * 1024;
+const SEGMENT: usize = 4 * 1024 * 1024;
+
+struct Depth {
+    depth: Cell<usize>,
+    // that of the runtime entered
+    max: Cell<usize>,
+}
+
+thread_local! {
+    static DEPTH: Depth = const {
+        Depth {
+            depth: Cell::new(0),
+            max: Cell::new(DEFAULT_MAX_DEPTH),
+        }
+    };
+}
+
+/// Limits the frames of evaluation in `runtime` to `depth`; `None` leaves
+/// only memory as the limit.
+pub fn set_max_depth(runtime: &Runtime, depth: Option<usize>) {
+    let max = depth.unwrap_or(usize::MAX);
+    runtime.max_depth.set(max);
+    if runtime::is_entered(runtime) {
+        set_limit(max);
+    }
+}
+
+/// Limits the frames of evaluation to `max` until limited again, and
+/// returns the limit before.
+pub(crate) fn set_limit(max: usize) -> usize {
+    DEPTH.with(|d| d.max.replace(max))
+}
+
+pub fn max_depth() -> Option<usize> {
+    DEPTH.with(|d| Some(d.max.get()).filter(|&max| max != usize::MAX))
+}
+
+/// The frames of evaluation there are now.
+pub fn depth() -> usize {
+    DEPTH.with(|d| d.depth.get())
+}
+
+fn enter() -> Fallible<()> {
+    DEPTH.with(|d| {
+        let depth = d.depth.get() + 1;
+        if depth > d.max.get() {
+            return Err(StackOverflow(d.max.get()).into());
+        }
+        d.depth.set(depth);
+        Ok(())
+    })
+}
+
+fn leave(n: usize) {
+    DEPTH.with(|d| d.depth.set(d.depth.get() - n));
+}
+
+/// Runs `f`, which may evaluate, on a new stack segment if little of the
+/// current one is left.
+pub fn grow<R, F: FnOnce() -> R>(f: F) -> R {
+    stacker::maybe_grow(RED_ZONE, SEGMENT, f)
+}
+
+/// Runs `f`, which nests evaluation from Rust, like realising a lazy seq,
+/// as a frame of its own: it counts towards the max depth, and runs on a
+/// new stack segment if little of the current one is left.
+pub fn nest<R, F: FnOnce() -> Fallible<R>>(f: F) -> Fallible<R> {
+    enter()?;
+    let result = grow(f);
+    leave(1);
+    result
+}
+
+/// A stack of frames of evaluation, each counting towards the max depth.
+pub struct Frames<T> {
+    items: Vec<T>,
+}
+
+impl<T> Frames<T> {
+    pub fn new() -> Frames<T> {
+        Frames { items: Vec::new() }
+    }
+
+    /// Pushes `item`, unless that would nest deeper than the max depth.
+    pub fn push(&mut self, item: T) -> Fallible<()> {
+        enter()?;
+        self.items.push(item);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let item = self.items.pop();
+        if item.is_some() {
+            leave(1);
+        }
+        item
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.items.len() {
+            leave(self.items.len() - len);
+            self.items.truncate(len);
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.items.get(idx)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.items.last()
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut T> {
+        self.items.last_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl<T> Default for Frames<T> {
+    fn default() -> Frames<T> {
+        Frames::new()
+    }
+}
+
+/// Frames left when evaluation returns, like after an error, no longer
+/// count.
+impl<T> Drop for Frames<T> {
+    fn drop(&mut self) {
+        leave(self.items.len());
+    }
+}
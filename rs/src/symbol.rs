@@ -0,0 +1,109 @@
+use fnv::FnvHashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct Interner {
+    ids: FnvHashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+lazy_static! {
+    static ref INTERNER: Mutex<Interner> = Mutex::new(Interner::default());
+}
+
+/// An interned name, as held by symbols, keywords and environments. Names
+/// are interned once into a global table and never freed, so a `Sym` is a
+/// small id that compares and hashes in O(1) and still derefs to its name.
+#[derive(Clone, Copy)]
+pub struct Sym {
+    id: u32,
+    name: &'static str,
+}
+
+impl Sym {
+    pub fn new(name: &str) -> Sym {
+        let mut interner = INTERNER.lock().expect("lock interner");
+        if let Some(&id) = interner.ids.get(name) {
+            return Sym {
+                id,
+                name: interner.names[id as usize],
+            };
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let id = interner.names.len() as u32;
+        interner.names.push(name);
+        interner.ids.insert(name, id);
+        Sym { id, name }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl Deref for Sym {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.name
+    }
+}
+
+impl PartialEq for Sym {
+    fn eq(&self, other: &Sym) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Sym {}
+
+impl PartialEq<&str> for Sym {
+    fn eq(&self, other: &&str) -> bool {
+        self.name == *other
+    }
+}
+
+impl Hash for Sym {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u32(self.id);
+    }
+}
+
+impl fmt::Debug for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.name, f)
+    }
+}
+
+impl fmt::Display for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl From<&str> for Sym {
+    fn from(name: &str) -> Sym {
+        Sym::new(name)
+    }
+}
+
+impl From<&String> for Sym {
+    fn from(name: &String) -> Sym {
+        Sym::new(name)
+    }
+}
+
+impl From<String> for Sym {
+    fn from(name: String) -> Sym {
+        Sym::new(&name)
+    }
+}
+
+impl From<&Sym> for Sym {
+    fn from(sym: &Sym) -> Sym {
+        *sym
+    }
+}
@@ -0,0 +1,220 @@
+//! Backtraces of mal calls.
+//!
+//! An error leaving a call has the frame of that call added to it, making
+//! it `Traced`. Lists read from a file keep where they start in a side
+//! table, which `analyze` carries over to the forms it makes of them, so a
+//! frame knows where its call was written. A form `analyze` made of one
+//! written differently, like by destructuring, keeps what was written in a
+//! third table, which its frame shows. A caught exception keeps the
+//! frames of its error in another, for `ex-stack`.
+
+use error::{Position, TraceFrame, Traced};
+use failure::Error;
+use fnv::FnvHashMap;
+use printer::pr_str;
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use types::{InnerMalType, MalType};
+
+/// Frames kept of an error; those of calls further out are only counted.
+const MAX_FRAMES: usize = 64;
+
+/// Chars of a form shown in a backtrace.
+const MAX_SHOWN: usize = 60;
+
+/// Table entries before the first sweep of those freed.
+const MIN_ENTRIES: usize = 1024;
+
+/// Values kept about mal values while they live, by address. The `Weak`
+/// keeps the address from being reused until the entry is swept.
+struct Table<V> {
+    entries: FnvHashMap<usize, (Weak<InnerMalType>, V)>,
+    sweep_at: usize,
+}
+
+impl<V: Clone> Table<V> {
+    fn new() -> Table<V> {
+        Table {
+            entries: FnvHashMap::default(),
+            sweep_at: MIN_ENTRIES,
+        }
+    }
+
+    fn get(&self, mal: &MalType) -> Option<V> {
+        match self.entries.get(&key(mal)) {
+            Some((weak, value)) if weak.upgrade().is_some() => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, mal: &MalType, value: V) {
+        self.entries.insert(key(mal), (Rc::downgrade(mal), value));
+        if self.entries.len() >= self.sweep_at {
+            self.entries.retain(|_, (weak, _)| weak.upgrade().is_some());
+            self.sweep_at = (self.entries.len() * 2).max(MIN_ENTRIES);
+        }
+    }
+}
+
+fn key(mal: &MalType) -> usize {
+    &**mal as *const InnerMalType as usize
+}
+
+/// The file, line and column a list starts at.
+type Start = (Rc<str>, usize, usize);
+
+thread_local! {
+    static POSITIONS: RefCell<Table<Start>> = RefCell::new(Table::new());
+    static SOURCES: RefCell<Table<MalType>> = RefCell::new(Table::new());
+    static STACKS: RefCell<Table<Rc<Vec<TraceFrame>>>> = RefCell::new(Table::new());
+}
+
+/// Records that `form` was read from `file` at `line` and `column`.
+pub fn set_position(form: &MalType, file: Rc<str>, line: usize, column: usize) {
+    POSITIONS.with(|p| p.borrow_mut().insert(form, (file, line, column)));
+}
+
+pub fn position(form: &MalType) -> Option<Position> {
+    POSITIONS
+        .with(|p| p.borrow().get(form))
+        .map(|(file, line, column)| Position {
+            file: file.to_string(),
+            line,
+            column,
+        })
+}
+
+/// Gives `to`, made of `from`, the position of `from` unless it has one.
+pub fn copy_position(from: &MalType, to: &MalType) {
+    POSITIONS.with(|p| {
+        let mut p = p.borrow_mut();
+        if p.get(to).is_none() {
+            if let Some(start) = p.get(from) {
+                p.insert(to, start);
+            }
+        }
+    });
+}
+
+/// Records that `form` was made of `from`, or of what `from` was made of,
+/// unless what it was made of is known already.
+pub fn set_source(form: &MalType, from: &MalType) {
+    SOURCES.with(|s| {
+        let mut s = s.borrow_mut();
+        if s.get(form).is_none() {
+            let source = s.get(from).unwrap_or_else(|| from.clone());
+            s.insert(form, source);
+        }
+    });
+}
+
+/// The form written which `form` was made of, if it was written otherwise.
+pub fn source(form: &MalType) -> Option<MalType> {
+    SOURCES.with(|s| s.borrow().get(form))
+}
+
+/// The name of the fn `form` calls.
+fn called(form: &MalType) -> String {
+    match form.to_items_ref().front().map(|head| &**head) {
+        Some(InnerMalType::Symbol(s))
+        | Some(InnerMalType::Local(s, ..))
+        | Some(InnerMalType::Global(s, _)) => s.to_string(),
+        Some(InnerMalType::Keyword(..)) => pr_str(form.to_items_ref().front().unwrap(), true),
+        _ => "fn".to_string(),
+    }
+}
+
+/// `e` raised in the call `form`, outside the calls it has frames of.
+pub fn frame(e: Error, form: &MalType) -> Error {
+    frames(e, Some(form))
+}
+
+/// `e` raised in the calls `forms`, innermost first, outside the calls it
+/// has frames of.
+pub fn frames<'a, I: IntoIterator<Item = &'a MalType>>(e: Error, forms: I) -> Error {
+    let mut traced = match e.downcast::<Traced>() {
+        Ok(traced) => traced,
+        Err(error) => Traced {
+            error,
+            stack: Vec::new(),
+            elided: 0,
+        },
+    };
+    for form in forms {
+        if traced.stack.len() < MAX_FRAMES {
+            let shown = source(form).unwrap_or_else(|| form.clone());
+            traced.stack.push(TraceFrame {
+                // a pattern, whose value a call made of it takes apart
+                name: if shown.is_list() {
+                    called(&shown)
+                } else {
+                    "destructuring".to_string()
+                },
+                form: pr_str(&shown, true),
+                position: position(form),
+            });
+        } else {
+            traced.elided += 1;
+        }
+    }
+    traced.into()
+}
+
+/// The error `e` was raised as, without its frames.
+pub fn cause(e: &Error) -> &Error {
+    match e.downcast_ref::<Traced>() {
+        Some(traced) => &traced.error,
+        None => e,
+    }
+}
+
+/// The frames `e` has.
+pub fn stack_of(e: &Error) -> Vec<TraceFrame> {
+    match e.downcast_ref::<Traced>() {
+        Some(traced) => traced.stack.clone(),
+        None => Vec::new(),
+    }
+}
+
+/// Keeps the frames of the error `exception` was caught from.
+pub fn set_stack(exception: &MalType, stack: Vec<TraceFrame>) {
+    if !stack.is_empty() {
+        STACKS.with(|s| s.borrow_mut().insert(exception, Rc::new(stack)));
+    }
+}
+
+/// The frames of the error `exception` was caught from, if it was.
+pub fn stack(exception: &MalType) -> Vec<TraceFrame> {
+    STACKS
+        .with(|s| s.borrow().get(exception))
+        .map(|stack| (*stack).clone())
+        .unwrap_or_default()
+}
+
+/// At most `MAX_SHOWN` chars of the printed `form`.
+fn abbreviated(form: &str) -> String {
+    match form.char_indices().nth(MAX_SHOWN) {
+        Some((at, _)) => format!("{}...", &form[..at]),
+        None => form.to_string(),
+    }
+}
+
+/// The lines printed under an error to show where it was raised.
+pub fn backtrace(e: &Error) -> String {
+    let traced = match e.downcast_ref::<Traced>() {
+        Some(traced) => traced,
+        None => return String::new(),
+    };
+    let mut s = String::new();
+    for frame in &traced.stack {
+        s.push_str(&format!("  at {} {}", frame.name, abbreviated(&frame.form)));
+        if let Some(ref p) = frame.position {
+            s.push_str(&format!(" ({}:{}:{})", p.file, p.line, p.column));
+        }
+        s.push('\n');
+    }
+    if traced.elided > 0 {
+        s.push_str(&format!("  ... {} more\n", traced.elided));
+    }
+    s
+}
@@ -1,8 +1,20 @@
-use env::Env;
+use arity::Arities;
+use compile::Proto;
+use env::{env_get, env_get_meta, Binding, Env};
 use failure::Fallible;
-use std::cell::RefCell;
+use gc;
+use lazy::LazySeq;
+use multi::MultiFn;
+use namespace::Namespace;
+use protocol::Protocol;
+use record::RecordType;
+use symbol::Sym;
+use regex::Regex;
+use std::cell::{OnceCell, RefCell};
 use std::collections::HashMap;
 use std::collections::LinkedList;
+use std::fmt;
+use std::mem;
 use std::rc::Rc;
 
 pub type ClosureFunc = fn(LinkedList<MalType>, Option<ClosureEnv>) -> Fallible<MalType>;
@@ -25,20 +37,53 @@ macro_rules! linked_list {
 pub enum InnerMalType {
     List(LinkedList<MalType>, MalType),
     Vec(LinkedList<MalType>, MalType),
+    LazySeq(LazySeq, MalType),
     Hashmap(HashMap<HashKey, MalType>, MalType),
+    Record(Rc<RecordType>, HashMap<HashKey, MalType>, MalType),
     Num(f64),
-    Symbol(String),
-    Keyword(String),
+    Symbol(Sym),
+    Keyword(Sym),
     String(String),
     Nil,
     Bool(bool),
+    Regex(Pattern),
 
     Atom(RefCell<MalType>),
     Closure(Closure, MalType),
+    Var(Var),
+    Type(Kind),
+    Protocol(Rc<Protocol>),
+    Namespace(Rc<Namespace>),
+    Env(Env),
+
+    // Symbols resolved by the analyzer: a lexical binding as (depth, slot),
+    // and a global with its binding cached on first lookup.
+    Local(Sym, usize, usize),
+    Global(Sym, RefCell<Option<Rc<Binding>>>),
 }
 
 pub type MalType = Rc<InnerMalType>;
 
+/// Drops the items of nested lists, vectors, maps and lazy seqs in a loop, as
+/// recursing into them could overflow the stack.
+impl Drop for InnerMalType {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.take_items(&mut pending);
+        drop_all(pending);
+    }
+}
+
+/// Drops `pending`, moving the items of values only they hold onto it
+/// first.
+pub(crate) fn drop_all(mut pending: Vec<MalType>) {
+    while let Some(mal) = pending.pop() {
+        if let Ok(mut inner) = Rc::try_unwrap(mal) {
+            inner.take_items(&mut pending);
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! new_mal {
     ($t:tt($($arg:expr),*)) => {{
@@ -54,22 +99,59 @@ pub struct Closure {
     pub func: ClosureFunc,
     pub c_env: Option<ClosureEnv>,
     pub is_macro: bool,
+    pub multi: Option<Rc<MultiFn>>,
+    pub arities: Option<Rc<Arities>>,
+    pub native: Option<NativeFn>,
+}
+
+/// A Rust closure callable from mal, as registered by an embedder.
+#[derive(Clone)]
+pub struct NativeFn(pub Rc<dyn Fn(LinkedList<MalType>) -> Fallible<MalType>>);
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFn")
+    }
+}
+
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &NativeFn) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
 }
 
 #[derive(DebugStub, Clone, PartialEq)]
 pub struct ClosureEnv {
     pub parameters: MalType,
+    // names bound by parameters, without "&"
+    pub binds: Rc<Vec<Sym>>,
+    pub variadic: bool,
     pub body: MalType,
     #[debug_stub = ".."]
     pub env: Env,
+    // the compiled body, unless it runs in `eval`
+    #[debug_stub = ".."]
+    pub proto: Option<Rc<Proto>>,
+    // locals of the compiled fns it is nested in, as `proto` refers to them
+    pub upvalues: Rc<Vec<MalType>>,
+    // the name of its `fn*`, or of the `def!` it was made for
+    pub name: Option<Sym>,
 }
 
 impl ClosureEnv {
     pub fn new(params: MalType, body: MalType, env: Env) -> Self {
+        let mut binds = params.to_symbol_list();
+        let variadic = binds.iter().position(|e| *e == "&").map(|idx| binds.remove(idx)).is_some();
+        gc::track_env(&env);
         ClosureEnv {
             parameters: params,
+            binds: Rc::new(binds),
+            variadic,
             body,
             env,
+            proto: None,
+            upvalues: Rc::new(Vec::new()),
+            name: None,
         }
     }
 }
@@ -80,41 +162,316 @@ impl Closure {
             func,
             c_env,
             is_macro: false,
+            multi: None,
+            arities: None,
+            native: None,
+        }
+    }
+
+    pub fn new_native<F>(f: F) -> Self
+    where
+        F: Fn(LinkedList<MalType>) -> Fallible<MalType> + 'static,
+    {
+        Closure {
+            func: call_native,
+            c_env: None,
+            is_macro: false,
+            multi: None,
+            arities: None,
+            native: Some(NativeFn(Rc::new(f))),
+        }
+    }
+
+    pub fn new_multi(multi: MultiFn) -> Self {
+        Closure {
+            func: call_multi,
+            c_env: None,
+            is_macro: false,
+            multi: Some(Rc::new(multi)),
+            arities: None,
+            native: None,
+        }
+    }
+
+    pub fn new_arities(arities: Arities) -> Self {
+        Closure {
+            func: call_arities,
+            c_env: None,
+            is_macro: false,
+            multi: None,
+            arities: Some(Rc::new(arities)),
+            native: None,
         }
     }
 
     pub fn call(&self, params: LinkedList<MalType>) -> Fallible<MalType> {
+        if let Some(ref multi) = self.multi {
+            return multi.method_for(&params)?.call(params);
+        }
+        if let Some(ref arities) = self.arities {
+            return arities.closure_for(params.len())?.call(params);
+        }
+        if let Some(NativeFn(ref f)) = self.native {
+            return f(params);
+        }
         let f = &self.func;
         f(params, self.c_env.clone())
     }
 }
 
+fn call_multi(_params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    unreachable!("multimethods are dispatched by Closure::call")
+}
+
+fn call_arities(_params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    unreachable!("fns of several arities are dispatched by Closure::call")
+}
+
+fn call_native(_params: LinkedList<MalType>, _c_env: Option<ClosureEnv>) -> Fallible<MalType> {
+    unreachable!("native fns are called by Closure::call")
+}
+
+/// A compiled regex, and the regex matching all of a string with it, made
+/// for `re-matches` when first needed. Patterns compare equal when their
+/// source is the same.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub regex: Regex,
+    anchored: Rc<OnceCell<Regex>>,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Fallible<Pattern> {
+        Ok(Pattern {
+            regex: Regex::new(source)?,
+            anchored: Rc::new(OnceCell::new()),
+        })
+    }
+
+    /// The regex anchored at the start and end of the string.
+    pub fn anchored(&self) -> Fallible<&Regex> {
+        if let Some(anchored) = self.anchored.get() {
+            return Ok(anchored);
+        }
+        let anchored = Regex::new(&format!("^(?:{})$", self.regex.as_str()))?;
+        Ok(self.anchored.get_or_init(|| anchored))
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+    }
+}
+
+/// A reference to a binding, as returned by `(var sym)`.
+#[derive(Clone)]
+pub struct Var {
+    pub env: Env,
+    pub name: Sym,
+}
+
+impl Var {
+    pub fn new(env: Env, name: Sym) -> Self {
+        gc::track_env(&env);
+        Var { env, name }
+    }
+
+    pub fn get(&self) -> MalType {
+        env_get(self.env.clone(), self.name).unwrap_or_else(|| new_mal!(Nil))
+    }
+
+    pub fn meta(&self) -> MalType {
+        env_get_meta(self.env.clone(), self.name).unwrap_or_else(|| new_mal!(Nil))
+    }
+}
+
+impl fmt::Debug for Var {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Var({})", self.name)
+    }
+}
+
+impl PartialEq for Var {
+    fn eq(&self, other: &Var) -> bool {
+        Rc::ptr_eq(&self.env, &other.env) && self.name == other.name
+    }
+}
+
+/// The type of a value, as returned by `(type x)` and used to dispatch
+/// protocol methods. `Object` is not the type of any value; extending it
+/// provides a default implementation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Nil,
+    Bool,
+    Number,
+    String,
+    Keyword,
+    Symbol,
+    List,
+    Vector,
+    LazySeq,
+    Map,
+    Fn,
+    Atom,
+    Regex,
+    Var,
+    Type,
+    Protocol,
+    Namespace,
+    Env,
+    Record(Rc<RecordType>),
+    Object,
+}
+
+impl Kind {
+    /// The built-in kinds bound by name in the global environment. `nil` is
+    /// its own type.
+    pub fn builtins() -> Vec<Kind> {
+        vec![
+            Kind::Bool,
+            Kind::Number,
+            Kind::String,
+            Kind::Keyword,
+            Kind::Symbol,
+            Kind::List,
+            Kind::Vector,
+            Kind::LazySeq,
+            Kind::Map,
+            Kind::Fn,
+            Kind::Atom,
+            Kind::Regex,
+            Kind::Var,
+            Kind::Type,
+            Kind::Protocol,
+            Kind::Namespace,
+            Kind::Env,
+            Kind::Object,
+        ]
+    }
+
+    pub fn of(mal: &InnerMalType) -> Kind {
+        match mal {
+            InnerMalType::Nil => Kind::Nil,
+            InnerMalType::Bool(_) => Kind::Bool,
+            InnerMalType::Num(_) => Kind::Number,
+            InnerMalType::String(_) => Kind::String,
+            InnerMalType::Keyword(_) => Kind::Keyword,
+            InnerMalType::Symbol(_) | InnerMalType::Local(..) | InnerMalType::Global(..) => {
+                Kind::Symbol
+            }
+            InnerMalType::List(..) => Kind::List,
+            InnerMalType::Vec(..) => Kind::Vector,
+            InnerMalType::LazySeq(..) => Kind::LazySeq,
+            InnerMalType::Hashmap(..) => Kind::Map,
+            InnerMalType::Record(rtype, ..) => Kind::Record(rtype.clone()),
+            InnerMalType::Closure(..) => Kind::Fn,
+            InnerMalType::Atom(_) => Kind::Atom,
+            InnerMalType::Regex(_) => Kind::Regex,
+            InnerMalType::Var(_) => Kind::Var,
+            InnerMalType::Type(_) => Kind::Type,
+            InnerMalType::Protocol(_) => Kind::Protocol,
+            InnerMalType::Namespace(_) => Kind::Namespace,
+            InnerMalType::Env(_) => Kind::Env,
+        }
+    }
+
+    /// Reads a type argument: a type value, or `nil` for the type of nil.
+    pub fn from_mal(mal: &MalType) -> Fallible<Kind> {
+        match **mal {
+            InnerMalType::Type(ref kind) => Ok(kind.clone()),
+            InnerMalType::Nil => Ok(Kind::Nil),
+            _ => bail_type!("{:?} is not a type", mal),
+        }
+    }
+
+    /// The kinds a value of this kind is an instance of, most specific
+    /// first: records are also maps, and everything is an `Object`.
+    pub fn supers(&self) -> Vec<Kind> {
+        match self {
+            Kind::Object => vec![Kind::Object],
+            Kind::Record(_) => vec![self.clone(), Kind::Map, Kind::Object],
+            _ => vec![self.clone(), Kind::Object],
+        }
+    }
+
+    pub fn is_record(&self) -> bool {
+        if let Kind::Record(_) = self {
+            return true;
+        }
+        return false;
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Kind::Nil => "nil",
+            Kind::Bool => "Boolean",
+            Kind::Number => "Number",
+            Kind::String => "String",
+            Kind::Keyword => "Keyword",
+            Kind::Symbol => "Symbol",
+            Kind::List => "List",
+            Kind::Vector => "Vector",
+            Kind::LazySeq => "LazySeq",
+            Kind::Map => "Map",
+            Kind::Fn => "Fn",
+            Kind::Atom => "Atom",
+            Kind::Regex => "Regex",
+            Kind::Var => "Var",
+            Kind::Type => "Type",
+            Kind::Protocol => "Protocol",
+            Kind::Namespace => "Namespace",
+            Kind::Env => "Env",
+            Kind::Record(rtype) => return rtype.name.clone(),
+            Kind::Object => "Object",
+        }.to_string()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum HashKey {
     String(String),
-    Keyword(String),
+    Keyword(Sym),
+    Symbol(Sym),
 }
 
 impl HashKey {
     pub fn to_mal_type(&self) -> MalType {
         match *self {
             HashKey::String(ref s) => new_mal!(String(s.to_owned())),
-            HashKey::Keyword(ref s) => new_mal!(Keyword(s.to_owned())),
+            HashKey::Keyword(s) => new_mal!(Keyword(s)),
+            HashKey::Symbol(s) => new_mal!(Symbol(s)),
         }
     }
     pub fn into_mal_type(self) -> MalType {
         match self {
             HashKey::String(s) => new_mal!(String(s)),
             HashKey::Keyword(s) => new_mal!(Keyword(s)),
+            HashKey::Symbol(s) => new_mal!(Symbol(s)),
         }
     }
 }
 
 impl InnerMalType {
+    fn take_items(&mut self, pending: &mut Vec<MalType>) {
+        match *self {
+            InnerMalType::List(ref mut l, _) | InnerMalType::Vec(ref mut l, _) => {
+                pending.extend(mem::take(l))
+            }
+            InnerMalType::Hashmap(ref mut m, _) | InnerMalType::Record(_, ref mut m, _) => {
+                pending.extend(m.drain().map(|(_, v)| v))
+            }
+            InnerMalType::LazySeq(ref seq, _) => seq.take_unshared(pending),
+            _ => {}
+        }
+    }
+
     pub fn to_hash_key(&self) -> HashKey {
         match self {
             InnerMalType::String(s) => HashKey::String(s.clone()),
-            InnerMalType::Keyword(s) => HashKey::Keyword(s.clone()),
+            InnerMalType::Keyword(s) => HashKey::Keyword(*s),
+            InnerMalType::Symbol(s) => HashKey::Symbol(*s),
             _ => unreachable!(),
         }
     }
@@ -126,16 +483,9 @@ impl InnerMalType {
         }
     }
 
-    pub fn to_symbol(&self) -> String {
+    pub fn to_symbol(&self) -> Sym {
         match self {
-            InnerMalType::Symbol(s) => s.clone(),
-            _ => unreachable!(),
-        }
-    }
-
-    pub fn to_symbol_ref(&self) -> &String {
-        match self {
-            InnerMalType::Symbol(s) => s,
+            InnerMalType::Symbol(s) => *s,
             _ => unreachable!(),
         }
     }
@@ -173,25 +523,57 @@ impl InnerMalType {
 
     pub fn to_hashmap(&self) -> HashMap<HashKey, MalType> {
         match self {
-            InnerMalType::Hashmap(l, ..) => l.clone(),
+            InnerMalType::Hashmap(l, ..) | InnerMalType::Record(_, l, _) => l.clone(),
             _ => unreachable!(),
         }
     }
 
     pub fn to_hashmap_ref(&self) -> &HashMap<HashKey, MalType> {
         match self {
-            InnerMalType::Hashmap(l, ..) => l,
+            InnerMalType::Hashmap(l, ..) | InnerMalType::Record(_, l, _) => l,
             _ => unreachable!(),
         }
     }
 
-    pub fn to_symbol_list(&self) -> Vec<String> {
+    pub fn to_symbol_list(&self) -> Vec<Sym> {
         let l = match *self {
             InnerMalType::List(ref l, ..) => l,
             InnerMalType::Vec(ref l, ..) => l,
             _ => unreachable!(),
         };
-        l.iter().map(|el| el.to_symbol().to_owned()).collect()
+        l.iter().map(|el| el.to_symbol()).collect()
+    }
+
+    pub fn to_regex(&self) -> Regex {
+        self.to_pattern().regex.clone()
+    }
+
+    pub fn to_pattern(&self) -> &Pattern {
+        match self {
+            InnerMalType::Regex(pattern) => pattern,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn to_namespace(&self) -> Rc<Namespace> {
+        match self {
+            InnerMalType::Namespace(ns) => ns.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn to_env(&self) -> Env {
+        match self {
+            InnerMalType::Env(env) => env.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn to_protocol(&self) -> Rc<Protocol> {
+        match self {
+            InnerMalType::Protocol(p) => p.clone(),
+            _ => unreachable!(),
+        }
     }
 
     pub fn to_number(&self) -> f64 {
@@ -231,6 +613,48 @@ impl InnerMalType {
         return false;
     }
 
+    pub fn is_regex(&self) -> bool {
+        if let &InnerMalType::Regex(_) = self {
+            return true;
+        }
+        return false;
+    }
+
+    pub fn is_var(&self) -> bool {
+        if let &InnerMalType::Var(_) = self {
+            return true;
+        }
+        return false;
+    }
+
+    pub fn is_namespace(&self) -> bool {
+        if let &InnerMalType::Namespace(_) = self {
+            return true;
+        }
+        return false;
+    }
+
+    pub fn is_env(&self) -> bool {
+        if let &InnerMalType::Env(_) = self {
+            return true;
+        }
+        return false;
+    }
+
+    pub fn is_protocol(&self) -> bool {
+        if let &InnerMalType::Protocol(_) = self {
+            return true;
+        }
+        return false;
+    }
+
+    pub fn is_type(&self) -> bool {
+        if let &InnerMalType::Type(_) = self {
+            return true;
+        }
+        return false;
+    }
+
     pub fn is_num(&self) -> bool {
         if let &InnerMalType::Num(_) = self {
             return true;
@@ -252,6 +676,17 @@ impl InnerMalType {
         return false;
     }
 
+    pub fn is_lazy_seq(&self) -> bool {
+        if let &InnerMalType::LazySeq(..) = self {
+            return true;
+        }
+        return false;
+    }
+
+    pub fn is_sequential(&self) -> bool {
+        return self.is_collection() || self.is_lazy_seq();
+    }
+
     pub fn is_collection(&self) -> bool {
         return self.is_vec() || self.is_list();
     }
@@ -281,8 +716,16 @@ impl InnerMalType {
         return false;
     }
 
+    /// True for maps and records, which support every map operation.
     pub fn is_hashmap(&self) -> bool {
-        if let &InnerMalType::Hashmap(..) = self {
+        match self {
+            InnerMalType::Hashmap(..) | InnerMalType::Record(..) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_record(&self) -> bool {
+        if let &InnerMalType::Record(..) = self {
             return true;
         }
         return false;
@@ -302,8 +745,8 @@ impl InnerMalType {
     }
 
     pub fn is_empty_hashmap(&self) -> bool {
-        if let InnerMalType::Hashmap(ref list, ..) = *self {
-            return list.is_empty();
+        if self.is_hashmap() {
+            return self.to_hashmap_ref().is_empty();
         }
         return false;
     }
@@ -365,8 +808,11 @@ impl InnerMalType {
         let m = match self {
             InnerMalType::List(_, metadata) => metadata,
             InnerMalType::Vec(_, metadata) => metadata,
+            InnerMalType::LazySeq(_, metadata) => metadata,
             InnerMalType::Hashmap(_, metadata) => metadata,
+            InnerMalType::Record(_, _, metadata) => metadata,
             InnerMalType::Closure(_, metadata) => metadata,
+            InnerMalType::Var(var) => return var.meta(),
             _ => unreachable!(),
         };
         m.clone()
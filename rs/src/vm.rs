@@ -0,0 +1,482 @@
+//! Runs the bytecode of compiled fns.
+//!
+//! Calls between compiled closures push a frame instead of recursing, and
+//! one in tail position replaces the frame of the caller. A frame's
+//! registers and the values it is working on share one stack: the callee
+//! sits below its args, which become the first registers of its frame.
+//! Any other callee is called through `Closure::call`.
+//!
+//! A fn whose locals are kept in envs changes the env of its frame as it
+//! binds them, and runs the forms needing an env in `eval` with it.
+
+use arity::{self, arity_error};
+use compile::{Capture, Op, Proto};
+use core::{apply_params, is_apply};
+use env::{env_bind, env_get_global, env_get_slot, env_new, env_outer, Env};
+use error::NotFound;
+use eval::{call_for_closure, eval, eval_top_in};
+use exception::{caught, selects};
+use failure::{Error, Fallible};
+#[cfg(jit)]
+use jit;
+use lazy::{seq_to_list, LazySeq};
+use stack::{self, Frames};
+use std::collections::{HashMap, LinkedList};
+use std::rc::Rc;
+use symbol::Sym;
+use trace;
+use types::{Closure, ClosureEnv, InnerMalType, MalType};
+
+struct Frame {
+    proto: Rc<Proto>,
+    upvalues: Rc<Vec<MalType>>,
+    env: Env,
+    ip: usize,
+    // where register 0 is on the stack
+    base: usize,
+}
+
+/// A `try*` being run.
+struct Handler {
+    frame: usize,
+    catch: usize,
+    reg: usize,
+    // the env of the frame, the height of the stack and of the errors
+    // caught when it began
+    env: Env,
+    stack: usize,
+    caught: usize,
+}
+
+struct Vm {
+    stack: Vec<MalType>,
+    frames: Frames<Frame>,
+    handlers: Vec<Handler>,
+    // the errors caught whose `catch*` clause is being chosen or which a
+    // `finally` clause raises again when done
+    caught: Vec<Error>,
+    nil: MalType,
+}
+
+/// Calls the compiled closure of `c_env` with `params`.
+pub fn call(c_env: &ClosureEnv, params: LinkedList<MalType>) -> Fallible<MalType> {
+    stack::grow(|| run(c_env, params))
+}
+
+fn run(c_env: &ClosureEnv, params: LinkedList<MalType>) -> Fallible<MalType> {
+    let nil = new_mal!(Nil);
+    let mut vm = Vm {
+        stack: Vec::with_capacity(64),
+        frames: Frames::new(),
+        handlers: Vec::new(),
+        caught: Vec::new(),
+        nil: nil.clone(),
+    };
+    vm.stack.push(nil);
+    let argc = params.len();
+    vm.stack.extend(params);
+    #[cfg(jit)]
+    {
+        if let Some(result) = jit::call(c_env, &vm.stack[1..]) {
+            return Ok(result);
+        }
+    }
+    vm.enter(c_env, argc, false)?;
+    vm.run()
+}
+
+/// The closure of the nested `proto` made in `frame`.
+fn make_closure(proto: &Rc<Proto>, frame: &Frame, stack: &[MalType]) -> MalType {
+    new_mal!(Closure(
+        closure(proto, None, &frame.env, frame, stack),
+        new_mal!(Nil)
+    ))
+}
+
+/// The fn of the nested clauses `protos` made in `frame`. A named one is
+/// bound to its name in an env around them.
+fn make_fn(protos: &[Rc<Proto>], name: Option<Sym>, frame: &Frame, stack: &[MalType]) -> MalType {
+    let env = match name {
+        Some(_) => env_new(Some(frame.env.clone()), vec![], vec![]),
+        None => frame.env.clone(),
+    };
+    let mut closures: Vec<Closure> = protos
+        .iter()
+        .map(|proto| closure(proto, name, &env, frame, stack))
+        .collect();
+    let f = if closures.len() == 1 {
+        new_mal!(Closure(closures.pop().unwrap(), new_mal!(Nil)))
+    } else {
+        arity::new_fn(name, closures)
+    };
+    if let Some(name) = name {
+        env_bind(&env, name, f.clone());
+    }
+    f
+}
+
+fn closure(
+    proto: &Rc<Proto>,
+    name: Option<Sym>,
+    env: &Env,
+    frame: &Frame,
+    stack: &[MalType],
+) -> Closure {
+    let upvalues = proto
+        .captures
+        .iter()
+        .map(|capture| match *capture {
+            Capture::Reg(reg) => stack[frame.base + reg as usize].clone(),
+            Capture::Upvalue(idx) => frame.upvalues[idx as usize].clone(),
+        })
+        .collect();
+    let c_env = ClosureEnv {
+        parameters: proto.params.clone(),
+        binds: proto.binds.clone(),
+        variadic: proto.variadic,
+        body: proto.body.clone(),
+        env: env.clone(),
+        proto: Some(proto.clone()),
+        upvalues: Rc::new(upvalues),
+        name,
+    };
+    Closure::new(call_for_closure, Some(c_env))
+}
+
+impl Vm {
+    fn run(&mut self) -> Fallible<MalType> {
+        loop {
+            let e = match self.execute() {
+                Ok(value) => return Ok(value),
+                Err(e) => self.raised(e),
+            };
+            let handler = match self.handlers.pop() {
+                Some(handler) => handler,
+                None => return Err(self.unwound(e, 0)),
+            };
+            let e = self.unwound(e, handler.frame + 1);
+            self.frames.truncate(handler.frame + 1);
+            self.stack.truncate(handler.stack);
+            self.caught.truncate(handler.caught);
+            let frame = self.frames.last_mut().unwrap();
+            frame.env = handler.env;
+            self.stack[frame.base + handler.reg] = caught(&e);
+            self.caught.push(e);
+            frame.ip = handler.catch;
+        }
+    }
+
+    /// `e` raised by the op just run, in the call it made if it was one.
+    fn raised(&self, e: Error) -> Error {
+        let frame = self.frames.last().unwrap();
+        match frame.proto.site(frame.ip - 1) {
+            Some(form) => trace::frame(e, form),
+            None => e,
+        }
+    }
+
+    /// `e` leaving the frames from `to` up, each in the call its caller
+    /// made. Who called the first frame is not known here.
+    fn unwound(&self, e: Error, to: usize) -> Error {
+        let frames = &self.frames;
+        let calls = (to.max(1)..frames.len()).rev().filter_map(|idx| {
+            let caller = frames.get(idx - 1).unwrap();
+            caller.proto.site(caller.ip - 1)
+        });
+        trace::frames(e, calls)
+    }
+
+    /// Makes a frame for the compiled closure of `c_env`, whose `argc` args
+    /// are on top of the stack. In `tail` position it replaces the current
+    /// frame.
+    fn enter(&mut self, c_env: &ClosureEnv, argc: usize, tail: bool) -> Fallible<()> {
+        let proto = c_env.proto.clone().unwrap();
+        let mut base = self.stack.len() - argc;
+        let nparams = proto.binds.len();
+        if proto.variadic {
+            let idx = nparams - 1;
+            if argc < idx {
+                return Err(arity_error(c_env, argc));
+            }
+            let varargs: LinkedList<MalType> = self.stack.drain(base + idx..).collect();
+            self.stack.push(new_mal!(List(varargs, new_mal!(Nil))));
+        } else if argc != nparams {
+            return Err(arity_error(c_env, argc));
+        }
+        let mut env = c_env.env.clone();
+        if proto.in_env {
+            let args = self.stack.drain(base..).collect();
+            if proto.frame {
+                env = env_new(Some(env), proto.binds.iter().cloned(), args);
+            }
+        }
+        if tail {
+            let frame = self.frames.pop().unwrap();
+            self.stack.drain(frame.base - 1..base - 1);
+            base = frame.base;
+        }
+        let len = base + proto.nregs;
+        let nil = &self.nil;
+        self.stack.resize_with(len, || nil.clone());
+        self.frames.push(Frame {
+            proto,
+            upvalues: c_env.upvalues.clone(),
+            env,
+            ip: 0,
+            base,
+        })
+    }
+
+    /// Calls the callee below the `argc` args on top of the stack. Returns
+    /// whether it entered a frame, else the result was pushed.
+    fn call(&mut self, argc: usize, tail: bool) -> Fallible<bool> {
+        let at = self.stack.len() - argc - 1;
+        let f = self.stack[at].clone();
+        match *f {
+            InnerMalType::Closure(ref closure, ..) => {
+                // the method chosen by a multimethod runs in tail position
+                let closure = match closure.multi {
+                    Some(ref multi) => {
+                        let params = self.stack[at + 1..].iter().cloned().collect();
+                        multi.method_for(&params)?
+                    }
+                    None => closure.clone(),
+                };
+                // and so does the clause of a fn of several arities
+                let closure = match closure.arities {
+                    Some(ref arities) => arities.closure_for(argc)?,
+                    None => closure,
+                };
+                if let Some(ref c_env) = closure.c_env {
+                    if c_env.proto.is_some() {
+                        #[cfg(jit)]
+                        {
+                            if let Some(result) = jit::call(c_env, &self.stack[at + 1..]) {
+                                self.stack.truncate(at);
+                                self.stack.push(result);
+                                return Ok(false);
+                            }
+                        }
+                        self.enter(c_env, argc, tail)?;
+                        return Ok(true);
+                    }
+                }
+                let params = self.stack.drain(at + 1..).collect();
+                self.stack.pop();
+                if closure.native.is_none() && is_apply(closure.func) {
+                    // the func `apply` calls is called in its place
+                    let (func, params) = apply_params(params)?;
+                    let argc = params.len();
+                    self.stack.push(func);
+                    self.stack.extend(params);
+                    return self.call(argc, tail);
+                }
+                let result = closure.call(params)?;
+                self.stack.push(result);
+                Ok(false)
+            }
+            InnerMalType::Keyword(..) => {
+                // (:k map) and (:k map default) look the keyword up in map
+                ensure_arity!(
+                    argc == 1 || argc == 2,
+                    "keyword lookup should have 1 or 2 params"
+                );
+                let default = if argc == 2 {
+                    self.stack.pop().unwrap()
+                } else {
+                    self.nil.clone()
+                };
+                let map = self.stack.pop().unwrap();
+                self.stack.pop();
+                let result = if map.is_hashmap() {
+                    map.to_hashmap_ref()
+                        .get(&f.to_hash_key())
+                        .cloned()
+                        .unwrap_or(default)
+                } else {
+                    default
+                };
+                self.stack.push(result);
+                Ok(false)
+            }
+            _ => bail_type!("{:?} is not a function", f),
+        }
+    }
+
+    /// Returns the value on top of the stack from the current frame, or
+    /// from the run if that was the first one.
+    fn ret(&mut self) -> Option<MalType> {
+        let value = self.stack.pop().unwrap();
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base - 1);
+        if self.frames.is_empty() {
+            return Some(value);
+        }
+        self.stack.push(value);
+        None
+    }
+
+    /// Runs until the first frame returns.
+    fn execute(&mut self) -> Fallible<MalType> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let op = frame.proto.code[frame.ip];
+            frame.ip += 1;
+            match op {
+                Op::Const(idx) => {
+                    let value = frame.proto.consts[idx as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Reg(reg) => {
+                    let value = self.stack[frame.base + reg as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Upvalue(idx) => {
+                    let value = frame.upvalues[idx as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Slot(depth, slot) => {
+                    let value = env_get_slot(&frame.env, depth as usize, slot as usize);
+                    self.stack.push(value);
+                }
+                Op::Global(idx) => {
+                    let global = &frame.proto.consts[idx as usize];
+                    let value = match **global {
+                        InnerMalType::Global(name, ref cache) => {
+                            match env_get_global(&frame.env, name, cache) {
+                                Some(value) => value,
+                                None => return Err(NotFound(name.to_string()).into()),
+                            }
+                        }
+                        _ => unreachable!(),
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetReg(reg) => {
+                    let value = self.stack.pop().unwrap();
+                    self.stack[frame.base + reg as usize] = value;
+                }
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::Jump(ip) => frame.ip = ip as usize,
+                Op::JumpIfFalse(ip) => match *self.stack.pop().unwrap() {
+                    InnerMalType::Nil | InnerMalType::Bool(false) => frame.ip = ip as usize,
+                    _ => {}
+                },
+                Op::Call(argc) => {
+                    self.call(argc as usize, false)?;
+                }
+                // one which did not enter a frame returns its result
+                Op::TailCall(argc) => {
+                    if !self.call(argc as usize, true)? {
+                        if let Some(value) = self.ret() {
+                            return Ok(value);
+                        }
+                    }
+                }
+                Op::Return => {
+                    if let Some(value) = self.ret() {
+                        return Ok(value);
+                    }
+                }
+                Op::Closure(idx) => {
+                    let closure =
+                        make_closure(&frame.proto.protos[idx as usize], frame, &self.stack);
+                    self.stack.push(closure);
+                }
+                Op::LazySeq(idx) => {
+                    let thunk = make_closure(&frame.proto.protos[idx as usize], frame, &self.stack)
+                        .to_closure();
+                    self.stack.push(new_mal!(LazySeq(
+                        LazySeq::new(move || thunk.call(LinkedList::new())),
+                        new_mal!(Nil)
+                    )));
+                }
+                Op::Vec(n) => {
+                    let at = self.stack.len() - n as usize;
+                    let items = self.stack.drain(at..).collect();
+                    self.stack.push(new_mal!(Vec(items, new_mal!(Nil))));
+                }
+                Op::Map(idx) => {
+                    let keys = &frame.proto.keys[idx as usize];
+                    let at = self.stack.len() - keys.len();
+                    let mapping: HashMap<_, _> =
+                        keys.iter().cloned().zip(self.stack.drain(at..)).collect();
+                    self.stack.push(new_mal!(Hashmap(mapping, new_mal!(Nil))));
+                }
+                Op::Try(catch, reg) => {
+                    let env = frame.env.clone();
+                    let handler = Handler {
+                        frame: self.frames.len() - 1,
+                        catch: catch as usize,
+                        reg: reg as usize,
+                        env,
+                        stack: self.stack.len(),
+                        caught: self.caught.len(),
+                    };
+                    self.handlers.push(handler);
+                }
+                Op::EndTry => {
+                    // realise a lazy result here so that errors it raises are caught
+                    let value = self.stack.last().unwrap();
+                    if value.is_lazy_seq() {
+                        seq_to_list(value)?;
+                    }
+                    self.handlers.pop();
+                }
+                Op::Selects(reg) => {
+                    let selector = self.stack.pop().unwrap();
+                    let e = self.caught.last().unwrap();
+                    let selected = selects(&selector, e, &self.stack[frame.base + reg as usize])?;
+                    self.stack.push(new_mal!(Bool(selected)));
+                }
+                Op::Handled => {
+                    self.caught.pop();
+                }
+                Op::Rethrow => return Err(self.caught.pop().unwrap()),
+                Op::Eval(argc) => {
+                    let target = if argc == 2 {
+                        let e = self.stack.pop().unwrap();
+                        ensure_type!(e.is_env(), "eval's second param should be env");
+                        Some(e.to_env())
+                    } else {
+                        None
+                    };
+                    let form = self.stack.pop().unwrap();
+                    let value = eval_top_in(form, target.as_ref())?;
+                    self.stack.push(value);
+                }
+                Op::Fn(idx) => {
+                    let (name, ref clauses) = frame.proto.fns[idx as usize];
+                    let protos: Vec<Rc<Proto>> = clauses
+                        .iter()
+                        .map(|&idx| frame.proto.protos[idx as usize].clone())
+                        .collect();
+                    let f = make_fn(&protos, name, frame, &self.stack);
+                    self.stack.push(f);
+                }
+                Op::Interp(idx) => {
+                    let form = frame.proto.consts[idx as usize].clone();
+                    let value = eval(form, frame.env.clone())?;
+                    self.stack.push(value);
+                }
+                Op::PushEnv(idx) => {
+                    let names = &frame.proto.names[idx as usize];
+                    let at = self.stack.len() - names.len();
+                    let values = self.stack.drain(at..).collect();
+                    frame.env = env_new(Some(frame.env.clone()), names.iter().cloned(), values);
+                }
+                Op::Bind(idx) => {
+                    let name = frame.proto.consts[idx as usize].to_symbol();
+                    env_bind(&frame.env, name, self.stack.pop().unwrap());
+                }
+                Op::PopEnv(n) => {
+                    for _ in 0..n {
+                        frame.env = env_outer(&frame.env).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
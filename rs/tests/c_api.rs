@@ -0,0 +1,74 @@
+//! Builds the cdylib and `tests/c_api/test.c` against it and runs it, and
+//! checks the checked-in header is the one `build.rs` generates.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Builds the cdylib, which the tests do not link, with the profile and
+/// features of this test. Returns the directory it is in, the parent of
+/// the `deps` one this test runs from.
+fn build_lib(manifest: &Path) -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    let dir = exe.parent().unwrap().parent().unwrap().to_path_buf();
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo
+        .args(["build", "--lib", "--manifest-path"])
+        .arg(manifest.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(dir.parent().unwrap());
+    if dir.file_name() == Some("release".as_ref()) {
+        cargo.arg("--release");
+    }
+    if cfg!(feature = "jit") {
+        cargo.args(["--features", "jit"]);
+    }
+    let output = cargo.output().expect("run cargo");
+    assert!(
+        output.status.success(),
+        "building the cdylib failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    dir
+}
+
+#[test]
+fn c_api() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib = build_lib(manifest);
+    let exe = Path::new(env!("CARGO_TARGET_TMPDIR")).join("c_api_test");
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+
+    let status = Command::new(cc)
+        .arg(manifest.join("tests/c_api/test.c"))
+        .arg("-I")
+        .arg(manifest.join("include"))
+        .arg("-L")
+        .arg(&lib)
+        .arg(format!("-Wl,-rpath,{}", lib.display()))
+        .args(["-Wall", "-Werror", "-lrs", "-o"])
+        .arg(&exe)
+        .status()
+        .expect("run the C compiler");
+    assert!(status.success(), "compiling tests/c_api/test.c failed");
+
+    let output = Command::new(&exe).output().expect("run the C test");
+    assert!(
+        output.status.success(),
+        "C test failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
+
+#[test]
+fn header_is_up_to_date() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let generated = fs::read_to_string(env!("MAL_GENERATED_HEADER")).unwrap();
+    let checked_in = fs::read_to_string(manifest.join("include/mal.h")).unwrap();
+    assert!(
+        generated == checked_in,
+        "include/mal.h is out of date; rebuild with MAL_UPDATE_HEADER=1 to rewrite it"
+    );
+}
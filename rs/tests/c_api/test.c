@@ -0,0 +1,122 @@
+/* Exercises the C API; run by tests/c_api.rs. */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "mal.h"
+
+static int failures = 0;
+
+static void check_eval(MalInterpreter *mal, const char *source, int status, const char *expected)
+{
+    char *result = NULL;
+    int got = mal_eval_string(mal, source, &result);
+    if (got != status || strstr(result, expected) == NULL) {
+        fprintf(stderr, "%s: expected %d \"%s\", got %d \"%s\"\n", source, status, expected, got,
+                result);
+        failures++;
+    }
+    mal_string_free(result);
+}
+
+/* Sums its args, counting the calls in user_data. */
+static const char *sum(void *user_data, size_t argc, const char *const *argv, const char **error)
+{
+    static char buf[64];
+    double total = 0;
+    size_t i;
+    (*(int *)user_data)++;
+    for (i = 0; i < argc; i++) {
+        char *end;
+        total += strtod(argv[i], &end);
+        if (*end != '\0') {
+            *error = "args should be numbers";
+            return NULL;
+        }
+    }
+    snprintf(buf, sizeof buf, "%g", total);
+    return buf;
+}
+
+/* Returns its only arg, a string, as a keyword. */
+static const char *keyword(void *user_data, size_t argc, const char *const *argv, const char **error)
+{
+    static char buf[256];
+    (void)user_data;
+    if (argc != 1 || argv[0][0] != '"') {
+        *error = "expected a string";
+        return NULL;
+    }
+    /* the arg is printed readably, in quotes */
+    snprintf(buf, sizeof buf, ":%.*s", (int)strlen(argv[0]) - 2, argv[0] + 1);
+    return buf;
+}
+
+int main(void)
+{
+    int calls = 0;
+    char *error = NULL;
+    MalInterpreter *other, *broken;
+    MalInterpreter *mal = mal_new();
+    if (mal == NULL) {
+        fprintf(stderr, "mal_new failed\n");
+        return 1;
+    }
+
+    check_eval(mal, "(+ 1 2)", MAL_OK, "3");
+    check_eval(mal, "(def! x [1 \"two\"]) x", MAL_OK, "[1 \"two\"]");
+    check_eval(mal, "(throw {:a 1})", MAL_ERROR, "{:a 1}");
+    check_eval(mal, "(undefined-fn 1)", MAL_ERROR, "undefined-fn");
+    check_eval(mal, "(+ 1", MAL_ERROR, "");
+    check_eval(NULL, "1", MAL_ERROR, "NULL");
+    check_eval(mal, NULL, MAL_ERROR, "NULL");
+
+    if (mal_register_fn(mal, "c-sum", sum, &calls, &error) != MAL_OK) {
+        fprintf(stderr, "mal_register_fn: %s\n", error);
+        return 1;
+    }
+    check_eval(mal, "(c-sum 1 2 3.5)", MAL_OK, "6.5");
+    check_eval(mal, "(map c-sum [1 2] [10 20])", MAL_OK, "(11 22)");
+    check_eval(mal, "(c-sum 1 \"x\")", MAL_ERROR, "c-sum: args should be numbers");
+    check_eval(mal, "(try* (c-sum :a) (catch* e (str \"caught \" e)))", MAL_OK, "caught");
+    if (calls != 5) {
+        fprintf(stderr, "expected 5 calls of c-sum, got %d\n", calls);
+        failures++;
+    }
+
+    mal_register_fn(mal, "c-keyword", keyword, NULL, NULL);
+    check_eval(mal, "(c-keyword \"k\")", MAL_OK, ":k");
+
+    if (mal_register_fn(mal, "c-null", NULL, NULL, &error) != MAL_ERROR) {
+        fprintf(stderr, "registering NULL should fail\n");
+        failures++;
+    }
+    mal_string_free(error);
+
+    /* interpreters share no definitions, not even once one is freed */
+    check_eval(mal, "(def! secret 7)", MAL_OK, "7");
+    other = mal_new();
+    check_eval(other, "secret", MAL_ERROR, "secret");
+    check_eval(other, "(def! secret 8)", MAL_OK, "8");
+    check_eval(mal, "secret", MAL_OK, "7");
+    mal_free(mal);
+    mal_free(other);
+    mal = mal_new();
+    check_eval(mal, "secret", MAL_ERROR, "secret");
+    mal_free(mal);
+
+    /* a panic is caught at the boundary */
+    broken = mal_new();
+    check_eval(broken, "(conj {:a 1} [:b 2])", MAL_PANIC, "panic");
+    mal_free(broken);
+
+    mal_free(NULL);
+    mal_string_free(NULL);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d failures\n", failures);
+        return 1;
+    }
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,155 @@
+//! Converts Rust values to mal values and back.
+
+extern crate rs;
+
+use rs::error::ConversionError;
+use rs::printer::pr_str;
+use rs::reader::read_str;
+use rs::{FromMal, Interpreter, IntoMal};
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+#[derive(Debug, PartialEq, Clone, IntoMal, FromMal)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Debug, PartialEq, Clone, IntoMal, FromMal)]
+struct Shape {
+    name: String,
+    fill_color: Option<String>,
+    points: Vec<Point>,
+}
+
+#[derive(Debug, PartialEq, Clone, IntoMal, FromMal)]
+enum Event {
+    Started,
+    Moved(i32, i32),
+    Renamed { from: String, to: String },
+}
+
+/// `value` converted to mal, which prints as `printed`, and back.
+fn round_trip<T: IntoMal + FromMal + PartialEq + Debug + Clone>(value: T, printed: &str) {
+    let mal = value.clone().into_mal();
+    assert_eq!(pr_str(&mal, true), printed);
+    assert_eq!(T::from_mal(&mal).unwrap(), value);
+}
+
+/// The error of converting the mal read from `s` to a `T`.
+fn error<T: FromMal + Debug>(s: &str) -> String {
+    let e = T::from_mal(&read_str(s).unwrap()).unwrap_err();
+    assert!(e.downcast_ref::<ConversionError>().is_some(), "{}", e);
+    e.to_string()
+}
+
+#[test]
+fn scalars() {
+    round_trip(true, "true");
+    round_trip((), "nil");
+    round_trip(42i32, "42");
+    round_trip(-7i64, "-7");
+    round_trip(255u8, "255");
+    round_trip(1.5f64, "1.5");
+    round_trip("hi".to_string(), "\"hi\"");
+}
+
+#[test]
+fn ints_out_of_range() {
+    assert_eq!(error::<u8>("256"), "expected u8, got Number 256");
+    assert_eq!(error::<u8>("-1"), "expected u8, got Number -1");
+    assert_eq!(error::<i32>("1.5"), "expected i32, got Number 1.5");
+    assert!(u64::from_mal(&18446744073709551616f64.into_mal()).is_err());
+    assert!(i64::from_mal(&9223372036854775808f64.into_mal()).is_err());
+    assert_eq!(
+        i64::from_mal(&(-9223372036854775808f64).into_mal()).unwrap(),
+        i64::MIN
+    );
+    assert_eq!(
+        u64::from_mal(&18446744073709549568f64.into_mal()).unwrap(),
+        18446744073709549568
+    );
+}
+
+#[test]
+fn collections() {
+    round_trip(vec![1i32, 2, 3], "[1 2 3]");
+    round_trip(Vec::<i32>::new(), "[]");
+    round_trip(Some(3i32), "3");
+    round_trip(None::<i32>, "nil");
+    round_trip((1i32, "a".to_string(), false), "[1 \"a\" false]");
+
+    let mut map = HashMap::new();
+    map.insert("a".to_string(), 1i32);
+    round_trip(map.clone(), "{\"a\" 1}");
+    map.insert("b".to_string(), 2);
+    let mal = map.clone().into_mal();
+    assert_eq!(HashMap::<String, i32>::from_mal(&mal).unwrap(), map);
+}
+
+#[test]
+fn lazy_seqs() {
+    let interp = Interpreter::new();
+    let seq = interp.eval_str("(map (fn* [x] (+ x 1)) [1 2])").unwrap();
+    assert_eq!(Vec::<i64>::from_mal(&seq).unwrap(), vec![2, 3]);
+    let pairs = interp.eval_str("(map vector [1 2] [\"a\" \"b\"])").unwrap();
+    assert_eq!(
+        Vec::<(i32, String)>::from_mal(&pairs).unwrap(),
+        vec![(1, "a".to_string()), (2, "b".to_string())]
+    );
+}
+
+#[test]
+fn derived_struct() {
+    let point = Point { x: 1, y: 2 };
+    let mal = point.clone().into_mal();
+    assert_eq!(mal, read_str("{:x 1 :y 2}").unwrap());
+    assert_eq!(Point::from_mal(&mal).unwrap(), point);
+    let shape = Shape {
+        name: "tri".to_string(),
+        fill_color: None,
+        points: vec![Point { x: 0, y: 0 }],
+    };
+    let mal = read_str("{:name \"tri\" :points [{:x 0 :y 0}]}").unwrap();
+    assert_eq!(Shape::from_mal(&mal).unwrap(), shape);
+    let back = Shape::from_mal(&shape.clone().into_mal()).unwrap();
+    assert_eq!(back, shape);
+
+    let mal = read_str("{:name \"sq\" :fill-color \"red\" :points []}").unwrap();
+    assert_eq!(
+        Shape::from_mal(&mal).unwrap().fill_color,
+        Some("red".to_string())
+    );
+}
+
+#[test]
+fn derived_enum() {
+    round_trip(Event::Started, ":started");
+    round_trip(Event::Moved(1, -2), "[:moved 1 -2]");
+    let renamed = Event::Renamed {
+        from: "a".to_string(),
+        to: "b".to_string(),
+    };
+    let back = Event::from_mal(&renamed.clone().into_mal()).unwrap();
+    assert_eq!(back, renamed);
+    assert_eq!(
+        error::<Event>(":stopped"),
+        "expected one of :started :moved :renamed, got Keyword :stopped"
+    );
+}
+
+#[test]
+fn error_paths() {
+    assert_eq!(
+        error::<Shape>("{:name \"tri\" :points [{:x 0 :y 0} {:x 1 :y 1} {:x \"2\" :y 2}]}"),
+        "expected i32, got String \"2\" at .points[2].x"
+    );
+    assert_eq!(
+        error::<Vec<Point>>("[{:x 0 :y 0} {:x 1 :y 1} 3]"),
+        "expected Point, got Number 3 at [2]"
+    );
+    assert_eq!(
+        error::<(i32, i32)>("[1]"),
+        "expected a vector of 2, got Vector [1]"
+    );
+}
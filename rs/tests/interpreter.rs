@@ -0,0 +1,183 @@
+//! Embeds an `Interpreter` and drives it from Rust.
+
+#[macro_use]
+extern crate rs;
+extern crate failure;
+
+use failure::Fallible;
+use rs::error::StackOverflow;
+use rs::printer::pr_str;
+use rs::trace;
+use rs::types::{InnerMalType, MalType};
+use rs::Interpreter;
+use std::cell::RefCell;
+use std::collections::LinkedList;
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// What mal printed, shared with the interpreter writing it.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Output {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn eval(interp: &Interpreter, s: &str) -> String {
+    pr_str(&interp.eval_str(s).unwrap(), true)
+}
+
+#[test]
+fn eval_str_returns_the_last_form() {
+    let interp = Interpreter::new();
+    assert_eq!(eval(&interp, "(def! x 2) (def! y (* x 3)) (+ x y)"), "8");
+    assert_eq!(eval(&interp, "y"), "6");
+    assert_eq!(eval(&interp, ""), "nil");
+    assert_eq!(eval(&interp, "(+ 1 2) ; and a comment"), "3");
+    assert!(interp.eval_str("(+ 1").is_err());
+}
+
+#[test]
+fn eval_str_rejects_trailing_input() {
+    let interp = Interpreter::new();
+    let e = interp.eval_str("1) (def! zz 2").unwrap_err();
+    assert_eq!(e.to_string(), "unexpected ')'");
+    assert!(interp.get("zz").is_none());
+    assert!(interp.eval_str("[1 2]]").is_err());
+    assert!(interp.eval_str("{:a 1}}").is_err());
+}
+
+#[test]
+fn interpreters_are_independent() {
+    let a = Interpreter::new();
+    let b = Interpreter::new();
+    a.eval_str("(ns other) (def! x 1) (in-ns (quote user)) (def! secret 7)")
+        .unwrap();
+    assert_eq!(eval(&a, "secret"), "7");
+    assert!(b.get("secret").is_none());
+    assert!(b.eval_str("secret").is_err());
+    assert_eq!(eval(&b, "(str *ns*)"), "\"#namespace[user]\"");
+    assert_eq!(eval(&b, "(find-ns (quote other))"), "nil");
+
+    let (out_a, out_b) = (Output::default(), Output::default());
+    a.set_stdout(Box::new(out_a.clone()));
+    b.set_stdout(Box::new(out_b.clone()));
+    a.eval_str("(prn :a)").unwrap();
+    b.eval_str("(prn :b)").unwrap();
+    assert_eq!(
+        (out_a.text(), out_b.text()),
+        (":a\n".to_string(), ":b\n".to_string())
+    );
+
+    a.set_max_depth(Some(100));
+    let deep = "(def! deep (fn* [n] (if (= n 0) 0 (+ 1 (deep (- n 1)))))) (deep 1000)";
+    assert!(a.eval_str(deep).is_err());
+    assert_eq!(eval(&b, deep), "1000");
+
+    // a call into one from a fn of the other runs in the other
+    let inner = Rc::new(b);
+    let callee = inner.clone();
+    a.register_fn("ask-b", move |_| callee.eval_str("(prn :from-b) secret-b"));
+    inner.eval_str("(def! secret-b 8)").unwrap();
+    assert_eq!(eval(&a, "(ask-b)"), "8");
+    assert_eq!(eval(&a, "secret"), "7");
+    assert!(out_b.text().ends_with(":from-b\n"));
+}
+
+#[test]
+fn define_get_and_call() {
+    let interp = Interpreter::new();
+    interp.define("answer", new_mal!(Num(42f64)));
+    assert_eq!(eval(&interp, "(+ answer 1)"), "43");
+
+    interp.register_fn("twice", |args: LinkedList<MalType>| -> Fallible<MalType> {
+        let n = args.front().unwrap().to_number();
+        Ok(new_mal!(Num(n * 2f64)))
+    });
+    assert_eq!(eval(&interp, "(twice 21)"), "42");
+
+    interp.eval_str("(def! inc (fn* [x] (+ x 1)))").unwrap();
+    let inc = interp.get("inc").unwrap();
+    let result = interp.call(&inc, vec![new_mal!(Num(1f64))]).unwrap();
+    assert_eq!(pr_str(&result, true), "2");
+    assert!(interp.get("no-such-name").is_none());
+
+    let e = interp.call(&new_mal!(Num(1f64)), vec![]).unwrap_err();
+    assert_eq!(e.to_string(), "1 is not a function");
+}
+
+#[test]
+fn dropping_frees_what_was_defined() {
+    let held = Rc::new(());
+    let interp = Interpreter::new();
+    let in_fn = held.clone();
+    interp.register_fn("held", move |_| {
+        let _ = &in_fn;
+        Ok(new_mal!(Nil))
+    });
+    // a closure defined in the env it closes over, which Rc alone never frees
+    interp
+        .eval_str("(def! again (fn* [] (held) again))")
+        .unwrap();
+    assert_eq!(Rc::strong_count(&held), 2);
+    drop(interp);
+    assert_eq!(Rc::strong_count(&held), 1);
+}
+
+#[test]
+fn set_stdout_captures_prints() {
+    let interp = Interpreter::new();
+    let out = Output::default();
+    interp.set_stdout(Box::new(out.clone()));
+    interp.eval_str(r#"(prn "a" :b) (println "c" 1)"#).unwrap();
+    assert_eq!(out.text(), "\"a\" :b\nc 1\n");
+}
+
+#[test]
+fn set_stdin_feeds_readline() {
+    let interp = Interpreter::new();
+    let out = Output::default();
+    interp.set_stdout(Box::new(out.clone()));
+    interp.set_stdin(Box::new(Cursor::new("first\nsecond\n")));
+    assert_eq!(eval(&interp, r#"(readline "> ")"#), "\"first\"");
+    assert_eq!(eval(&interp, r#"(readline "> ")"#), "\"second\"");
+    assert_eq!(eval(&interp, r#"(readline "> ")"#), "nil");
+    assert_eq!(out.text(), "> > > ");
+}
+
+#[test]
+fn load_file_defines_its_forms() {
+    let interp = Interpreter::new();
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/modules/greet/shout.mal");
+    interp.load_file(&path).unwrap();
+    assert_eq!(eval(&interp, r#"(shout "hi")"#), "\"hi!\"");
+    assert!(interp.load_file("/no/such/file.mal").is_err());
+}
+
+#[test]
+fn set_max_depth_overflows() {
+    let interp = Interpreter::new();
+    interp.set_max_depth(Some(100));
+    interp
+        .eval_str("(def! deep (fn* [n] (if (= n 0) 0 (+ 1 (deep (- n 1))))))")
+        .unwrap();
+    assert_eq!(eval(&interp, "(deep 10)"), "10");
+    let e = interp.eval_str("(deep 1000)").unwrap_err();
+    let cause = trace::cause(&e);
+    assert!(cause.downcast_ref::<StackOverflow>().is_some(), "{}", e);
+    interp.set_max_depth(None);
+    assert_eq!(eval(&interp, "(deep 1000)"), "1000");
+}
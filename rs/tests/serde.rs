@@ -0,0 +1,47 @@
+//! Round-trips strings, keywords and symbols through serde, whose data
+//! model has only strings for all three.
+
+extern crate rs;
+extern crate serde_json;
+
+use rs::de::from_mal;
+use rs::reader::read_str;
+use rs::ser::to_mal;
+use rs::types::MalType;
+
+/// Strings which look like keywords and symbols, next to the real ones.
+const TEXTS: &str = r#"[:a b ":c" "'d" "\\e" "\\:f" "" "plain" {:k ":v" "'s" x ":k" :k}]"#;
+
+fn texts() -> MalType {
+    read_str(TEXTS).unwrap()
+}
+
+#[test]
+fn to_mal_and_from_mal() {
+    let mal = texts();
+    assert_eq!(to_mal(&mal).unwrap(), mal);
+    assert_eq!(from_mal::<MalType>(&mal).unwrap(), mal);
+}
+
+/// Rust strings have no encoding: keywords and symbols become their names.
+#[test]
+fn rust_strings() {
+    let mal = read_str(r#"[:a b ":c" "'d" "\\e" "f"]"#).unwrap();
+    let strings: Vec<String> = from_mal(&mal).unwrap();
+    assert_eq!(strings, vec!["a", "b", ":c", "'d", "\\e", "f"]);
+
+    let strings = read_str(r#"[":a" "'b" "\\:c"]"#).unwrap();
+    assert_eq!(to_mal(&vec![":a", "'b", "\\:c"]).unwrap(), strings);
+}
+
+#[test]
+fn json() {
+    let mal = read_str(r#"[:a b ":c" "'d" "\\e" "f"]"#).unwrap();
+    let json = serde_json::to_string(&mal).unwrap();
+    assert_eq!(json, r#"[":a","'b","\\:c","\\'d","\\\\e","f"]"#);
+    assert_eq!(serde_json::from_str::<MalType>(&json).unwrap(), mal);
+
+    let mal = texts();
+    let json = serde_json::to_string(&mal).unwrap();
+    assert_eq!(serde_json::from_str::<MalType>(&json).unwrap(), mal);
+}
@@ -0,0 +1,105 @@
+//! Checks that fns are compiled to run on the VM rather than in `eval`.
+
+#[macro_use]
+extern crate rs;
+
+use rs::printer::pr_str;
+use rs::types::{InnerMalType, MalType};
+use rs::Interpreter;
+use std::rc::Rc;
+
+/// Whether every clause of the fn `f` is compiled.
+fn compiled(f: &MalType) -> bool {
+    match **f {
+        InnerMalType::Closure(ref closure, _) => match closure.arities {
+            Some(ref arities) => arities
+                .closures()
+                .all(|c| c.c_env.as_ref().map(|c_env| c_env.proto.is_some()) == Some(true)),
+            None => closure.c_env.as_ref().map(|c_env| c_env.proto.is_some()) == Some(true),
+        },
+        _ => false,
+    }
+}
+
+fn eval(interp: &Interpreter, s: &str) -> String {
+    pr_str(&interp.eval_str(s).unwrap(), true)
+}
+
+#[test]
+fn named_recursive_fns() {
+    let interp = Interpreter::new();
+    interp
+        .eval_str(
+            "(def! count-down
+               (fn* count-down [n] (if (= n 0) :done (count-down (- n 1)))))",
+        )
+        .unwrap();
+    assert!(compiled(&interp.get("count-down").unwrap()));
+    assert_eq!(eval(&interp, "(count-down 100000)"), ":done");
+
+    interp
+        .eval_str(
+            "(def! make-fact
+               (fn* [] (fn* fact [n] (if (= n 0) 1 (* n (fact (- n 1)))))))",
+        )
+        .unwrap();
+    assert!(compiled(&interp.get("make-fact").unwrap()));
+    let fact = interp.eval_str("(make-fact)").unwrap();
+    assert!(compiled(&fact));
+    assert_eq!(
+        pr_str(
+            &interp.call(&fact, vec![new_mal!(Num(10f64))]).unwrap(),
+            true
+        ),
+        "3628800"
+    );
+}
+
+#[test]
+fn fns_of_several_clauses() {
+    let interp = Interpreter::new();
+    interp
+        .eval_str(
+            "(def! adder
+               (fn* [x] (fn* add ([] x) ([y] (+ x y)) ([y & more] (add (+ y (count more)))))))",
+        )
+        .unwrap();
+    assert!(compiled(&interp.get("adder").unwrap()));
+    let add = interp.eval_str("(adder 1)").unwrap();
+    assert!(compiled(&add));
+    assert_eq!(
+        eval(&interp, "[((adder 1)) ((adder 1) 2) ((adder 1) 2 :a :b)]"),
+        "[1 3 5]"
+    );
+}
+
+#[test]
+fn bodies_needing_an_env() {
+    let interp = Interpreter::new();
+    interp.eval_str("(def! ^:dynamic *depth* 0)").unwrap();
+    interp
+        .eval_str(
+            "(def! f
+               (fn* [x] (let* [y (+ x 1)]
+                          (do (def! z (* y 2))
+                              (binding [*depth* z]
+                                [y z *depth* (eval (quote y) (current-env))])))))",
+        )
+        .unwrap();
+    assert!(compiled(&interp.get("f").unwrap()));
+    assert_eq!(eval(&interp, "(f 1)"), "[2 4 4 2]");
+    assert_eq!(eval(&interp, "*depth*"), "0");
+
+    // a `loop*` in an env makes the env of its names anew on each `recur`
+    interp
+        .eval_str(
+            "(def! g
+               (fn* [n] (loop* [i 0 fs []]
+                          (if (< i n)
+                            (do (def! last i) (recur (+ i 1) (conj fs (fn* [] i))))
+                            (map (fn* [f] (f)) fs)))))",
+        )
+        .unwrap();
+    assert!(compiled(&interp.get("g").unwrap()));
+    assert_eq!(eval(&interp, "(g 3)"), "(0 1 2)");
+}